@@ -1,64 +1,95 @@
 use crate::*;
 
+// why the response of the auth contract wasn't used, unlike a missing owner it says nothing about the contact
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailureReason {
+    CallFailed,
+    MalformedResponse,
+}
+
 #[near_bindgen]
 impl NearTips {
     pub fn send_tip_to_telegram_with_auth(&mut self,
                                           telegram_account: TelegramAccountId,
                                           amount: WrappedBalance,
                                           chat_id: Option<TelegramChatId>,
-                                          token_id: Option<TokenAccountId>) -> Promise {
+                                          token_id: Option<TokenAccountId>) -> PromiseOrValue<()> {
         self.assert_tip_available();
         assert!(amount.0 > 0, "Positive amount needed");
-        self.assert_check_whitelisted_token(&token_id);
+        self.assert_token_available(&token_id);
+        self.assert_min_tip(&NearTips::unwrap_token_id(&token_id), amount.0);
 
         let account_id = env::predecessor_account_id();
         let deposit = self.get_deposit_for_account_id(&account_id, &token_id);
 
         assert!(amount.0 <= deposit, "Not enough tokens to tip (Deposit: {}. Requested: {})", deposit, amount.0);
 
-        let contact: Contact = Contact {
-            category: ContactCategories::Telegram,
-            value: "".to_string(),
-            account_id: Some(telegram_account),
-        };
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        let contact = telegram_contact(telegram_account);
 
-        self.get_contact_owner(contact, self.auth_account_id.to_string()).
-            then(ext_self::on_get_contact_owner_on_send_tip_to_telegram_with_auth(
-                account_id,
-                amount.0,
-                telegram_account,
-                chat_id,
-                token_id,
-                &env::current_account_id(),
-                NO_DEPOSIT,
-                CALLBACK_GAS * 2,
-            ))
+        // owner resolved recently, the tip is sent without asking the identity provider
+        if let Some(receiver_account_id) = self.take_fresh_contact_owner(&contact) {
+            self.decrease_deposit(account_id.clone(), token_id_unwrapped.clone(), amount.0);
+            self.send_tip_to_contact_owner(account_id, receiver_account_id, telegram_account, amount.0, token_id_unwrapped, chat_id);
+            return PromiseOrValue::Value(());
+        }
+
+        // locked until the callback, so concurrent calls can't spend the same deposit
+        let reservation_id = self.reserve_deposit(&account_id, &token_id_unwrapped, amount.0);
+
+        PromiseOrValue::Promise(
+            self.get_contact_owner(contact).
+                then(ext_self::on_get_contact_owner_on_send_tip_to_telegram_with_auth(
+                    reservation_id,
+                    telegram_account,
+                    chat_id,
+                    &env::current_account_id(),
+                    NO_DEPOSIT,
+                    CALLBACK_GAS * 2,
+                )))
     }
 
     pub fn on_get_contact_owner_on_send_tip_to_telegram_with_auth(&mut self,
-                                                                  #[callback] account: Option<AccountId>,
-                                                                  sender_account_id: AccountId,
-                                                                  tip_amount: Balance,
+                                                                  reservation_id: ReservationId,
                                                                   telegram_account: TelegramAccountId,
-                                                                  chat_id: Option<TelegramChatId>,
-                                                                  token_id: Option<TokenAccountId>) {
+                                                                  chat_id: Option<TelegramChatId>) {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Callback can only be called from the contract"
         );
 
-        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        let account = match get_auth_result::<AccountId>() {
+            Ok(account) => account,
+            Err(reason) => {
+                let reservation = self.release_reservation(reservation_id);
+                NearTips::auth_failed("send_tip_to_telegram_with_auth", &reservation.account_id, telegram_contact(telegram_account), reason);
+                // the tip doesn't depend on the owner, so it's sent to the telegram balance
+                self.send_released_tip_to_telegram(reservation, telegram_account, chat_id);
+                return;
+            }
+        };
+
+        self.cache_contact_owner(&telegram_contact(telegram_account), account.as_ref());
 
         match account {
             Some(account_id) => {
-                self.decrease_deposit(sender_account_id.clone(), token_id_unwrapped.clone(), tip_amount);
-                self.deposit_amount_to_account(&account_id, tip_amount, token_id);
-                env::log(format!("@{} tipped {} of {:?} to NEAR account {} telegram account {}", sender_account_id, tip_amount, token_id_unwrapped, account_id, telegram_account).as_bytes());
+                let reservation = self.reservations.get(&reservation_id).expect("Reservation not found");
+                // a panic after the commit would keep the funds locked, so storage is checked first
+                if !self.has_storage_for_deposit(&reservation.account_id, &account_id, &reservation.token_id) {
+                    env::log(format!("Not enough storage balance for account {}. Tip returned to the deposit", reservation.account_id).as_bytes());
+                    self.release_reservation(reservation_id);
+                    return;
+                }
+                let reservation = self.commit_reservation(reservation_id);
+                self.send_tip_to_contact_owner(reservation.account_id, account_id, telegram_account, reservation.amount, reservation.token_id, chat_id);
             }
             None => {
-                env::log(format!("Authorized contact wasn't found for telegram {}. Continue to send from @{}", telegram_account, sender_account_id).as_bytes());
-                self.send_tip_to_telegram_from_account(sender_account_id, telegram_account, U128::from(tip_amount), chat_id, token_id);
+                let reservation = self.release_reservation(reservation_id);
+                env::log(format!("Authorized contact wasn't found for telegram {}. Continue to send from @{}", telegram_account, reservation.account_id).as_bytes());
+                self.send_released_tip_to_telegram(reservation, telegram_account, chat_id);
             }
         }
     }
@@ -67,22 +98,20 @@ impl NearTips {
     // decentralized tips withdraw for those who made near auth. telegram_account is numeric ID 123123123
     pub fn withdraw_from_telegram_with_auth(&mut self,
                                             telegram_account: TelegramAccountId,
-                                            token_id: Option<TokenAccountId>) -> Promise {
+                                            token_id: Option<TokenAccountId>,
+                                            amount: Option<WrappedBalance>) -> Promise {
         self.assert_withdraw_available();
 
         let account_id = env::predecessor_account_id();
 
-        let contact: Contact = Contact {
-            category: ContactCategories::Telegram,
-            value: "".to_string(),
-            account_id: Some(telegram_account),
-        };
+        let contact = telegram_contact(telegram_account);
 
-        self.get_contact_owner(contact.clone(), self.auth_account_id.to_string()).
+        self.get_contact_owner(contact.clone()).
             then(ext_self::on_get_contact_owner_on_withdraw_from_telegram_with_auth(
                 account_id,
                 contact,
                 token_id,
+                amount,
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 CALLBACK_GAS * 3,
@@ -90,53 +119,60 @@ impl NearTips {
     }
 
     pub fn on_get_contact_owner_on_withdraw_from_telegram_with_auth(&mut self,
-                                                                    #[callback] account: Option<AccountId>,
                                                                     recipient_account_id: AccountId,
                                                                     contact: Contact,
-                                                                    token_id: Option<TokenAccountId>) -> Promise {
+                                                                    token_id: Option<TokenAccountId>,
+                                                                    amount: Option<WrappedBalance>) -> PromiseOrValue<bool> {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Callback can only be called from the contract"
         );
 
+        let account = match get_auth_result::<AccountId>() {
+            Ok(account) => account,
+            Err(reason) => {
+                NearTips::auth_failed("withdraw_from_telegram_with_auth", &recipient_account_id, contact, reason);
+                return PromiseOrValue::Value(false);
+            }
+        };
+
+        self.cache_contact_owner(&contact, account.as_ref());
+
         match account {
             Some(account) => {
                 assert!(account == recipient_account_id, "Not authorized to withdraw");
-                assert!(!contact.account_id.is_none(), "Account ID is missing");
+                assert!(contact.account_id.is_some(), "Account ID is missing");
 
                 let balance: Balance = self.get_balance(contact.account_id.unwrap(), token_id.clone()).0;
                 assert!(balance > 0, "Not enough tokens to withdraw");
+                let balance: Balance = NearTips::get_withdraw_amount(balance, amount);
 
                 let telegram_account = contact.account_id.unwrap();
-                let predecessor_account_id = env::predecessor_account_id();
                 let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
 
-                self.set_balance_to_zero(telegram_account, token_id_unwrapped.clone());
-
-                env::log(format!("@{} withdrew {} of {:?} from telegram account {}",
-                                 predecessor_account_id, balance, token_id_unwrapped, telegram_account).as_bytes());
-
-                if token_id_unwrapped == NEAR {
-                    Promise::new(recipient_account_id).transfer(balance)
-                } else {
-                    ext_fungible_token::ft_transfer(
-                        recipient_account_id,
-                        balance.into(),
-                        Some(format!("Claiming tips: {} of {:?} from @{}", balance, token_id_unwrapped, env::current_account_id())),
-                        &token_id_unwrapped,
-                        ONE_YOCTO,
-                        GAS_FOR_FT_TRANSFER,
-                    )
+                let claimed_expiring_tips = self.decrease_balance(telegram_account, token_id_unwrapped.clone(), balance);
+
+                EventKind::Withdraw(vec![WithdrawEvent {
+                    account_id: recipient_account_id.clone(),
+                    telegram_account: Some(telegram_account),
+                    token_id: token_id_unwrapped.clone(),
+                    amount: balance.into(),
+                    fee: 0.into(),
+                }]).emit();
+
+                PromiseOrValue::Promise(
+                    NearTips::transfer_tokens(recipient_account_id, balance, &token_id_unwrapped)
                         .then(ext_self::after_ft_transfer_balance(
                             telegram_account,
                             balance.into(),
                             token_id_unwrapped,
+                            None,
+                            Some(claimed_expiring_tips),
                             &env::current_account_id(),
                             NO_DEPOSIT,
                             GAS_FOR_AFTER_FT_TRANSFER,
-                        ))
-                }
+                        )))
             }
             None => {
                 panic!("Contact wasn't authorized to any account");
@@ -147,32 +183,31 @@ impl NearTips {
     #[payable]
     // tip from balance to near account deposit without knowing NEAR account_id. telegram_account is numeric ID 123123123
     pub fn tip_contact_to_deposit(&mut self, telegram_account: TelegramAccountId, amount: WrappedBalance, token_id: Option<TokenAccountId>) -> Promise {
-        self.assert_tip_available();
-        assert!(amount.0 > 0, "Positive amount needed");
-        self.assert_check_whitelisted_token(&token_id);
+        let contact = telegram_contact(telegram_account);
 
-        let account_id = env::predecessor_account_id();
-        let account_id_prepared: ValidAccountId = ValidAccountId::try_from(account_id.clone()).unwrap();
-        let deposit: Balance = NearTips::get_deposit(self, account_id_prepared, token_id.clone()).0;
+        self.tip_contact_from_deposit(env::predecessor_account_id(), contact, amount.0, token_id)
+    }
 
-        let contact: Contact = Contact {
-            category: ContactCategories::Telegram,
-            value: "".to_string(),
-            account_id: Some(telegram_account),
-        };
+    pub(crate) fn tip_contact_from_deposit(&mut self, account_id: AccountId, contact: Contact, amount: Balance, token_id: Option<TokenAccountId>) -> Promise {
+        self.assert_tip_available();
+        assert!(amount > 0, "Positive amount needed");
+        self.assert_token_available(&token_id);
+        self.assert_min_tip(&NearTips::unwrap_token_id(&token_id), amount);
+
+        let deposit: Balance = self.get_deposit_for_account_id(&account_id, &token_id);
 
         assert!(
-            amount.0 <= deposit,
+            amount <= deposit,
             "Not enough tokens deposited to tip (Deposit: {}. Requested: {})",
-            deposit, amount.0
+            deposit, amount
         );
 
-        self.get_contact_owner(contact.clone(), self.auth_account_id.to_string()).
+        let reservation_id = self.reserve_deposit(&account_id, &NearTips::unwrap_token_id(&token_id), amount);
+
+        self.get_contact_owner(contact.clone()).
             then(ext_self::on_get_contact_owner_on_tip_contact_to_deposit(
-                account_id,
+                reservation_id,
                 contact,
-                amount.0,
-                token_id,
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 CALLBACK_GAS,
@@ -180,27 +215,129 @@ impl NearTips {
     }
 
     pub fn on_get_contact_owner_on_tip_contact_to_deposit(&mut self,
-                                                          #[callback] account: Option<AccountId>,
-                                                          sender_account_id: AccountId,
-                                                          contact: Contact,
-                                                          amount: Balance,
-                                                          token_id: Option<TokenAccountId>) {
+                                                          reservation_id: ReservationId,
+                                                          contact: Contact) -> bool {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Callback can only be called from the contract"
         );
 
-        assert!(!account.is_none(), "Owner not found");
-        let receiver_account_id: AccountId = account.unwrap();
-
-        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        let receiver_account_id: AccountId = match get_auth_result::<AccountId>() {
+            Ok(Some(account_id)) => {
+                self.cache_contact_owner(&contact, Some(&account_id));
+                let reservation = self.reservations.get(&reservation_id).expect("Reservation not found");
+                if !self.has_storage_for_deposit(&reservation.account_id, &account_id, &reservation.token_id) {
+                    env::log(format!("Not enough storage balance for account {}. Tip returned to the deposit", reservation.account_id).as_bytes());
+                    self.release_reservation(reservation_id);
+                    return false;
+                }
+                account_id
+            }
+            Ok(None) => {
+                self.cache_contact_owner(&contact, None);
+                env::log("Owner not found".to_string().as_bytes());
+                self.release_reservation(reservation_id);
+                return false;
+            }
+            Err(reason) => {
+                let reservation = self.release_reservation(reservation_id);
+                NearTips::auth_failed("tip_contact_to_deposit", &reservation.account_id, contact, reason);
+                return false;
+            }
+        };
 
-        self.decrease_deposit(sender_account_id.clone(), token_id_unwrapped.clone(), amount);
+        let reservation = self.commit_reservation(reservation_id);
+        let sender_account_id = reservation.account_id;
+        let token_id_unwrapped = reservation.token_id;
+        let amount = reservation.amount;
 
+        let initial_storage_usage = env::storage_usage();
         self.increase_deposit(receiver_account_id.clone(), token_id_unwrapped.clone(), amount);
+        self.charge_storage(&sender_account_id, initial_storage_usage);
+
+        self.record_tips(vec![TipEvent {
+            sender_account_id,
+            telegram_account: contact.account_id,
+            receiver_account_id: Some(receiver_account_id),
+            contact: Some(contact),
+            token_id: token_id_unwrapped,
+            amount: amount.into(),
+            fee: 0.into(),
+            chat_id: None,
+            expires_at: None,
+        }]);
+        true
+    }
+}
+
+impl NearTips {
+    // amount has already left the sender's deposit
+    fn send_tip_to_contact_owner(&mut self,
+                                 sender_account_id: AccountId,
+                                 receiver_account_id: AccountId,
+                                 telegram_account: TelegramAccountId,
+                                 amount: Balance,
+                                 token_id: TokenAccountId,
+                                 chat_id: Option<TelegramChatId>) {
+        let initial_storage_usage = env::storage_usage();
+        self.increase_deposit(receiver_account_id.clone(), token_id.clone(), amount);
+        self.charge_storage(&sender_account_id, initial_storage_usage);
+
+        self.record_tips(vec![TipEvent {
+            sender_account_id,
+            telegram_account: Some(telegram_account),
+            receiver_account_id: Some(receiver_account_id),
+            contact: None,
+            token_id,
+            amount: amount.into(),
+            fee: 0.into(),
+            chat_id,
+            expires_at: None,
+        }]);
+    }
+
+    // released funds are back in the sender's deposit, they stay there if the telegram tip would fail
+    fn send_released_tip_to_telegram(&mut self, reservation: Reservation, telegram_account: TelegramAccountId, chat_id: Option<TelegramChatId>) {
+        let token_id = reservation.token_id;
+        let config = self.get_token_config_or_default(&token_id);
+        let can_tip = self.tip_available
+            && self.whitelisted_tokens.contains(&token_id)
+            && !config.paused
+            && reservation.amount >= config.min_tip.0
+            && self.has_storage_for_telegram_tip(&reservation.account_id, telegram_account, &token_id, chat_id);
+
+        if can_tip {
+            self.send_tip_to_telegram_from_account(reservation.account_id, telegram_account, U128::from(reservation.amount), chat_id, Some(token_id), None);
+        } else {
+            env::log(format!("Tip to telegram {} is not available. Tip returned to the deposit of @{}", telegram_account, reservation.account_id).as_bytes());
+        }
+    }
+
+    pub(crate) fn auth_failed(method: &str, account_id: &AccountId, contact: Contact, reason: AuthFailureReason) {
+        EventKind::AuthFailed(vec![AuthFailedEvent {
+            method: method.to_string(),
+            account_id: account_id.clone(),
+            contact,
+            reason,
+        }]).emit();
+    }
+}
+
+// auth contract answers with an optional value, failed call and unexpected data are errors
+pub(crate) fn get_auth_result<T: near_sdk::serde::de::DeserializeOwned>() -> Result<Option<T>, AuthFailureReason> {
+    assert_eq!(env::promise_results_count(), 1, "Contract expected a result on the callback");
+    match env::promise_result(0) {
+        PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<Option<T>>(&value)
+            .map_err(|_| AuthFailureReason::MalformedResponse),
+        _ => Err(AuthFailureReason::CallFailed),
+    }
+}
 
-        env::log(format!("@{} transferred {} of {:?} to deposit of @{} [{:?} account {:?}]",
-                         sender_account_id, amount, token_id_unwrapped, receiver_account_id, contact.category, contact.value).as_bytes());
+pub(crate) fn telegram_contact(telegram_account: TelegramAccountId) -> Contact {
+    Contact {
+        category: ContactCategories::Telegram,
+        value: "".to_string(),
+        account_id: Some(telegram_account),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,100 @@
+use crate::*;
+
+/* WITHDRAW COMMISSIONS, accrued in the withdrawn token and owned by master_account_id */
+#[near_bindgen]
+impl NearTips {
+    pub fn get_commission_balance(&self, token_id: Option<TokenAccountId>) -> WrappedBalance {
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        self.get_commission_balance_for_token(&token_id_unwrapped).into()
+    }
+
+    pub fn withdraw_commission(&mut self, token_id: Option<TokenAccountId>) -> Promise {
+        self.assert_master_account_id();
+
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        let amount: Balance = self.get_commission_balance_for_token(&token_id_unwrapped);
+        assert!(amount > 0, "Missing commission");
+
+        self.commissions.insert(&token_id_unwrapped, &0);
+        self.update_liabilities(&token_id_unwrapped, |l| l.commissions = l.commissions.saturating_sub(amount));
+
+        EventKind::WithdrawCommission(vec![WithdrawEvent {
+            account_id: self.master_account_id.clone(),
+            telegram_account: None,
+            token_id: token_id_unwrapped.clone(),
+            amount: amount.into(),
+            fee: 0.into(),
+        }]).emit();
+
+        let transfer = if token_id_unwrapped == NEAR {
+            Promise::new(self.master_account_id.clone()).transfer(amount)
+        } else {
+            ext_fungible_token::ft_transfer(
+                self.master_account_id.clone(),
+                amount.into(),
+                Some(format!("Claiming commission: {} of {:?} from @{}", amount, token_id_unwrapped, env::current_account_id())),
+                &token_id_unwrapped,
+                ONE_YOCTO,
+                GAS_FOR_FT_TRANSFER,
+            )
+        };
+
+        transfer.then(ext_self::after_ft_transfer_commission(
+            amount.into(),
+            token_id_unwrapped,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_AFTER_FT_TRANSFER,
+        ))
+    }
+
+    pub fn after_ft_transfer_commission(
+        &mut self,
+        amount: WrappedBalance,
+        token_account_id: TokenAccountId,
+    ) -> bool {
+        assert_eq!(
+            env::predecessor_account_id(),
+            env::current_account_id(),
+            "Callback can only be called from the contract"
+        );
+
+        let promise_success = is_promise_success();
+        if !promise_success {
+            log!("Token {} commission withdraw failed. Amount to recharge: {}", token_account_id, amount.0);
+
+            self.increase_commission(&token_account_id, amount.0);
+
+            EventKind::WithdrawFailed(vec![WithdrawFailedEvent {
+                account_id: Some(self.master_account_id.clone()),
+                telegram_account: None,
+                token_id: token_account_id,
+                amount,
+            }]).emit();
+        }
+        promise_success
+    }
+
+    pub(crate) fn get_commission_balance_for_token(&self, token_id: &TokenAccountId) -> Balance {
+        self.commissions.get(token_id).unwrap_or(0)
+    }
+
+    pub(crate) fn increase_commission(&mut self, token_id: &TokenAccountId, amount: Balance) {
+        if amount > 0 {
+            let commission = self.get_commission_balance_for_token(token_id);
+            self.commissions.insert(token_id, &(commission + amount));
+            self.update_liabilities(token_id, |l| l.commissions += amount);
+        }
+    }
+
+    // returns the part of the commission which is still in the ledger
+    pub(crate) fn refund_commission(&mut self, token_id: &TokenAccountId, amount: Balance) -> Balance {
+        let commission = self.get_commission_balance_for_token(token_id);
+        let refund = std::cmp::min(commission, amount);
+        if refund > 0 {
+            self.commissions.insert(token_id, &(commission - refund));
+            self.update_liabilities(token_id, |l| l.commissions = l.commissions.saturating_sub(refund));
+        }
+        refund
+    }
+}
@@ -0,0 +1,97 @@
+use crate::*;
+use crate::auth_tips::telegram_contact;
+use near_sdk::{Duration, Timestamp};
+
+/* CONTACT OWNERS, cache of the owners resolved by the identity providers */
+pub(crate) const DEFAULT_CONTACT_OWNER_TTL: Duration = 24 * 60 * 60 * 1_000_000_000; // 1 day
+
+// storage is paid by the contract, stale records are removed on read or replaced by the next lookup
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CachedContactOwner {
+    pub account_id: AccountId,
+    // record is ignored once the category is moved to another provider
+    pub provider_account_id: AccountId,
+    pub cached_at: Timestamp,
+}
+
+#[near_bindgen]
+impl NearTips {
+    // 0 disables the cache, every lookup goes to the identity provider
+    pub fn set_contact_owner_ttl(&mut self, ttl: U64) {
+        self.assert_master_account_id();
+        self.contact_owner_ttl = ttl.0;
+    }
+
+    pub fn get_contact_owner_ttl(&self) -> U64 {
+        self.contact_owner_ttl.into()
+    }
+
+    pub fn get_cached_contact_owner(&self, contact: Contact) -> Option<AccountId> {
+        self.get_fresh_contact_owner(&contact)
+    }
+
+    // called by the identity provider of the category when the contact is unlinked or moved to another account
+    pub fn notify_contact_changed(&mut self, contact: Contact) -> bool {
+        let predecessor_account_id = env::predecessor_account_id();
+        assert!(
+            predecessor_account_id == self.get_identity_provider_for(&contact.category) || predecessor_account_id == self.master_account_id,
+            "No access"
+        );
+
+        self.contact_owners.remove(&NearTips::contact_owner_key(&contact)).is_some()
+    }
+}
+
+impl NearTips {
+    pub(crate) fn get_fresh_contact_owner(&self, contact: &Contact) -> Option<AccountId> {
+        self.contact_owners.get(&NearTips::contact_owner_key(contact))
+            .filter(|owner| self.is_contact_owner_fresh(&contact.category, owner))
+            .map(|owner| owner.account_id)
+    }
+
+    // stale record is removed, so the next lookup writes a new one
+    pub(crate) fn take_fresh_contact_owner(&mut self, contact: &Contact) -> Option<AccountId> {
+        let key = NearTips::contact_owner_key(contact);
+        let owner = self.contact_owners.get(&key)?;
+        if self.is_contact_owner_fresh(&contact.category, &owner) {
+            Some(owner.account_id)
+        } else {
+            self.contact_owners.remove(&key);
+            None
+        }
+    }
+
+    // result of get_account_for_contact, missing owner drops the cached one
+    pub(crate) fn cache_contact_owner(&mut self, contact: &Contact, account_id: Option<&AccountId>) {
+        let key = NearTips::contact_owner_key(contact);
+        match account_id {
+            Some(account_id) if self.contact_owner_ttl > 0 => {
+                self.contact_owners.insert(&key, &CachedContactOwner {
+                    account_id: account_id.clone(),
+                    provider_account_id: self.get_identity_provider_for(&contact.category),
+                    cached_at: env::block_timestamp(),
+                });
+            }
+            _ => {
+                self.contact_owners.remove(&key);
+            }
+        }
+    }
+
+    fn is_contact_owner_fresh(&self, category: &ContactCategories, owner: &CachedContactOwner) -> bool {
+        owner.provider_account_id == self.get_identity_provider_for(category)
+            && env::block_timestamp() < owner.cached_at.saturating_add(self.contact_owner_ttl)
+    }
+
+    // contacts are matched as in are_contacts_equal, telegram by the numeric id, others by the value
+    fn contact_owner_key(contact: &Contact) -> Contact {
+        match (&contact.category, contact.account_id) {
+            (ContactCategories::Telegram, Some(telegram_account)) => telegram_contact(telegram_account),
+            _ => Contact {
+                category: contact.category.clone(),
+                value: contact.value.clone(),
+                account_id: None,
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::*;
+
+/* ENUMERATION of deposits, telegram balances and chats */
+#[near_bindgen]
+impl NearTips {
+    // accounts stay listed after their deposits are spent, until the storage unregistration
+    pub fn get_deposit_holders(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        NearTips::paginate(self.deposit_holders.as_vector(), from_index, limit)
+    }
+
+    pub fn get_deposit_holders_count(&self) -> u64 {
+        self.deposit_holders.len()
+    }
+
+    // only telegram accounts with unclaimed balances are listed
+    pub fn get_telegram_balances(&self,
+                                 token_id: Option<TokenAccountId>,
+                                 from_index: Option<u64>,
+                                 limit: Option<u64>,
+    ) -> Vec<(TelegramAccountId, WrappedBalance)> {
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        match self.telegram_accounts_per_token.get(&token_id_unwrapped) {
+            Some(telegram_accounts) =>
+                NearTips::paginate(telegram_accounts.as_vector(), from_index, limit)
+                    .into_iter()
+                    .map(|telegram_account| (telegram_account, self.get_balance(telegram_account, Some(token_id_unwrapped.clone()))))
+                    .collect(),
+            None => vec![]
+        }
+    }
+
+    pub fn get_chats(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<(TelegramChatId, ChatSettings)> {
+        NearTips::paginate(self.chats.as_vector(), from_index, limit)
+            .into_iter()
+            .filter_map(|chat_id| self.get_chat_settings(chat_id).map(|settings| (chat_id, settings)))
+            .collect()
+    }
+
+    pub fn get_chats_count(&self) -> u64 {
+        self.chats.len()
+    }
+
+    pub(crate) fn paginate<T: BorshSerialize + BorshDeserialize>(values: &Vector<T>, from_index: Option<u64>, limit: Option<u64>) -> Vec<T> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+
+        (from_index..std::cmp::min(from_index.saturating_add(limit), values.len()))
+            .map(|index| values.get(index).unwrap())
+            .collect()
+    }
+
+    // returns false if the telegram account was already indexed
+    pub(crate) fn add_telegram_account_to_index(&mut self, telegram_account: TelegramAccountId, token_id: &TokenAccountId) -> bool {
+        let mut telegram_accounts = self.telegram_accounts_per_token.get(token_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::TelegramAccountsPerToken { token_id_hash: env::sha256(token_id.as_bytes()) })
+        });
+        let inserted = telegram_accounts.insert(&telegram_account);
+        if inserted {
+            self.telegram_accounts_per_token.insert(token_id, &telegram_accounts);
+        }
+        inserted
+    }
+
+    pub(crate) fn remove_telegram_account_from_index(&mut self, telegram_account: TelegramAccountId, token_id: &TokenAccountId) {
+        if let Some(mut telegram_accounts) = self.telegram_accounts_per_token.get(token_id) {
+            if telegram_accounts.remove(&telegram_account) {
+                self.telegram_accounts_per_token.insert(token_id, &telegram_accounts);
+            }
+        }
+    }
+}
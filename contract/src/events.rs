@@ -0,0 +1,427 @@
+use crate::*;
+
+/* NEP-297 EVENTS */
+const EVENT_STANDARD: &str = "tipbot";
+const EVENT_STANDARD_VERSION: &str = "1.0.0";
+const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Deposit(Vec<DepositEvent>),
+    Tip(Vec<TipEvent>),
+    BatchTip(Vec<BatchTipEvent>),
+    RainTip(Vec<RainTipEvent>),
+    TransferToDeposit(Vec<WithdrawEvent>),
+    Withdraw(Vec<WithdrawEvent>),
+    WithdrawFailed(Vec<WithdrawFailedEvent>),
+    WithdrawCommission(Vec<WithdrawEvent>),
+    WithdrawLinkdrop(Vec<WithdrawLinkdropEvent>),
+    ChatPoint(Vec<ChatPointEvent>),
+    DistributeTiptokens(Vec<DistributeTiptokensEvent>),
+    ClaimTiptokens(Vec<ClaimTiptokensEvent>),
+    ClaimTiptokensFailed(Vec<ClaimTiptokensEvent>),
+    RedeemTiptokens(Vec<RedeemTiptokensEvent>),
+    SolvencyAlert(Vec<SolvencyAlertEvent>),
+    TransferRejected(Vec<TransferRejectedEvent>),
+    CreateGiveaway(Vec<GiveawayEvent>),
+    ClaimGiveaway(Vec<ClaimGiveawayEvent>),
+    CloseGiveaway(Vec<GiveawayEvent>),
+    ReclaimTips(Vec<ReclaimTipsEvent>),
+    CreateSubscription(Vec<SubscriptionEvent>),
+    SkipSubscription(Vec<SkipSubscriptionEvent>),
+    CloseSubscription(Vec<CloseSubscriptionEvent>),
+    UnresolvedTip(Vec<UnresolvedTipEvent>),
+    AuthFailed(Vec<AuthFailedEvent>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a EventKind,
+}
+
+impl EventKind {
+    pub fn to_json_string(&self) -> String {
+        near_sdk::serde_json::to_string(&EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_STANDARD_VERSION,
+            event: self,
+        }).expect("Failed to serialize event")
+    }
+
+    pub fn emit(&self) {
+        env::log(format!("{}{}", EVENT_JSON_PREFIX, self.to_json_string()).as_bytes());
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositEvent {
+    pub account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+}
+
+// tip to telegram account, to NEAR account deposit or to generic contact
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TipEvent {
+    pub sender_account_id: AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_account: Option<TelegramAccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receiver_account_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<Contact>,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub fee: WrappedBalance,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<TelegramChatId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<WrappedTimestamp>,
+}
+
+// totals of send_tips_to_telegram, followed by the tip event with every recipient
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchTipEvent {
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub recipients_count: u32,
+    pub amount: WrappedBalance,
+    pub fee: WrappedBalance,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<TelegramChatId>,
+}
+
+// totals of rain_tip, remainder of the even split stays in the sender's deposit
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RainTipEvent {
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub chat_id: TelegramChatId,
+    pub recipients_count: u32,
+    pub amount: WrappedBalance,
+    pub fee: WrappedBalance,
+    pub remainder: WrappedBalance,
+}
+
+// tokens leaving a telegram balance or a deposit
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawEvent {
+    pub account_id: AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_account: Option<TelegramAccountId>,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub fee: WrappedBalance,
+}
+
+// failed transfer, amount was returned to the telegram balance or to the deposit
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawFailedEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_account: Option<TelegramAccountId>,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawLinkdropEvent {
+    pub telegram_account: TelegramAccountId,
+    pub public_key: String,
+    pub amount: WrappedBalance,
+    pub fee: WrappedBalance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChatPointEvent {
+    pub chat_id: TelegramChatId,
+    pub telegram_account: TelegramAccountId,
+    pub total_points: WrappedBalance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DistributeTiptokensEvent {
+    pub chat_id: TelegramChatId,
+    pub chat_admin_account_id: AccountId,
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub fee: WrappedBalance,
+    pub chat_admin_points: WrappedBalance,
+    pub sender_points: WrappedBalance,
+    pub treasury_amount: WrappedBalance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimTiptokensEvent {
+    pub account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub reward_points: WrappedBalance,
+    pub tiptoken_amount: WrappedBalance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RedeemTiptokensEvent {
+    pub account_id: AccountId,
+    pub tiptoken_amount: WrappedBalance,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+}
+
+// contract balance of the token doesn't cover its liabilities
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SolvencyAlertEvent {
+    pub token_id: TokenAccountId,
+    pub balance: WrappedBalance,
+    pub liabilities: WrappedBalance,
+    pub shortfall: WrappedBalance,
+}
+
+// ft_transfer_call which was refunded by ft_on_transfer
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferRejectedEvent {
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub reason: TransferRejectReason,
+}
+
+// created giveaway or the unclaimed remainder and slots of the closed one
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GiveawayEvent {
+    pub giveaway_id: GiveawayId,
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub slots: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<TelegramChatId>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimGiveawayEvent {
+    pub giveaway_id: GiveawayId,
+    pub telegram_account: TelegramAccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+}
+
+// expired tips returned from the telegram balance to the sender's deposit
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReclaimTipsEvent {
+    pub sender_account_id: AccountId,
+    pub telegram_account: TelegramAccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionEvent {
+    pub subscription_id: SubscriptionId,
+    pub sender_account_id: AccountId,
+    pub recipient: SubscriptionRecipient,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub period: U64,
+    pub max_payments: u32,
+}
+
+// due payment which wasn't made, payments are sent as tip events
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SkipSubscriptionEvent {
+    pub subscription_id: SubscriptionId,
+    pub reason: SubscriptionSkipReason,
+}
+
+// cancelled or fully paid subscription
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CloseSubscriptionEvent {
+    pub subscription_id: SubscriptionId,
+    pub payments_made: u32,
+}
+
+// generic tip with attached tokens which owner wasn't found by the auth contract
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnresolvedTipEvent {
+    pub sender_account_id: AccountId,
+    pub contact: Contact,
+    pub amount: WrappedBalance,
+    pub outcome: UnresolvedTipOutcome,
+}
+
+// auth contract call of the method failed or returned unexpected data
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuthFailedEvent {
+    pub method: String,
+    pub account_id: AccountId,
+    pub contact: Contact,
+    pub reason: AuthFailureReason,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deposit_event() {
+        let event = EventKind::Deposit(vec![DepositEvent {
+            account_id: "alice.near".to_string(),
+            token_id: NEAR.to_string(),
+            amount: U128(100),
+        }]);
+        assert_eq!(
+            event.to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"deposit","data":[{"account_id":"alice.near","token_id":"near","amount":"100"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_tip_event_skips_empty_fields() {
+        let event = EventKind::Tip(vec![TipEvent {
+            sender_account_id: "alice.near".to_string(),
+            telegram_account: Some(123),
+            receiver_account_id: None,
+            contact: None,
+            token_id: "dai.near".to_string(),
+            amount: U128(95),
+            fee: U128(5),
+            chat_id: Some(42),
+            expires_at: None,
+        }]);
+        assert_eq!(
+            event.to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"tip","data":[{"sender_account_id":"alice.near","telegram_account":123,"token_id":"dai.near","amount":"95","fee":"5","chat_id":42}]}"#
+        );
+    }
+
+    #[test]
+    fn test_tip_to_contact_event() {
+        let event = EventKind::Tip(vec![TipEvent {
+            sender_account_id: "alice.near".to_string(),
+            telegram_account: None,
+            receiver_account_id: Some("bob.near".to_string()),
+            contact: Some(Contact {
+                category: ContactCategories::Github,
+                value: "bob".to_string(),
+                account_id: None,
+            }),
+            token_id: NEAR.to_string(),
+            amount: U128(10),
+            fee: U128(0),
+            chat_id: None,
+            expires_at: None,
+        }]);
+        assert_eq!(
+            event.to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"tip","data":[{"sender_account_id":"alice.near","receiver_account_id":"bob.near","contact":{"category":"Github","value":"bob","account_id":null},"token_id":"near","amount":"10","fee":"0"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_withdraw_events() {
+        let withdraw = WithdrawEvent {
+            account_id: "bob.near".to_string(),
+            telegram_account: Some(123),
+            token_id: NEAR.to_string(),
+            amount: U128(997),
+            fee: U128(3),
+        };
+        assert_eq!(
+            EventKind::Withdraw(vec![withdraw]).to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"withdraw","data":[{"account_id":"bob.near","telegram_account":123,"token_id":"near","amount":"997","fee":"3"}]}"#
+        );
+
+        let failed = WithdrawFailedEvent {
+            account_id: None,
+            telegram_account: Some(123),
+            token_id: "dai.near".to_string(),
+            amount: U128(1000),
+        };
+        assert_eq!(
+            EventKind::WithdrawFailed(vec![failed]).to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"withdraw_failed","data":[{"telegram_account":123,"token_id":"dai.near","amount":"1000"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_withdraw_linkdrop_event() {
+        let event = EventKind::WithdrawLinkdrop(vec![WithdrawLinkdropEvent {
+            telegram_account: 123,
+            public_key: "ed25519:key".to_string(),
+            amount: U128(997),
+            fee: U128(3),
+        }]);
+        assert_eq!(
+            event.to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"withdraw_linkdrop","data":[{"telegram_account":123,"public_key":"ed25519:key","amount":"997","fee":"3"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_distribute_tiptokens_event() {
+        let event = EventKind::DistributeTiptokens(vec![DistributeTiptokensEvent {
+            chat_id: 42,
+            chat_admin_account_id: "admin.near".to_string(),
+            sender_account_id: "alice.near".to_string(),
+            token_id: NEAR.to_string(),
+            fee: U128(10),
+            chat_admin_points: U128(4),
+            sender_points: U128(4),
+            treasury_amount: U128(2),
+        }]);
+        assert_eq!(
+            event.to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"distribute_tiptokens","data":[{"chat_id":42,"chat_admin_account_id":"admin.near","sender_account_id":"alice.near","token_id":"near","fee":"10","chat_admin_points":"4","sender_points":"4","treasury_amount":"2"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_redeem_tiptokens_event() {
+        let event = EventKind::RedeemTiptokens(vec![
+            RedeemTiptokensEvent {
+                account_id: "alice.near".to_string(),
+                tiptoken_amount: U128(50),
+                token_id: NEAR.to_string(),
+                amount: U128(7),
+            },
+            RedeemTiptokensEvent {
+                account_id: "alice.near".to_string(),
+                tiptoken_amount: U128(50),
+                token_id: "dai.near".to_string(),
+                amount: U128(3),
+            },
+        ]);
+        assert_eq!(
+            event.to_json_string(),
+            r#"{"standard":"tipbot","version":"1.0.0","event":"redeem_tiptokens","data":[{"account_id":"alice.near","tiptoken_amount":"50","token_id":"near","amount":"7"},{"account_id":"alice.near","tiptoken_amount":"50","token_id":"dai.near","amount":"3"}]}"#
+        );
+    }
+}
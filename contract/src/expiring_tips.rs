@@ -0,0 +1,193 @@
+use crate::*;
+use near_sdk::Timestamp;
+
+/* EXPIRING TIPS, unclaimed part of a telegram tip returns to the sender after the expiry */
+// records of a sender per telegram account and token, one sender can't use up the records of others.
+// Storage of the records is paid by the senders
+const MAX_EXPIRING_TIPS_PER_SENDER: usize = 10;
+// all records per telegram account and token, keeps the vector cheap to read on every withdrawal
+const MAX_EXPIRING_TIPS: usize = 50;
+
+// amount is still a part of the telegram balance, tokens leaving the balance are taken from the oldest records first
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ExpiringTip {
+    pub sender_account_id: AccountId,
+    pub amount: Balance,
+    pub created_at: Timestamp,
+    pub expires_at: Timestamp,
+}
+
+// also passed to the withdraw callbacks, so the claimed records are restored if the transfer fails
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExpiringTipOutput {
+    pub sender_account_id: AccountId,
+    pub amount: WrappedBalance,
+    pub created_at: WrappedTimestamp,
+    pub expires_at: WrappedTimestamp,
+}
+
+impl From<ExpiringTip> for ExpiringTipOutput {
+    fn from(tip: ExpiringTip) -> Self {
+        ExpiringTipOutput {
+            sender_account_id: tip.sender_account_id,
+            amount: tip.amount.into(),
+            created_at: tip.created_at.into(),
+            expires_at: tip.expires_at.into(),
+        }
+    }
+}
+
+impl From<ExpiringTipOutput> for ExpiringTip {
+    fn from(tip: ExpiringTipOutput) -> Self {
+        ExpiringTip {
+            sender_account_id: tip.sender_account_id,
+            amount: tip.amount.0,
+            created_at: tip.created_at.0,
+            expires_at: tip.expires_at.0,
+        }
+    }
+}
+
+#[near_bindgen]
+impl NearTips {
+    pub fn get_expiring_tips(&self, telegram_account: TelegramAccountId, token_id: Option<TokenAccountId>) -> Vec<ExpiringTipOutput> {
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id: NearTips::unwrap_token_id(&token_id),
+        };
+        self.expiring_tips.get(&key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(ExpiringTipOutput::from)
+            .collect()
+    }
+
+    // unclaimed amounts of the expired tips from the sender to the telegram account return to the sender's deposit
+    pub fn reclaim_expired_tips(&mut self, telegram_account: TelegramAccountId, token_id: Option<TokenAccountId>) -> WrappedBalance {
+        let sender_account_id = env::predecessor_account_id();
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id: token_id_unwrapped.clone(),
+        };
+
+        let now = env::block_timestamp();
+        let (expired_tips, tips): (Vec<ExpiringTip>, Vec<ExpiringTip>) = self.expiring_tips.get(&key)
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|tip| tip.sender_account_id == sender_account_id && tip.expires_at <= now);
+        let amount: Balance = expired_tips.iter().map(|tip| tip.amount).sum();
+        assert!(amount > 0, "No expired tips to reclaim");
+
+        let initial_storage_usage = env::storage_usage();
+        self.set_expiring_tips(&key, tips);
+        self.debit_balance(telegram_account, token_id_unwrapped.clone(), amount);
+        self.increase_deposit(sender_account_id.clone(), token_id_unwrapped.clone(), amount);
+        self.charge_storage(&sender_account_id, initial_storage_usage);
+
+        EventKind::ReclaimTips(vec![ReclaimTipsEvent {
+            sender_account_id,
+            telegram_account,
+            token_id: token_id_unwrapped,
+            amount: amount.into(),
+        }]).emit();
+
+        amount.into()
+    }
+}
+
+impl NearTips {
+    pub(crate) fn add_expiring_tip(&mut self,
+                                   sender_account_id: &AccountId,
+                                   telegram_account: TelegramAccountId,
+                                   token_id: &TokenAccountId,
+                                   amount: Balance,
+                                   expires_at: Timestamp) {
+        let now = env::block_timestamp();
+        assert!(expires_at > now, "Tip expiration should be in the future");
+        assert!(
+            self.can_add_expiring_tip(sender_account_id, telegram_account, token_id),
+            "Too many expiring tips for telegram account {}", telegram_account
+        );
+
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id: token_id.clone(),
+        };
+        let mut tips = self.expiring_tips.get(&key).unwrap_or_default();
+
+        tips.push(ExpiringTip {
+            sender_account_id: sender_account_id.clone(),
+            amount,
+            created_at: now,
+            expires_at,
+        });
+        self.expiring_tips.insert(&key, &tips);
+    }
+
+    pub(crate) fn can_add_expiring_tip(&self, sender_account_id: &AccountId, telegram_account: TelegramAccountId, token_id: &TokenAccountId) -> bool {
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id: token_id.clone(),
+        };
+        let tips = self.expiring_tips.get(&key).unwrap_or_default();
+        let sender_tips = tips.iter()
+            .filter(|tip| &tip.sender_account_id == sender_account_id)
+            .count();
+        tips.len() < MAX_EXPIRING_TIPS && sender_tips < MAX_EXPIRING_TIPS_PER_SENDER
+    }
+
+    // withdrawn tokens are claimed from the oldest expiring tips first, claimed parts are returned for the rollback
+    pub(crate) fn claim_expiring_tips(&mut self, key: &TokenByTelegramAccount, amount: Balance) -> Vec<ExpiringTip> {
+        let mut claimed_tips = vec![];
+        if let Some(tips) = self.expiring_tips.get(key) {
+            let mut amount_left = amount;
+            let tips = tips
+                .into_iter()
+                .filter_map(|mut tip| {
+                    let claimed = std::cmp::min(tip.amount, amount_left);
+                    amount_left -= claimed;
+                    tip.amount -= claimed;
+                    if claimed > 0 {
+                        claimed_tips.push(ExpiringTip {
+                            sender_account_id: tip.sender_account_id.clone(),
+                            amount: claimed,
+                            created_at: tip.created_at,
+                            expires_at: tip.expires_at,
+                        });
+                    }
+                    if tip.amount > 0 { Some(tip) } else { None }
+                })
+                .collect();
+            self.set_expiring_tips(key, tips);
+        }
+        claimed_tips
+    }
+
+    // claimed parts of a failed withdrawal are merged back in the order of creation
+    pub(crate) fn restore_expiring_tips(&mut self, key: &TokenByTelegramAccount, claimed_tips: Vec<ExpiringTip>) {
+        if claimed_tips.is_empty() {
+            return;
+        }
+        let mut tips = self.expiring_tips.get(key).unwrap_or_default();
+        for claimed_tip in claimed_tips {
+            match tips.iter_mut().find(|tip| tip.sender_account_id == claimed_tip.sender_account_id
+                && tip.created_at == claimed_tip.created_at
+                && tip.expires_at == claimed_tip.expires_at) {
+                Some(tip) => tip.amount += claimed_tip.amount,
+                None => tips.push(claimed_tip),
+            }
+        }
+        tips.sort_by_key(|tip| tip.created_at);
+        self.set_expiring_tips(key, tips);
+    }
+
+    fn set_expiring_tips(&mut self, key: &TokenByTelegramAccount, tips: Vec<ExpiringTip>) {
+        if tips.is_empty() {
+            self.expiring_tips.remove(key);
+        } else {
+            self.expiring_tips.insert(key, &tips);
+        }
+    }
+}
@@ -1,8 +1,20 @@
 use crate::*;
+use crate::auth_tips::get_auth_result;
 
 /* GENERIC TIPS, using tips object */
 const UNDEFINED_ACCOUNT_ID: &str = "";
 
+// what happened with attached tokens when the contact owner wasn't resolved
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum UnresolvedTipOutcome {
+    // auth contract call failed, tokens are sent back to the sender
+    Refunded,
+    // contact has no owner yet, tip waits for the owner under the undefined account
+    Parked,
+}
+
 #[near_bindgen]
 impl NearTips {
     pub(crate) fn assert_generic_tips_available(&self) {
@@ -29,7 +41,7 @@ impl NearTips {
 
         let account_id = env::predecessor_account_id();
 
-        self.get_contact_owner(contact.clone(), self.auth_account_id.to_string()).
+        self.get_contact_owner(contact.clone()).
             then(ext_self::on_get_contact_owner_on_tip_contact_with_attached_tokens(
                 account_id,
                 contact,
@@ -41,21 +53,44 @@ impl NearTips {
     }
 
 
+    // attached tokens have no ledger entry yet, so the callback never panics on a missing owner
     pub fn on_get_contact_owner_on_tip_contact_with_attached_tokens(&mut self,
-                                                                    #[callback] account: Option<AccountId>,
                                                                     sender_account_id: AccountId,
                                                                     contact: Contact,
-                                                                    deposit: Balance) {
+                                                                    deposit: Balance) -> bool {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Callback can only be called from the contract"
         );
 
-        assert!(!account.is_none(), "Owner not found");
-        let receiver_account_id: AccountId = account.unwrap();
+        // failed call or malformed response means the owner is unknown, not missing
+        let outcome = match get_auth_result::<AccountId>() {
+            Ok(Some(receiver_account_id)) => {
+                self.cache_contact_owner(&contact, Some(&receiver_account_id));
+                NearTips::tip_transfer(self, sender_account_id, receiver_account_id, contact, deposit);
+                return true;
+            }
+            Ok(None) => {
+                self.cache_contact_owner(&contact, None);
+                NearTips::tip_transfer(self, sender_account_id.clone(), UNDEFINED_ACCOUNT_ID.to_string(), contact.clone(), deposit);
+                UnresolvedTipOutcome::Parked
+            }
+            Err(reason) => {
+                NearTips::auth_failed("tip_contact_with_attached_tokens", &sender_account_id, contact.clone(), reason);
+                Promise::new(sender_account_id.clone()).transfer(deposit);
+                UnresolvedTipOutcome::Refunded
+            }
+        };
+
+        EventKind::UnresolvedTip(vec![UnresolvedTipEvent {
+            sender_account_id,
+            contact,
+            amount: deposit.into(),
+            outcome,
+        }]).emit();
 
-        NearTips::tip_transfer(self, sender_account_id, receiver_account_id, contact, deposit);
+        false
     }
 
 
@@ -67,53 +102,20 @@ impl NearTips {
                                receiver_account_id: AccountId,
                                contact: Contact,
                                deposit: Balance) {
-        self.assert_tip_available();
-        self.assert_generic_tips_available();
-
-        match self.tips.get(&receiver_account_id) {
-            Some(tips) => {
-                let mut contact_found = false;
-                let mut filtered_tips: Vec<_> =
-                    tips
-                        .iter()
-                        .map(|tip| {
-                            if NearTips::are_contacts_equal(tip.contact.clone(), contact.clone()) {
-                                contact_found = true;
-                                Tip {
-                                    contact: contact.clone(),
-                                    amount: tip.amount + deposit,
-                                }
-                            } else {
-                                tip.clone()
-                            }
-                        })
-                        .collect();
-
-                env::log(format!("contact_found {}", contact_found).as_bytes());
-
-                if !contact_found {
-                    let tip: Tip = Tip {
-                        contact: contact.clone(),
-                        amount: deposit,
-                    };
-                    filtered_tips.push(tip);
-                }
-
-                self.tips.insert(&receiver_account_id.clone(), &filtered_tips);
-            }
-            None => {
-                let mut tips: Vec<Tip> = vec![];
-                let tip: Tip = Tip {
-                    contact: contact.clone(),
-                    amount: deposit,
-                };
-                tips.push(tip);
-                self.tips.insert(&receiver_account_id.clone(), &tips);
-            }
-        }
-
-        env::log(format!("@{} tipped {} yNEAR to @{} [{:?} account {:?}]",
-                         sender_account_id, deposit, receiver_account_id, contact.category, contact.value).as_bytes());
+        // availability is checked by the callers, the callback has to record the attached tokens anyway
+        self.increase_tip(&receiver_account_id, &contact, deposit);
+
+        self.record_tips(vec![TipEvent {
+            sender_account_id,
+            telegram_account: None,
+            receiver_account_id: Some(receiver_account_id),
+            contact: Some(contact),
+            token_id: NEAR.to_string(),
+            amount: deposit.into(),
+            fee: 0.into(),
+            chat_id: None,
+            expires_at: None,
+        }]);
     }
 
     #[payable]
@@ -133,31 +135,28 @@ impl NearTips {
     }
 
     pub fn get_tips_wrapped(&self, account_id: AccountId) -> Option<Vec<TipWrapped>> {
-        match self.tips.get(&account_id) {
-            Some(tips) => Some(tips
-                .iter()
-                .map(|tip| TipWrapped {
-                    contact: tip.contact.clone(),
-                    amount: WrappedBalance::from(tip.amount),
-                })
-                .collect::<Vec<TipWrapped>>()
-                .to_vec()),
-            None => None
-        }
+        self.tips.get(&account_id).map(|tips| tips
+            .iter()
+            .map(|tip| TipWrapped {
+                contact: tip.contact.clone(),
+                amount: WrappedBalance::from(tip.amount),
+            })
+            .collect::<Vec<TipWrapped>>())
     }
 
     // we can tip contact which doesn't have near account_id yet
-    fn withdraw_tip_for_undefined_account(&self, contact: Contact, balance_to_withdraw: Balance) -> Promise {
+    fn withdraw_tip_for_undefined_account(&mut self, contact: Contact, balance_to_withdraw: Balance) -> Promise {
         self.assert_withdraw_available();
         self.assert_generic_tips_available();
 
         let account_id = env::predecessor_account_id();
+        let reservation_id = self.reserve_tip(&UNDEFINED_ACCOUNT_ID.to_string(), contact.clone(), balance_to_withdraw);
 
-        self.get_contact_owner(contact.clone(), self.auth_account_id.to_string())
+        self.get_contact_owner(contact.clone())
             .then(ext_self::on_get_contact_owner_on_withdraw_tip_for_undefined_account(
+                reservation_id,
                 account_id,
                 contact,
-                balance_to_withdraw,
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 CALLBACK_GAS * 3,
@@ -166,49 +165,57 @@ impl NearTips {
 
 
     pub fn on_get_contact_owner_on_withdraw_tip_for_undefined_account(&mut self,
-                                                                      #[callback] account: Option<AccountId>,
+                                                                      reservation_id: ReservationId,
                                                                       recipient_account_id: AccountId,
-                                                                      recipient_contact: Contact,
-                                                                      balance_to_withdraw: Balance) -> Promise {
+                                                                      recipient_contact: Contact) -> PromiseOrValue<bool> {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Callback can only be called from the contract"
         );
 
-        assert!(!account.is_none(), "Owner not found");
-        let contact_owner_account_id: AccountId = account.unwrap();
+        let contact_owner_account_id = match get_auth_result::<AccountId>() {
+            Ok(account_id) => account_id,
+            Err(reason) => {
+                NearTips::auth_failed("withdraw_tip", &recipient_account_id, recipient_contact, reason);
+                self.release_reservation(reservation_id);
+                return PromiseOrValue::Value(false);
+            }
+        };
+        self.cache_contact_owner(&recipient_contact, contact_owner_account_id.as_ref());
+        if contact_owner_account_id.as_ref() != Some(&recipient_account_id) {
+            env::log("Current user not allowed to withdraw tip for this contact".to_string().as_bytes());
+            self.release_reservation(reservation_id);
+            return PromiseOrValue::Value(false);
+        }
 
-        assert_eq!(
-            contact_owner_account_id,
-            recipient_account_id,
-            "Current user not allowed to withdraw tip for this contact");
+        let balance_to_withdraw = self.reservations.get(&reservation_id).expect("Reservation not found").amount;
 
         env::log(format!("Transfer to @{} [{:?} account {:?}]", recipient_account_id, recipient_contact.category, recipient_contact.value).as_bytes());
 
-        Promise::new(recipient_account_id)
-            .transfer(balance_to_withdraw)
-            .then(ext_self::on_withdraw_tip(
-                UNDEFINED_ACCOUNT_ID.to_string(),
-                recipient_contact,
-                balance_to_withdraw,
-                &env::current_account_id(),
-                0,
-                CALLBACK_GAS,
-            ))
+        PromiseOrValue::Promise(
+            Promise::new(recipient_account_id.clone())
+                .transfer(balance_to_withdraw)
+                .then(ext_self::on_withdraw_tip(
+                    reservation_id,
+                    recipient_account_id,
+                    &env::current_account_id(),
+                    0,
+                    CALLBACK_GAS,
+                )))
     }
 
-    fn withdraw_tip_for_current_account(&self, contact: Contact, balance_to_withdraw: Balance) -> Promise {
+    fn withdraw_tip_for_current_account(&mut self, contact: Contact, balance_to_withdraw: Balance) -> Promise {
         self.assert_withdraw_available();
         self.assert_generic_tips_available();
 
         let account_id = env::predecessor_account_id();
+        let reservation_id = self.reserve_tip(&account_id, contact.clone(), balance_to_withdraw);
 
-        auth::get_contacts(account_id.clone(), &self.auth_account_id, NO_DEPOSIT, BASE_GAS)
+        auth::get_contacts(account_id, &self.get_identity_provider_for(&contact.category), NO_DEPOSIT, BASE_GAS)
             .then(ext_self::on_get_contacts_on_withdraw_tip_for_current_account(
-                account_id,
+                reservation_id,
                 contact,
-                balance_to_withdraw,
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 CALLBACK_GAS * 3,
@@ -216,44 +223,45 @@ impl NearTips {
     }
 
     pub fn on_get_contacts_on_withdraw_tip_for_current_account(&mut self,
-                                                               #[callback] contacts: Option<Vec<Contact>>,
-                                                               recipient_account_id: AccountId,
-                                                               recipient_contact: Contact,
-                                                               balance: Balance) -> bool {
+                                                               reservation_id: ReservationId,
+                                                               recipient_contact: Contact) -> bool {
         assert_eq!(
             env::predecessor_account_id(),
             env::current_account_id(),
             "Callback can only be called from the contract"
         );
 
-        match contacts {
-            Some(contacts) => {
-                for contact in &contacts {
-                    env::log(format!("Check: [{:?} account {:?}]", contact.category, contact.value).as_bytes());
-                    if NearTips::are_contacts_equal(contact.clone(), recipient_contact.clone()) {
-                        Promise::new(recipient_account_id.clone())
-                            .transfer(balance)
-                            .then(ext_self::on_withdraw_tip(
-                                recipient_account_id.clone(),
-                                contact.clone(),
-                                balance,
-                                &env::current_account_id(),
-                                0,
-                                CALLBACK_GAS,
-                            ));
-
-                        env::log(format!("Transfer to {} [{:?} account {:?}]", recipient_account_id, contact.category, contact.value).as_bytes());
-
-                        return true;
-                    }
-                }
+        let contacts = match get_auth_result::<Vec<Contact>>() {
+            Ok(contacts) => contacts.unwrap_or_default(),
+            Err(reason) => {
+                let reservation = self.release_reservation(reservation_id);
+                NearTips::auth_failed("withdraw_tip", &reservation.account_id, recipient_contact, reason);
+                return false;
+            }
+        };
+        match contacts.into_iter().find(|contact| NearTips::are_contacts_equal(contact.clone(), recipient_contact.clone())) {
+            Some(contact) => {
+                let reservation = self.reservations.get(&reservation_id).expect("Reservation not found");
+                Promise::new(reservation.account_id.clone())
+                    .transfer(reservation.amount)
+                    .then(ext_self::on_withdraw_tip(
+                        reservation_id,
+                        reservation.account_id.clone(),
+                        &env::current_account_id(),
+                        0,
+                        CALLBACK_GAS,
+                    ));
+
+                env::log(format!("Transfer to {} [{:?} account {:?}]", reservation.account_id, contact.category, contact.value).as_bytes());
+
+                true
             }
             None => {
                 env::log("Contacts not found".to_string().as_bytes());
+                self.release_reservation(reservation_id);
+                false
             }
         }
-
-        false
     }
 
     pub fn withdraw_tip(&mut self, contact: Contact) -> PromiseOrValue<bool> {
@@ -273,8 +281,9 @@ impl NearTips {
         if balance_of_account > 0 && balance_of_undefined_account > 0 {
             env::log(format!("Tips for account & undefined account {} found", account_id).as_bytes());
 
+            let withdraw_for_current_account = NearTips::withdraw_tip_for_current_account(self, contact.clone(), balance_of_account);
             PromiseOrValue::Promise(
-                NearTips::withdraw_tip_for_current_account(self, contact.clone(), balance_of_account)
+                withdraw_for_current_account
                     .then(NearTips::withdraw_tip_for_undefined_account(self, contact, balance_of_undefined_account)))
         } else if balance_of_account > 0 {
             env::log(format!("Tips for account {} found", account_id).as_bytes());
@@ -289,47 +298,55 @@ impl NearTips {
         }
     }
 
+    // the tip was reserved before the transfer, failed transfer returns it to the contact.
+    // Tips of undefined accounts are reserved for "", so the recipient is passed separately
     #[private]
-    pub fn on_withdraw_tip(&mut self, account_id: AccountId, contact: Contact, balance: Balance) -> bool {
-
-        let transfer_succeeded = is_promise_success();
-        if transfer_succeeded {
-            match self.tips.get(&account_id) {
-                Some(tips) => {
-                    let mut contact_found = false;
-                    let filtered_tips: Vec<_> =
-                        tips
-                            .iter()
-                            .map(|tip| {
-                                if tip.contact == contact {
-                                    contact_found = true;
-                                    Tip {
-                                        contact: contact.clone(),
-                                        amount: tip.amount - balance,
-                                    }
-                                } else {
-                                    tip.clone()
-                                }
-                            })
-                            .collect();
-
-                    env::log(format!("on_withdraw_tip contact_found {}", contact_found).as_bytes());
-
-                    if contact_found {
-                        env::log(format!("Tip deducted for @{} by {} [{:?} account {:?}]", account_id, balance, contact.category, contact.value).as_bytes());
-                        self.tips.insert(&account_id.clone(), &filtered_tips);
-                        true
-                    } else {
-                        false
-                    }
-                }
-                None => {
-                    false
-                }
-            }
-        } else {
-            false
+    pub fn on_withdraw_tip(&mut self, reservation_id: ReservationId, recipient_account_id: AccountId) -> bool {
+        if !is_promise_success() {
+            self.release_reservation(reservation_id);
+            return false;
+        }
+
+        let reservation = self.commit_reservation(reservation_id);
+        let contact = match reservation.source {
+            ReservationSource::Tip(contact) => contact,
+            ReservationSource::Deposit => env::panic(b"Reservation of the deposit"),
+        };
+
+        EventKind::Withdraw(vec![WithdrawEvent {
+            account_id: recipient_account_id,
+            telegram_account: contact.account_id,
+            token_id: NEAR.to_string(),
+            amount: reservation.amount.into(),
+            fee: 0.into(),
+        }]).emit();
+        true
+    }
+}
+
+impl NearTips {
+    pub(crate) fn increase_tip(&mut self, account_id: &AccountId, contact: &Contact, amount: Balance) {
+        let mut tips = self.tips.get(account_id).unwrap_or_default();
+        match tips.iter_mut().find(|tip| NearTips::are_contacts_equal(tip.contact.clone(), contact.clone())) {
+            Some(tip) => tip.amount += amount,
+            None => tips.push(Tip {
+                contact: contact.clone(),
+                amount,
+            }),
         }
+        self.tips.insert(account_id, &tips);
+        self.tip_holders.insert(account_id);
+        self.update_liabilities(&NEAR.to_string(), |l| l.tips += amount);
     }
 
-}
\ No newline at end of file
+    pub(crate) fn decrease_tip(&mut self, account_id: &AccountId, contact: &Contact, amount: Balance) {
+        let mut tips = self.tips.get(account_id).expect("Tips not found");
+        let tip = tips.iter_mut()
+            .find(|tip| NearTips::are_contacts_equal(tip.contact.clone(), contact.clone()))
+            .expect("Tip not found");
+        assert!(amount <= tip.amount, "Not enough tokens to withdraw (Balance: {}. Requested: {})", tip.amount, amount);
+        tip.amount -= amount;
+        self.tips.insert(account_id, &tips);
+        self.update_liabilities(&NEAR.to_string(), |l| l.tips = l.tips.saturating_sub(amount));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::*;
+use near_sdk::Timestamp;
+use std::convert::TryInto;
+
+/* GIVEAWAYS, packets locked from the deposit and claimed slot by slot for telegram accounts */
+pub type GiveawayId = u64;
+
+// slots of a single giveaway, claimed accounts are kept in the packet
+pub(crate) const MAX_GIVEAWAY_SLOTS: u32 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum GiveawaySplit {
+    Equal,
+    // random amount between min_tip (at least 1) and twice the average of the unclaimed slots
+    Random,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Giveaway {
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: Balance,
+    pub remaining: Balance,
+    pub slots: u32,
+    pub split: GiveawaySplit,
+    pub chat_id: Option<TelegramChatId>,
+    pub expires_at: Option<Timestamp>,
+    pub claimed_by: Vec<TelegramAccountId>,
+}
+
+impl Giveaway {
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GiveawayOutput {
+    pub giveaway_id: GiveawayId,
+    pub sender_account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub remaining: WrappedBalance,
+    pub slots: u32,
+    pub claimed: u32,
+    pub split: GiveawaySplit,
+    pub chat_id: Option<TelegramChatId>,
+    pub expires_at: Option<WrappedTimestamp>,
+}
+
+#[near_bindgen]
+impl NearTips {
+    // locks amount from the deposit, expires_at is in nanoseconds
+    pub fn create_giveaway(&mut self,
+                           amount: WrappedBalance,
+                           slots: u32,
+                           token_id: Option<TokenAccountId>,
+                           split: GiveawaySplit,
+                           chat_id: Option<TelegramChatId>,
+                           expires_at: Option<WrappedTimestamp>) -> GiveawayId {
+        self.assert_tip_available();
+        self.assert_token_available(&token_id);
+
+        let sender_account_id = env::predecessor_account_id();
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        self.create_giveaway_from_deposit(sender_account_id, token_id_unwrapped, amount.0, slots, split, chat_id, expires_at.map(|t| t.0))
+    }
+
+    // bot claims a slot on behalf of the telegram account, chat_id is the chat of the claim
+    pub fn claim_giveaway(&mut self,
+                          giveaway_id: GiveawayId,
+                          telegram_account: TelegramAccountId,
+                          chat_id: Option<TelegramChatId>) -> WrappedBalance {
+        self.assert_master_account_id();
+        self.assert_tip_available();
+
+        let mut giveaway = self.giveaways.get(&giveaway_id).expect("Giveaway not found");
+        assert!(!giveaway.is_expired(), "Giveaway expired");
+        if let Some(giveaway_chat_id) = giveaway.chat_id {
+            assert_eq!(chat_id, Some(giveaway_chat_id), "Giveaway is restricted to chat {}", giveaway_chat_id);
+        }
+        assert!(!giveaway.claimed_by.contains(&telegram_account), "Giveaway was already claimed by {}", telegram_account);
+
+        let min_amount = self.get_token_config_or_default(&giveaway.token_id).min_tip.0;
+        let amount = NearTips::get_giveaway_slot_amount(&giveaway, min_amount, telegram_account);
+
+        // claim records are paid by the sender
+        let initial_storage_usage = env::storage_usage();
+        giveaway.remaining -= amount;
+        giveaway.claimed_by.push(telegram_account);
+        if giveaway.claimed_by.len() as u32 == giveaway.slots {
+            self.remove_giveaway(giveaway_id, &giveaway);
+        } else {
+            self.giveaways.insert(&giveaway_id, &giveaway);
+        }
+
+        self.update_liabilities(&giveaway.token_id, |l| l.giveaways = l.giveaways.saturating_sub(amount));
+        self.increase_balance(telegram_account, giveaway.token_id.clone(), amount);
+        self.charge_storage(&giveaway.sender_account_id, initial_storage_usage);
+
+        EventKind::ClaimGiveaway(vec![ClaimGiveawayEvent {
+            giveaway_id,
+            telegram_account,
+            token_id: giveaway.token_id,
+            amount: amount.into(),
+        }]).emit();
+
+        amount.into()
+    }
+
+    // unclaimed remainder returns to the sender's deposit. Anyone can close an expired giveaway,
+    // giveaway without expiry can be closed by the sender at any time
+    pub fn close_giveaway(&mut self, giveaway_id: GiveawayId) -> WrappedBalance {
+        let giveaway = self.giveaways.get(&giveaway_id).expect("Giveaway not found");
+        if giveaway.expires_at.is_some() {
+            assert!(giveaway.is_expired(), "Giveaway is not expired yet");
+        } else {
+            assert_eq!(env::predecessor_account_id(), giveaway.sender_account_id, "No access");
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        self.remove_giveaway(giveaway_id, &giveaway);
+        self.update_liabilities(&giveaway.token_id, |l| l.giveaways = l.giveaways.saturating_sub(giveaway.remaining));
+        self.increase_deposit(giveaway.sender_account_id.clone(), giveaway.token_id.clone(), giveaway.remaining);
+        self.charge_storage(&giveaway.sender_account_id, initial_storage_usage);
+
+        EventKind::CloseGiveaway(vec![GiveawayEvent {
+            giveaway_id,
+            sender_account_id: giveaway.sender_account_id,
+            token_id: giveaway.token_id,
+            amount: giveaway.remaining.into(),
+            slots: giveaway.slots - giveaway.claimed_by.len() as u32,
+            chat_id: giveaway.chat_id,
+        }]).emit();
+
+        giveaway.remaining.into()
+    }
+
+    pub fn get_giveaway(&self, giveaway_id: GiveawayId) -> Option<GiveawayOutput> {
+        self.giveaways.get(&giveaway_id).map(|giveaway| NearTips::giveaway_output(giveaway_id, giveaway))
+    }
+
+    // includes expired giveaways which are waiting to be closed
+    pub fn get_giveaways(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<GiveawayOutput> {
+        NearTips::paginate(self.giveaways.keys_as_vector(), from_index, limit)
+            .into_iter()
+            .map(|giveaway_id| self.get_giveaway(giveaway_id).unwrap())
+            .collect()
+    }
+
+    // open giveaways of the chat, expired ones are skipped
+    pub fn get_chat_giveaways(&self, chat_id: TelegramChatId, from_index: Option<u64>, limit: Option<u64>) -> Vec<GiveawayOutput> {
+        match self.chat_giveaways.get(&chat_id) {
+            Some(giveaway_ids) =>
+                NearTips::paginate(giveaway_ids.as_vector(), from_index, limit)
+                    .into_iter()
+                    .filter_map(|giveaway_id| self.giveaways.get(&giveaway_id).map(|giveaway| (giveaway_id, giveaway)))
+                    .filter(|(_, giveaway)| !giveaway.is_expired())
+                    .map(|(giveaway_id, giveaway)| NearTips::giveaway_output(giveaway_id, giveaway))
+                    .collect(),
+            None => vec![]
+        }
+    }
+}
+
+impl NearTips {
+    pub(crate) fn assert_valid_giveaway(&self, token_id: &TokenAccountId, amount: Balance, slots: u32, expires_at: Option<Timestamp>) {
+        assert!(slots > 0, "Positive slots needed");
+        assert!(slots <= MAX_GIVEAWAY_SLOTS, "Too many slots, max {}", MAX_GIVEAWAY_SLOTS);
+        assert!(amount >= slots as Balance, "Not enough tokens for {} slots", slots);
+        self.assert_min_tip(token_id, amount / slots as Balance);
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at > env::block_timestamp(), "Giveaway expiration should be in the future");
+        }
+    }
+
+    pub(crate) fn create_giveaway_from_deposit(&mut self,
+                                               sender_account_id: AccountId,
+                                               token_id: TokenAccountId,
+                                               amount: Balance,
+                                               slots: u32,
+                                               split: GiveawaySplit,
+                                               chat_id: Option<TelegramChatId>,
+                                               expires_at: Option<Timestamp>) -> GiveawayId {
+        assert!(amount > 0, "Positive amount needed");
+        self.assert_valid_giveaway(&token_id, amount, slots, expires_at);
+
+        let deposit = self.get_deposit_for_account_id_and_token_id(&sender_account_id, &token_id);
+        assert!(amount <= deposit, "Not enough tokens deposited to create a giveaway (Deposit: {}. Requested: {})", deposit, amount);
+
+        let initial_storage_usage = env::storage_usage();
+        self.decrease_deposit(sender_account_id.clone(), token_id.clone(), amount);
+        self.update_liabilities(&token_id, |l| l.giveaways += amount);
+
+        let giveaway_id = self.next_giveaway_id;
+        self.next_giveaway_id += 1;
+        self.giveaways.insert(&giveaway_id, &Giveaway {
+            sender_account_id: sender_account_id.clone(),
+            token_id: token_id.clone(),
+            amount,
+            remaining: amount,
+            slots,
+            split,
+            chat_id,
+            expires_at,
+            claimed_by: vec![],
+        });
+        let open_giveaways = self.open_giveaways_per_account.get(&sender_account_id).unwrap_or(0);
+        self.open_giveaways_per_account.insert(&sender_account_id, &(open_giveaways + 1));
+        if let Some(chat_id) = chat_id {
+            let mut giveaway_ids = self.chat_giveaways.get(&chat_id).unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::ChatGiveaways { chat_id })
+            });
+            giveaway_ids.insert(&giveaway_id);
+            self.chat_giveaways.insert(&chat_id, &giveaway_ids);
+        }
+        self.charge_storage(&sender_account_id, initial_storage_usage);
+
+        EventKind::CreateGiveaway(vec![GiveawayEvent {
+            giveaway_id,
+            sender_account_id,
+            token_id,
+            amount: amount.into(),
+            slots,
+            chat_id,
+        }]).emit();
+
+        giveaway_id
+    }
+
+    fn remove_giveaway(&mut self, giveaway_id: GiveawayId, giveaway: &Giveaway) {
+        self.giveaways.remove(&giveaway_id);
+        let open_giveaways = self.open_giveaways_per_account.get(&giveaway.sender_account_id).unwrap_or(0);
+        if open_giveaways > 1 {
+            self.open_giveaways_per_account.insert(&giveaway.sender_account_id, &(open_giveaways - 1));
+        } else {
+            self.open_giveaways_per_account.remove(&giveaway.sender_account_id);
+        }
+        if let Some(chat_id) = giveaway.chat_id {
+            if let Some(mut giveaway_ids) = self.chat_giveaways.get(&chat_id) {
+                giveaway_ids.remove(&giveaway_id);
+                if giveaway_ids.is_empty() {
+                    self.chat_giveaways.remove(&chat_id);
+                } else {
+                    self.chat_giveaways.insert(&chat_id, &giveaway_ids);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn has_open_giveaways(&self, account_id: &AccountId) -> bool {
+        self.open_giveaways_per_account.contains_key(account_id)
+    }
+
+    // the last slot takes the whole remainder. Random amount stays in min_amount..=2 * average - min_amount,
+    // so the average is kept and every unclaimed slot still gets min_amount
+    pub(crate) fn get_giveaway_slot_amount(giveaway: &Giveaway, min_amount: Balance, telegram_account: TelegramAccountId) -> Balance {
+        let slots_left = (giveaway.slots - giveaway.claimed_by.len() as u32) as Balance;
+        if slots_left == 1 {
+            return giveaway.remaining;
+        }
+
+        let average = giveaway.remaining / slots_left;
+        match giveaway.split {
+            GiveawaySplit::Equal => average,
+            GiveawaySplit::Random => {
+                // every slot gets at least 1, amount covers it
+                let min_amount = std::cmp::min(std::cmp::max(min_amount, 1), average);
+                let seed = env::sha256(&[env::random_seed(), telegram_account.to_le_bytes().to_vec()].concat());
+                let random = u128::from_le_bytes(seed[..16].try_into().unwrap());
+                min_amount + random % (2 * (average - min_amount) + 1)
+            }
+        }
+    }
+
+    fn giveaway_output(giveaway_id: GiveawayId, giveaway: Giveaway) -> GiveawayOutput {
+        GiveawayOutput {
+            giveaway_id,
+            sender_account_id: giveaway.sender_account_id,
+            token_id: giveaway.token_id,
+            amount: giveaway.amount.into(),
+            remaining: giveaway.remaining.into(),
+            slots: giveaway.slots,
+            claimed: giveaway.claimed_by.len() as u32,
+            split: giveaway.split,
+            chat_id: giveaway.chat_id,
+            expires_at: giveaway.expires_at.map(|t| t.into()),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::*;
+
+/* IDENTITY PROVIDERS, contracts which verify the contacts of each category */
+#[near_bindgen]
+impl NearTips {
+    // provider implements get_account_for_contact and get_contacts of the auth contract
+    pub fn set_identity_provider(&mut self, category: ContactCategories, provider_account_id: ValidAccountId) {
+        self.assert_master_account_id();
+        self.identity_providers.insert(&category, &provider_account_id.into());
+    }
+
+    // contacts of the category are verified by the auth contract again
+    pub fn remove_identity_provider(&mut self, category: ContactCategories) {
+        self.assert_master_account_id();
+        assert!(self.identity_providers.remove(&category).is_some(), "Identity provider not found");
+    }
+
+    pub fn get_identity_provider(&self, category: ContactCategories) -> AccountId {
+        self.get_identity_provider_for(&category)
+    }
+
+    // categories with a dedicated provider, others use the auth contract
+    pub fn get_identity_providers(&self) -> Vec<(ContactCategories, AccountId)> {
+        self.identity_providers.to_vec()
+    }
+}
+
+impl NearTips {
+    pub(crate) fn get_identity_provider_for(&self, category: &ContactCategories) -> AccountId {
+        self.identity_providers.get(category).unwrap_or_else(|| self.auth_account_id.clone())
+    }
+}
@@ -1,8 +1,12 @@
 use crate::*;
 
-uint::construct_uint! {
-    pub struct U256(4);
+#[allow(clippy::manual_div_ceil, clippy::assign_op_pattern)]
+mod uint_types {
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
 }
+pub use uint_types::U256;
 
 #[near_bindgen]
 impl NearTips {
@@ -36,6 +40,8 @@ impl NearTips {
         assert!(amount <= sender_deposit, "Not enough tokens to tip (Deposit: {}. Requested: {})", sender_deposit, amount);
 
         self.deposits.insert(&key, &(sender_deposit - amount));
+        self.update_account_tokens(&key);
+        self.update_liabilities(&key.token_account_id, |l| l.deposits = l.deposits.saturating_sub(amount));
     }
 
     pub(crate) fn increase_deposit(&mut self,
@@ -49,18 +55,38 @@ impl NearTips {
 
         let sender_deposit: Balance = self.deposits.get(&key).unwrap_or(0);
 
+        self.deposit_holders.insert(&key.account_id);
         self.deposits.insert(&key, &(sender_deposit + amount));
+        self.update_account_tokens(&key);
+        self.update_liabilities(&key.token_account_id, |l| l.deposits += amount);
     }
 
-    pub(crate) fn set_deposit_to_zero(&mut self,
-                                      account_id: AccountId,
-                                      token_account_id: TokenAccountId) {
-        let key = TokenByNearAccount {
-            account_id,
-            token_account_id,
-        };
+    // tokens of the account are tracked apart from token_configs, removed tokens stay withdrawable
+    pub(crate) fn update_account_tokens(&mut self, key: &TokenByNearAccount) {
+        let mut tokens = self.account_tokens.get(&key.account_id).unwrap_or_default();
+        let holds_token = self.deposits.get(key).unwrap_or(0) > 0 || self.locked_balances.contains_key(key);
+        let tracked = tokens.contains(&key.token_account_id);
+        if holds_token == tracked {
+            return;
+        }
 
-        self.deposits.insert(&key, &0);
+        if holds_token {
+            tokens.push(key.token_account_id.clone());
+        } else {
+            tokens.retain(|token_id| token_id != &key.token_account_id);
+        }
+        if tokens.is_empty() {
+            self.account_tokens.remove(&key.account_id);
+        } else {
+            self.account_tokens.insert(&key.account_id, &tokens);
+        }
+    }
+
+    pub(crate) fn has_positive_deposit(&self, account_id: &AccountId) -> bool {
+        self.account_tokens.get(account_id)
+            .unwrap_or_default()
+            .iter()
+            .any(|token_id| self.get_deposit_for_account_id_and_token_id(account_id, token_id) > 0)
     }
 
     pub(crate) fn increase_balance(&mut self,
@@ -73,7 +99,42 @@ impl NearTips {
         };
         let balance = self.telegram_tips.get(&key).unwrap_or(0);
 
+        self.add_telegram_account_to_index(telegram_account, &key.token_account_id);
         self.telegram_tips.insert(&key, &(balance + amount));
+        self.update_liabilities(&key.token_account_id, |l| l.telegram_balances += amount);
+    }
+
+    // withdrawn amount is claimed from the expiring tips, claimed parts are restored if the withdrawal fails
+    pub(crate) fn decrease_balance(&mut self,
+                                   telegram_account: TelegramAccountId,
+                                   token_account_id: TokenAccountId,
+                                   amount: Balance) -> Vec<ExpiringTipOutput> {
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id,
+        };
+        let claimed_tips = self.claim_expiring_tips(&key, amount);
+        self.debit_balance(telegram_account, key.token_account_id, amount);
+        claimed_tips.into_iter().map(ExpiringTipOutput::from).collect()
+    }
+
+    pub(crate) fn debit_balance(&mut self,
+                                telegram_account: TelegramAccountId,
+                                token_account_id: TokenAccountId,
+                                amount: Balance) {
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id,
+        };
+        let balance = self.telegram_tips.get(&key).unwrap_or(0);
+
+        assert!(amount <= balance, "Not enough tokens to withdraw (Balance: {}. Requested: {})", balance, amount);
+
+        if amount == balance {
+            self.remove_telegram_account_from_index(telegram_account, &key.token_account_id);
+        }
+        self.telegram_tips.insert(&key, &(balance - amount));
+        self.update_liabilities(&key.token_account_id, |l| l.telegram_balances = l.telegram_balances.saturating_sub(amount));
     }
 
     pub(crate) fn set_balance_to_zero(&mut self,
@@ -83,14 +144,21 @@ impl NearTips {
             telegram_account,
             token_account_id,
         };
+        let balance = self.telegram_tips.get(&key).unwrap_or(0);
+
+        self.remove_telegram_account_from_index(telegram_account, &key.token_account_id);
+        self.expiring_tips.remove(&key);
         self.telegram_tips.insert(&key, &0);
+        self.update_liabilities(&key.token_account_id, |l| l.telegram_balances = l.telegram_balances.saturating_sub(balance));
     }
 
 
-    pub(crate) fn get_contact_owner(&self, contact: Contact, contract_address: AccountId) -> Promise {
+    // asks the identity provider of the contact category
+    pub(crate) fn get_contact_owner(&self, contact: Contact) -> Promise {
+        let provider_account_id = self.get_identity_provider_for(&contact.category);
         auth::get_account_for_contact(
             contact,
-            &contract_address,
+            &provider_account_id,
             NO_DEPOSIT,
             BASE_GAS)
     }
@@ -115,7 +183,7 @@ impl NearTips {
 
     pub(crate) fn assert_check_whitelisted_token(&self, token_id: &Option<TokenAccountId>) {
         if let Some(token_id) = token_id {
-            assert!(self.whitelisted_tokens.contains(&token_id), "Token wasn't whitelisted");
+            assert!(self.whitelisted_tokens.contains(token_id), "Token wasn't whitelisted");
         }
     }
 }
\ No newline at end of file
@@ -1,24 +1,52 @@
+#![allow(clippy::too_many_arguments)]
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{wee_alloc, env, near_bindgen, AccountId, Balance, Promise, Gas, ext_contract, PromiseResult, PromiseOrValue, PanicOnDefault, BorshStorageKey,
-               log, assert_one_yocto};
-use near_sdk::json_types::{ValidAccountId, U128};
-use near_sdk::collections::{LookupSet, LookupMap};
+               Duration, log, assert_one_yocto};
+use near_sdk::json_types::{ValidAccountId, U128, U64, WrappedTimestamp};
+use near_sdk::collections::{LookupSet, LookupMap, UnorderedMap, UnorderedSet, Vector};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use near_contract_standards::fungible_token::core_impl::ext_fungible_token;
 
 use crate::internal::*;
-pub use crate::generic_tips::*;
-pub use crate::auth_tips::*;
+use crate::storage::*;
+pub use crate::events::*;
+pub use crate::tokens::*;
 pub use crate::tiptoken::*;
-pub use crate::migration::*;
+pub use crate::solvency::*;
+pub use crate::transfer_actions::*;
+pub use crate::giveaways::*;
+pub use crate::expiring_tips::*;
+pub use crate::tip_history::*;
+pub use crate::subscriptions::*;
+pub use crate::generic_tips::*;
+pub use crate::reservations::*;
+pub use crate::contact_owners::*;
+pub use crate::auth_tips::AuthFailureReason;
 
 mod internal;
 mod auth_tips;
 mod generic_tips;
 mod tiptoken;
 mod migration;
+mod events;
+mod tokens;
+mod commissions;
+mod storage;
+mod enumeration;
+mod solvency;
+mod transfer_actions;
+mod giveaways;
+mod expiring_tips;
+mod tip_history;
+mod subscriptions;
+mod reservations;
+mod identity_providers;
+mod contact_owners;
+#[cfg(test)]
+mod tests;
 
 // TelegramAccountId may potentially overflow the u64 limit
 pub type TelegramAccountId = u64;
@@ -54,7 +82,11 @@ const BASE_GAS: Gas = 25_000_000_000_000;
 const CALLBACK_GAS: Gas = 25_000_000_000_000;
 const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
 const GAS_FOR_AFTER_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_FT_BALANCE_OF: Gas = 5_000_000_000_000;
+const GAS_FOR_AFTER_FT_BALANCE_OF: Gas = 10_000_000_000_000;
 const NO_DEPOSIT: Balance = 0;
+// recipients of a single send_tips_to_telegram call, keeps gas and log size in limits
+const MAX_TIPS_IN_BATCH: usize = 25;
 const ONE_YOCTO: Balance = 1;
 const NEAR: &str = "near";
 
@@ -95,8 +127,9 @@ pub struct NearTips {
     telegram_tips_v1: HashMap<String, Balance>,
 
     //total_chat_points: RewardPoint,
-    // not used, TODO for each token?
-    chat_settings: LookupMap<TelegramChatId, ChatSettings>,
+    // settings saved before the fee split, read as a fallback and removed once the chat is updated
+    chat_settings_v1: LookupMap<TelegramChatId, ChatSettingsV1>,
+    // protocol treasury share of the chat fees
     treasure: LookupMap<TokenAccountId, Balance>,
     chat_points: LookupMap<TelegramChatId, RewardPoint>,
 
@@ -107,6 +140,57 @@ pub struct NearTips {
     tiptoken_account_id: TokenAccountId,
     total_tiptokens: Balance,
     tiptokens_burned: Balance,
+
+    token_configs: UnorderedMap<TokenAccountId, TokenConfig>,
+    commissions: LookupMap<TokenAccountId, Balance>,
+    storage_accounts: LookupMap<AccountId, AccountStorage>,
+
+    chat_settings: LookupMap<TelegramChatId, ChatSettings>,
+    // chat admin and sender shares of the chat fees, backs reward points and TipTokens
+    reward_pool: LookupMap<TokenAccountId, Balance>,
+    fee_stats: LookupMap<TokenAccountId, FeeStats>,
+    tiptoken_rates: LookupMap<TokenAccountId, Vec<TiptokenRate>>,
+
+    // enumerable indices of the ledgers above
+    deposit_holders: UnorderedSet<AccountId>,
+    // tokens with a positive deposit or a locked balance of the account
+    account_tokens: LookupMap<AccountId, Vec<TokenAccountId>>,
+    // accounts with generic tips, legacy tips are added to the liabilities only once
+    tip_holders: LookupSet<AccountId>,
+    telegram_accounts_per_token: LookupMap<TokenAccountId, UnorderedSet<TelegramAccountId>>,
+    chats: UnorderedSet<TelegramChatId>,
+
+    liabilities: LookupMap<TokenAccountId, Liabilities>,
+
+    giveaways: UnorderedMap<GiveawayId, Giveaway>,
+    chat_giveaways: LookupMap<TelegramChatId, UnorderedSet<GiveawayId>>,
+    next_giveaway_id: GiveawayId,
+    // number of open giveaways of the sender, checked by storage_unregister
+    open_giveaways_per_account: LookupMap<AccountId, u32>,
+
+    expiring_tips: LookupMap<TokenByTelegramAccount, Vec<ExpiringTip>>,
+
+    tip_history: LookupMap<TipRecordId, TipRecord>,
+    tip_history_range: TipHistoryRange,
+    tip_history_limit: u64,
+    tip_history_indices: LookupMap<TipHistoryIndex, TipHistoryRange>,
+    tip_history_index_entries: LookupMap<TipHistoryIndexEntry, TipRecordId>,
+
+    subscriptions: UnorderedMap<SubscriptionId, Subscription>,
+    subscriptions_per_account: LookupMap<AccountId, UnorderedSet<SubscriptionId>>,
+    next_subscription_id: SubscriptionId,
+    // position in subscriptions where the next execute_due_subscriptions starts
+    subscriptions_cursor: u64,
+
+    reservations: LookupMap<ReservationId, Reservation>,
+    next_reservation_id: ReservationId,
+    locked_balances: LookupMap<TokenByNearAccount, Balance>,
+
+    // auth_account_id is used for categories without a provider
+    identity_providers: UnorderedMap<ContactCategories, AccountId>,
+
+    contact_owners: LookupMap<Contact, CachedContactOwner>,
+    contact_owner_ttl: Duration,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
@@ -155,12 +239,72 @@ pub struct TelegramUserInChat {
     pub chat_id: TelegramChatId, // chat_id is negative, so don't forget * -1
 }
 
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChatSettingsV1 {
+    pub admin_account_id: AccountId,
+    pub treasure_fee_numerator: TreasureFeeNumerator,
+    pub track_chat_points: bool,
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct ChatSettings {
     pub admin_account_id: AccountId,
     pub treasure_fee_numerator: TreasureFeeNumerator,
     pub track_chat_points: bool,
+    pub fee_split: FeeSplit,
+}
+
+impl From<ChatSettingsV1> for ChatSettings {
+    fn from(settings: ChatSettingsV1) -> Self {
+        ChatSettings {
+            admin_account_id: settings.admin_account_id,
+            treasure_fee_numerator: settings.treasure_fee_numerator,
+            track_chat_points: settings.track_chat_points,
+            fee_split: FeeSplit::default(),
+        }
+    }
+}
+
+// shares of the chat fee in basis points, sum is 10000
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSplit {
+    pub chat_admin: u16,
+    pub sender: u16,
+    pub treasury: u16,
+}
+
+impl Default for FeeSplit {
+    fn default() -> Self {
+        FeeSplit {
+            chat_admin: 4000,
+            sender: 4000,
+            treasury: 2000,
+        }
+    }
+}
+
+// totals of the distributed chat fees for the token
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct FeeStats {
+    pub chat_admin: Balance,
+    pub sender: Balance,
+    pub treasury: Balance,
+    pub redeemed: Balance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeBreakdown {
+    pub token_id: TokenAccountId,
+    pub chat_admin_collected: WrappedBalance,
+    pub sender_collected: WrappedBalance,
+    pub treasury_collected: WrappedBalance,
+    pub redeemed: WrappedBalance,
+    pub reward_pool: WrappedBalance,
+    pub treasury: WrappedBalance,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -193,18 +337,20 @@ pub enum ContactCategories {
 pub trait ExtNearTips {
     fn on_withdraw(&mut self, predecessor_account_id: AccountId, deposit: Balance, token_id: Option<TokenAccountId>) -> bool;
     fn on_withdraw_linkdrop(&mut self, amount: Balance, telegram_account: TelegramAccountId, public_key: String) -> bool;
-    fn on_get_contacts_on_withdraw_tip_for_current_account(&mut self, #[callback] contacts: Option<Vec<Contact>>, recipient_account_id: AccountId, recipient_contact: Contact, balance: Balance) -> bool;
-    fn on_get_contact_owner_on_tip_contact_to_deposit(&mut self, #[callback] account: Option<AccountId>, sender_account_id: AccountId, contact: Contact, amount: Balance, token_id: Option<TokenAccountId>) -> bool;
-    fn on_get_contact_owner_on_tip_contact_with_attached_tokens(&mut self, #[callback] account: Option<AccountId>, sender_account_id: AccountId, contact: Contact, deposit: Balance) -> bool;
-    fn on_get_contact_owner_on_withdraw_tip_for_undefined_account(&mut self, #[callback] account: Option<AccountId>, recipient_account_id: AccountId, recipient_contact: Contact, balance_to_withdraw: Balance) -> bool;
-    fn on_withdraw_tip(&mut self, account_id: AccountId, contact: Contact, balance: Balance) -> bool;
-    fn on_get_contact_owner_on_withdraw_from_telegram_with_auth(&mut self, #[callback] account: Option<AccountId>, recipient_account_id: AccountId, contact: Contact, token_id: Option<TokenAccountId>) -> bool;
-    fn on_get_contact_owner_on_send_tip_to_telegram_with_auth(&mut self, #[callback] account: Option<AccountId>, sender_account_id: AccountId, tip_amount: Balance, telegram_account: TelegramAccountId, chat_id: Option<TelegramChatId>, token_id: Option<TokenAccountId>) -> bool;
-
-    fn after_ft_transfer_balance(&mut self, telegram_account: TelegramAccountId, amount: WrappedBalance, token_account_id: TokenAccountId) -> bool;
+    fn on_get_contacts_on_withdraw_tip_for_current_account(&mut self, reservation_id: ReservationId, recipient_contact: Contact) -> bool;
+    fn on_get_contact_owner_on_tip_contact_to_deposit(&mut self, reservation_id: ReservationId, contact: Contact) -> bool;
+    fn on_get_contact_owner_on_tip_contact_with_attached_tokens(&mut self, sender_account_id: AccountId, contact: Contact, deposit: Balance) -> bool;
+    fn on_get_contact_owner_on_withdraw_tip_for_undefined_account(&mut self, reservation_id: ReservationId, recipient_account_id: AccountId, recipient_contact: Contact) -> bool;
+    fn on_withdraw_tip(&mut self, reservation_id: ReservationId, recipient_account_id: AccountId) -> bool;
+    fn on_get_contact_owner_on_withdraw_from_telegram_with_auth(&mut self, recipient_account_id: AccountId, contact: Contact, token_id: Option<TokenAccountId>, amount: Option<WrappedBalance>) -> bool;
+    fn on_get_contact_owner_on_send_tip_to_telegram_with_auth(&mut self, reservation_id: ReservationId, telegram_account: TelegramAccountId, chat_id: Option<TelegramChatId>) -> bool;
+
+    fn after_ft_transfer_balance(&mut self, telegram_account: TelegramAccountId, amount: WrappedBalance, token_account_id: TokenAccountId, commission: Option<WrappedBalance>, expiring_tips: Option<Vec<ExpiringTipOutput>>) -> bool;
     fn after_ft_transfer_deposit(&mut self, account_id: AccountId, amount: WrappedBalance, token_account_id: TokenAccountId) -> bool;
     fn after_ft_transfer_claim_by_chat(&mut self, chat_id: TelegramChatId, amount_claimed: WrappedBalance, token_account_id: TokenAccountId) -> bool;
-    fn after_ft_transfer_claim_tiptokens(&mut self, account_id: AccountId, amount_redeemed: WrappedBalance, token_account_id: TokenAccountId) -> bool;
+    fn after_ft_transfer_commission(&mut self, amount: WrappedBalance, token_account_id: TokenAccountId) -> bool;
+    fn on_ft_balance_of_check_solvency(&mut self, token_id: TokenAccountId) -> bool;
+    fn after_ft_transfer_claim_tiptokens(&mut self, account_id: AccountId, amount_redeemed: WrappedBalance, token_account_id: TokenAccountId, rate_version: Option<u32>) -> bool;
 }
 
 fn is_promise_success() -> bool {
@@ -213,10 +359,7 @@ fn is_promise_success() -> bool {
         1,
         "Contract expected a result on the callback"
     );
-    match env::promise_result(0) {
-        PromiseResult::Successful(_) => true,
-        _ => false,
-    }
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
 }
 
 /// Helper structure to for keys of the persistent collections.
@@ -235,6 +378,35 @@ pub enum StorageKey {
     TreasureLookupMap,
     ChatTokensLookupMap,
     UserTokensToClaimLookupMap,
+    TokenConfigsUnorderedMap,
+    CommissionsLookupMap,
+    StorageAccountsLookupMap,
+    ChatSettingsV2LookupMap,
+    RewardPoolLookupMap,
+    FeeStatsLookupMap,
+    TiptokenRatesLookupMap,
+    DepositHoldersUnorderedSet,
+    TelegramAccountsPerTokenLookupMap,
+    TelegramAccountsPerToken { token_id_hash: Vec<u8> },
+    ChatsUnorderedSet,
+    LiabilitiesLookupMap,
+    GiveawaysUnorderedMap,
+    ChatGiveawaysLookupMap,
+    ChatGiveaways { chat_id: TelegramChatId },
+    ExpiringTipsLookupMap,
+    TipHistoryLookupMap,
+    TipHistoryIndicesLookupMap,
+    TipHistoryIndexEntriesLookupMap,
+    SubscriptionsUnorderedMap,
+    SubscriptionsPerAccountLookupMap,
+    SubscriptionsPerAccount { account_id_hash: Vec<u8> },
+    ReservationsLookupMap,
+    LockedBalancesLookupMap,
+    IdentityProvidersUnorderedMap,
+    ContactOwnersLookupMap,
+    OpenGiveawaysPerAccountLookupMap,
+    AccountTokensLookupMap,
+    TipHoldersLookupSet,
 }
 
 #[near_bindgen]
@@ -258,7 +430,7 @@ impl NearTips {
             generic_tips_available: false,
             telegram_tips_v1: HashMap::new(),
             //total_chat_points: 0,
-            chat_settings: LookupMap::new(StorageKey::ChatSettingsLookupMap),
+            chat_settings_v1: LookupMap::new(StorageKey::ChatSettingsLookupMap),
             treasure: LookupMap::new(StorageKey::TreasureLookupMap),
 
             chat_points: LookupMap::new(StorageKey::ChatPointsLookupMap), // fix storage
@@ -268,7 +440,51 @@ impl NearTips {
             auth_account_id: auth_account_id.into(),
             tiptoken_account_id: tiptoken_account_id.into(),
             total_tiptokens: 0,
-            tiptokens_burned: 0
+            tiptokens_burned: 0,
+
+            token_configs: UnorderedMap::new(StorageKey::TokenConfigsUnorderedMap),
+            commissions: LookupMap::new(StorageKey::CommissionsLookupMap),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccountsLookupMap),
+
+            chat_settings: LookupMap::new(StorageKey::ChatSettingsV2LookupMap),
+            reward_pool: LookupMap::new(StorageKey::RewardPoolLookupMap),
+            fee_stats: LookupMap::new(StorageKey::FeeStatsLookupMap),
+            tiptoken_rates: LookupMap::new(StorageKey::TiptokenRatesLookupMap),
+
+            deposit_holders: UnorderedSet::new(StorageKey::DepositHoldersUnorderedSet),
+            account_tokens: LookupMap::new(StorageKey::AccountTokensLookupMap),
+            tip_holders: LookupSet::new(StorageKey::TipHoldersLookupSet),
+            telegram_accounts_per_token: LookupMap::new(StorageKey::TelegramAccountsPerTokenLookupMap),
+            chats: UnorderedSet::new(StorageKey::ChatsUnorderedSet),
+
+            liabilities: LookupMap::new(StorageKey::LiabilitiesLookupMap),
+
+            giveaways: UnorderedMap::new(StorageKey::GiveawaysUnorderedMap),
+            chat_giveaways: LookupMap::new(StorageKey::ChatGiveawaysLookupMap),
+            next_giveaway_id: 0,
+            open_giveaways_per_account: LookupMap::new(StorageKey::OpenGiveawaysPerAccountLookupMap),
+
+            expiring_tips: LookupMap::new(StorageKey::ExpiringTipsLookupMap),
+
+            tip_history: LookupMap::new(StorageKey::TipHistoryLookupMap),
+            tip_history_range: TipHistoryRange::default(),
+            tip_history_limit: DEFAULT_TIP_HISTORY_LIMIT,
+            tip_history_indices: LookupMap::new(StorageKey::TipHistoryIndicesLookupMap),
+            tip_history_index_entries: LookupMap::new(StorageKey::TipHistoryIndexEntriesLookupMap),
+
+            subscriptions: UnorderedMap::new(StorageKey::SubscriptionsUnorderedMap),
+            subscriptions_per_account: LookupMap::new(StorageKey::SubscriptionsPerAccountLookupMap),
+            next_subscription_id: 0,
+            subscriptions_cursor: 0,
+
+            reservations: LookupMap::new(StorageKey::ReservationsLookupMap),
+            next_reservation_id: 0,
+            locked_balances: LookupMap::new(StorageKey::LockedBalancesLookupMap),
+
+            identity_providers: UnorderedMap::new(StorageKey::IdentityProvidersUnorderedMap),
+
+            contact_owners: LookupMap::new(StorageKey::ContactOwnersLookupMap),
+            contact_owner_ttl: DEFAULT_CONTACT_OWNER_TTL,
         }
     }
 
@@ -282,76 +498,99 @@ impl NearTips {
         );
         let attached_deposit: Balance = env::attached_deposit();
 
+        // storage of the new deposit is paid by the receiver, so it has to be registered
+        let initial_storage_usage = env::storage_usage();
         self.deposit_amount_to_account(account_id_prepared.as_ref(), attached_deposit, Some(NEAR.to_string()));
+        self.charge_storage(account_id_prepared.as_ref(), initial_storage_usage);
     }
 
     pub(crate) fn deposit_amount_to_account(&mut self, account_id: &AccountId, amount: Balance, token_id: Option<TokenAccountId>) {
         self.assert_withdraw_available();
+        self.assert_token_available(&token_id);
         let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
 
-        if token_id_unwrapped == NEAR {
-            assert!(amount >= MIN_DEPOSIT_NEAR, "Minimum deposit is 0.1");
-        } else {
-            assert!(amount >= MIN_DEPOSIT_FT, "Minimum deposit is 0.1");
-        }
-
-        self.assert_check_whitelisted_token(&token_id);
+        let min_deposit = self.get_token_config_or_default(&token_id_unwrapped).min_deposit.0;
+        assert!(amount >= min_deposit, "Minimum deposit is {}", min_deposit);
 
         self.increase_deposit(account_id.clone(), token_id_unwrapped.clone(), amount);
 
-        env::log(format!("@{} deposited {} of {:?}", account_id, amount, token_id_unwrapped).as_bytes());
+        EventKind::Deposit(vec![DepositEvent {
+            account_id: account_id.clone(),
+            token_id: token_id_unwrapped,
+            amount: amount.into(),
+        }]).emit();
     }
 
-    #[allow(unused_variables)]
+    // msg is a TransferAction, rejected transfers are refunded instead of panicking
     pub fn ft_on_transfer(
         &mut self,
         sender_id: ValidAccountId,
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
-        let token_account_id = Some(env::predecessor_account_id());
-        self.assert_check_whitelisted_token(&token_account_id);
-
-        self.deposit_amount_to_account(sender_id.as_ref(), amount.0, token_account_id);
-
-        PromiseOrValue::Value(0.into())
+        let token_account_id = env::predecessor_account_id();
+        let sender_account_id: AccountId = sender_id.into();
+
+        let reject_reason = match NearTips::parse_transfer_action(&msg) {
+            Some(action) => match self.get_transfer_reject_reason(&sender_account_id, &token_account_id, amount.0, &action) {
+                Some(reason) => reason,
+                None => {
+                    self.execute_transfer_action(sender_account_id, token_account_id, amount.0, action);
+                    return PromiseOrValue::Value(0.into());
+                }
+            },
+            None => TransferRejectReason::InvalidMessage,
+        };
+
+        EventKind::TransferRejected(vec![TransferRejectedEvent {
+            sender_account_id,
+            token_id: token_account_id,
+            amount,
+            reason: reject_reason,
+        }]).emit();
+
+        PromiseOrValue::Value(amount)
     }
 
     pub fn transfer_tips_to_deposit(&mut self, telegram_account: TelegramAccountId,
                                     account_id: ValidAccountId,
                                     token_id: Option<TokenAccountId>) {
         self.assert_withdraw_available();
-        self.assert_check_whitelisted_token(&token_id);
         self.assert_master_account_id();
 
         let balance: Balance = self.get_balance(telegram_account, token_id.clone()).0;
 
-        let amount: Balance;
-
         let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
-        if token_id_unwrapped == NEAR { // TODO commission for DAI withdrawals?
-            assert!(balance > WITHDRAW_COMMISSION, "Not enough tokens to pay transfer commission");
-            amount = balance - WITHDRAW_COMMISSION;
-            Promise::new(self.master_account_id.clone()).transfer(WITHDRAW_COMMISSION);
-        } else {
-            amount = balance;
-        }
+        let commission: Balance = self.get_withdraw_commission(&token_id_unwrapped, balance);
+        assert!(balance > commission, "Not enough tokens to pay transfer commission");
+        let amount: Balance = balance - commission;
 
+        self.increase_commission(&token_id_unwrapped, commission);
+
+        let initial_storage_usage = env::storage_usage();
         self.increase_deposit(account_id.clone().into(), token_id_unwrapped.clone(), amount);
+        self.charge_storage(account_id.as_ref(), initial_storage_usage);
         self.set_balance_to_zero(telegram_account, token_id_unwrapped.clone());
 
-        env::log(format!("@{} transfer {} of {:?} from telegram account {}. Transfer commission: {} yNEAR",
-                         account_id, balance, token_id_unwrapped, telegram_account, WITHDRAW_COMMISSION).as_bytes());
+        EventKind::TransferToDeposit(vec![WithdrawEvent {
+            account_id: account_id.into(),
+            telegram_account: Some(telegram_account),
+            token_id: token_id_unwrapped,
+            amount: amount.into(),
+            fee: commission.into(),
+        }]).emit();
     }
 
     /* SEND TIPS */
+    // unclaimed part of the tip can be reclaimed by the sender after expires_at (in nanoseconds)
     pub fn send_tip_to_telegram(&mut self,
                                 telegram_account: TelegramAccountId,
                                 amount: WrappedBalance,
                                 chat_id: Option<TelegramChatId>,
-                                token_id: Option<TokenAccountId>) {
+                                token_id: Option<TokenAccountId>,
+                                expires_at: Option<WrappedTimestamp>) {
         let account_id = env::predecessor_account_id();
-        self.send_tip_to_telegram_from_account(account_id, telegram_account, amount, chat_id, token_id);
+        self.send_tip_to_telegram_from_account(account_id, telegram_account, amount, chat_id, token_id, expires_at);
     }
 
 
@@ -360,17 +599,191 @@ impl NearTips {
                                                     telegram_account: TelegramAccountId,
                                                     amount: WrappedBalance,
                                                     chat_id: Option<TelegramChatId>,
-                                                    token_id: Option<TokenAccountId>) {
+                                                    token_id: Option<TokenAccountId>,
+                                                    expires_at: Option<WrappedTimestamp>) {
         self.assert_tip_available();
-        self.assert_check_whitelisted_token(&token_id);
+        self.assert_token_available(&token_id);
         assert!(amount.0 > 0, "Positive amount needed");
 
         let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        self.assert_min_tip(&token_id_unwrapped, amount.0);
         let deposit = self.get_deposit_for_account_id_and_token_id(&sender_account_id, &token_id_unwrapped);
 
         assert!(amount.0 <= deposit, "Not enough tokens deposited to tip (Deposit: {}. Requested: {})", deposit, amount.0);
 
-        let mut tip_amount: Balance = amount.0;
+        // new records for the telegram account and chat rewards are paid by the sender
+        let initial_storage_usage = env::storage_usage();
+
+        let (tip_amount, treasure_fee) = self.tip_telegram_account(telegram_account, amount.0, chat_id, &token_id_unwrapped);
+        self.distribute_chat_fee(chat_id, &token_id_unwrapped, treasure_fee, &sender_account_id);
+        if let Some(expires_at) = expires_at {
+            self.add_expiring_tip(&sender_account_id, telegram_account, &token_id_unwrapped, tip_amount, expires_at.0);
+        }
+
+        self.deposits.insert( // don't use helper to avoid second deposit check
+                              &TokenByNearAccount {
+                                  account_id: sender_account_id.clone(),
+                                  token_account_id: token_id_unwrapped.clone(),
+                              },
+                              &(deposit - amount.0));
+        self.update_account_tokens(&TokenByNearAccount {
+            account_id: sender_account_id.clone(),
+            token_account_id: token_id_unwrapped.clone(),
+        });
+        self.update_liabilities(&token_id_unwrapped, |l| l.deposits = l.deposits.saturating_sub(amount.0));
+
+        self.charge_storage(&sender_account_id, initial_storage_usage);
+
+        self.record_tips(vec![TipEvent {
+            sender_account_id,
+            telegram_account: Some(telegram_account),
+            receiver_account_id: None,
+            contact: None,
+            token_id: token_id_unwrapped,
+            amount: tip_amount.into(),
+            fee: treasure_fee.into(),
+            chat_id,
+            expires_at,
+        }]);
+    }
+
+    // tip several telegram accounts from the deposit in one transaction, chat fee is distributed once
+    pub fn send_tips_to_telegram(&mut self,
+                                 recipients: Vec<(TelegramAccountId, WrappedBalance)>,
+                                 chat_id: Option<TelegramChatId>,
+                                 token_id: Option<TokenAccountId>) {
+        self.assert_tip_available();
+        self.assert_token_available(&token_id);
+        assert!(!recipients.is_empty(), "Recipients are missing");
+        assert!(recipients.len() <= MAX_TIPS_IN_BATCH, "Too many recipients, max {}", MAX_TIPS_IN_BATCH);
+
+        let sender_account_id = env::predecessor_account_id();
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+
+        let mut total_amount: Balance = 0;
+        for (_, amount) in &recipients {
+            assert!(amount.0 > 0, "Positive amount needed");
+            self.assert_min_tip(&token_id_unwrapped, amount.0);
+            total_amount = total_amount.checked_add(amount.0).expect("Amount overflow");
+        }
+
+        let deposit = self.get_deposit_for_account_id_and_token_id(&sender_account_id, &token_id_unwrapped);
+        assert!(total_amount <= deposit, "Not enough tokens deposited to tip (Deposit: {}. Requested: {})", deposit, total_amount);
+
+        let recipients = recipients.into_iter().map(|(telegram_account, amount)| (telegram_account, amount.0)).collect();
+        let (tips, total_fee) = self.tip_telegram_accounts(&sender_account_id, recipients, total_amount, chat_id, &token_id_unwrapped);
+
+        EventKind::BatchTip(vec![BatchTipEvent {
+            sender_account_id,
+            token_id: token_id_unwrapped,
+            recipients_count: tips.len() as u32,
+            amount: (total_amount - total_fee).into(),
+            fee: total_fee.into(),
+            chat_id,
+        }]).emit();
+        self.record_tips(tips);
+    }
+
+    // split total_amount evenly among active members of the chat, supplied by the bot.
+    // the first `count` eligible members are tipped, the remainder stays in the sender's deposit
+    pub fn rain_tip(&mut self,
+                    chat_id: TelegramChatId,
+                    total_amount: WrappedBalance,
+                    token_id: Option<TokenAccountId>,
+                    count: u32,
+                    telegram_accounts: Vec<TelegramAccountId>) {
+        self.assert_tip_available();
+        self.assert_token_available(&token_id);
+        assert!(count > 0, "Positive count needed");
+        assert!(count as usize <= MAX_TIPS_IN_BATCH, "Too many recipients, max {}", MAX_TIPS_IN_BATCH);
+
+        let sender_account_id = env::predecessor_account_id();
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+
+        let amount_per_recipient: Balance = total_amount.0 / count as Balance;
+        assert!(amount_per_recipient > 0, "Positive amount needed");
+        self.assert_min_tip(&token_id_unwrapped, amount_per_recipient);
+
+        let mut members: Vec<TelegramAccountId> = vec![];
+        for telegram_account in telegram_accounts {
+            if members.len() == count as usize {
+                break;
+            }
+            let user_in_chat = TelegramUserInChat {
+                telegram_id: telegram_account,
+                chat_id,
+            };
+            if !members.contains(&telegram_account) && self.telegram_users_in_chats.contains(&user_in_chat) {
+                members.push(telegram_account);
+            }
+        }
+        assert_eq!(members.len(), count as usize, "Not enough active chat members (Found: {}. Requested: {})", members.len(), count);
+
+        let rain_amount: Balance = amount_per_recipient * count as Balance;
+        let deposit = self.get_deposit_for_account_id_and_token_id(&sender_account_id, &token_id_unwrapped);
+        assert!(rain_amount <= deposit, "Not enough tokens deposited to tip (Deposit: {}. Requested: {})", deposit, rain_amount);
+
+        let recipients = members.into_iter().map(|telegram_account| (telegram_account, amount_per_recipient)).collect();
+        let (tips, total_fee) = self.tip_telegram_accounts(&sender_account_id, recipients, rain_amount, Some(chat_id), &token_id_unwrapped);
+
+        EventKind::RainTip(vec![RainTipEvent {
+            sender_account_id,
+            token_id: token_id_unwrapped,
+            chat_id,
+            recipients_count: count,
+            amount: (rain_amount - total_fee).into(),
+            fee: total_fee.into(),
+            remainder: (total_amount.0 - rain_amount).into(),
+        }]).emit();
+        self.record_tips(tips);
+    }
+
+    // debits the checked total from the sender's deposit once and tips every recipient.
+    // returns tip events of recipients and the summed chat fee
+    fn tip_telegram_accounts(&mut self,
+                             sender_account_id: &AccountId,
+                             recipients: Vec<(TelegramAccountId, Balance)>,
+                             total_amount: Balance,
+                             chat_id: Option<TelegramChatId>,
+                             token_id: &TokenAccountId) -> (Vec<TipEvent>, Balance) {
+        let initial_storage_usage = env::storage_usage();
+        self.decrease_deposit(sender_account_id.clone(), token_id.clone(), total_amount);
+
+        let mut total_fee: Balance = 0;
+        let mut tips: Vec<TipEvent> = vec![];
+        for (telegram_account, amount) in recipients {
+            let (tip_amount, treasure_fee) = self.tip_telegram_account(telegram_account, amount, chat_id, token_id);
+            total_fee += treasure_fee;
+
+            tips.push(TipEvent {
+                sender_account_id: sender_account_id.clone(),
+                telegram_account: Some(telegram_account),
+                receiver_account_id: None,
+                contact: None,
+                token_id: token_id.clone(),
+                amount: tip_amount.into(),
+                fee: treasure_fee.into(),
+                chat_id,
+                expires_at: None,
+            });
+        }
+
+        self.distribute_chat_fee(chat_id, token_id, total_fee, sender_account_id);
+        self.charge_storage(sender_account_id, initial_storage_usage);
+
+        (tips, total_fee)
+    }
+
+    // applies chat points and credits the telegram balance minus the chat fee,
+    // the sender's deposit is debited and the fee is distributed by the caller.
+    // returns the tip amount after the fee and the fee
+    pub(crate) fn tip_telegram_account(&mut self,
+                                       telegram_account: TelegramAccountId,
+                                       amount: Balance,
+                                       chat_id: Option<TelegramChatId>,
+                                       token_id: &TokenAccountId) -> (Balance, Balance) {
+        let mut tip_amount: Balance = amount;
+        let mut treasure_fee: Balance = 0;
 
         // treasure fee & points
         if let Some(chat_id_unwrapped) = chat_id {
@@ -381,103 +794,109 @@ impl NearTips {
                     let treasure_fee_numerator = chat_settings_unwrapped.treasure_fee_numerator;
                     NearTips::assert_valid_treasure_fee_numerator(treasure_fee_numerator);
 
-                    if chat_settings_unwrapped.track_chat_points {
-                        if amount.0 > MIN_AMOUNT_TO_REWARD_CHAT {
-                            let user_in_chat: TelegramUserInChat = TelegramUserInChat {
-                                telegram_id: telegram_account,
+                    if chat_settings_unwrapped.track_chat_points && amount > MIN_AMOUNT_TO_REWARD_CHAT {
+                        let user_in_chat: TelegramUserInChat = TelegramUserInChat {
+                            telegram_id: telegram_account,
+                            chat_id: chat_id_unwrapped,
+                        };
+
+                        if !self.telegram_users_in_chats.contains(&user_in_chat) {
+                            let chat_score: RewardPoint = self.chat_points.get(&chat_id_unwrapped).unwrap_or(0);
+                            let new_score = chat_score + 1;
+                            self.chat_points.insert(&chat_id_unwrapped, &new_score);
+                            self.telegram_users_in_chats.insert(&user_in_chat);
+
+                            EventKind::ChatPoint(vec![ChatPointEvent {
                                 chat_id: chat_id_unwrapped,
-                            };
-
-                            if !self.telegram_users_in_chats.contains(&user_in_chat) {
-                                let chat_score: RewardPoint = self.chat_points.get(&chat_id_unwrapped).unwrap_or(0);
-                                let new_score = chat_score + 1;
-                                self.chat_points.insert(&chat_id_unwrapped, &new_score);
-                                self.telegram_users_in_chats.insert(&user_in_chat);
-                                env::log(format!("Reward point for chat {} added. Total: {}", chat_id_unwrapped, new_score).as_bytes());
-                            }
+                                telegram_account,
+                                total_points: new_score.into(),
+                            }]).emit();
                         }
                     }
 
                     if treasure_fee_numerator > 0 {
-                        let treasure_fee = NearTips::get_treasure_fee(amount.0, treasure_fee_numerator);
-                        tip_amount = amount.0 - treasure_fee; // overwrite tip amount
-
-                        if treasure_fee > 0 {
-                            self.distribute_tiptokens(chat_id_unwrapped, chat_settings_unwrapped.admin_account_id, token_id_unwrapped.clone(), treasure_fee, sender_account_id.clone());
-                        }
-                        env::log(format!("@{} tipped {} of {:?} for telegram account {}.",
-                                         sender_account_id, tip_amount, token_id_unwrapped, telegram_account).as_bytes());
+                        treasure_fee = NearTips::get_treasure_fee(amount, treasure_fee_numerator);
+                        tip_amount = amount - treasure_fee; // overwrite tip amount
                     }
                 }
             }
-        } else {
-            env::log(format!("@{} tipped {} of {:?} for telegram account {}", sender_account_id, tip_amount, token_id_unwrapped, telegram_account).as_bytes());
         }
 
         // perform a tip
-        self.increase_balance(telegram_account, token_id_unwrapped.clone(), tip_amount);
+        self.increase_balance(telegram_account, token_id.clone(), tip_amount);
 
-        self.deposits.insert( // don't use helper to avoid second deposit check
-                              &TokenByNearAccount {
-                                  account_id: sender_account_id.clone(),
-                                  token_account_id: token_id_unwrapped.clone(),
-                              },
-                              &(deposit - amount.0));
+        (tip_amount, treasure_fee)
+    }
+
+    // fee of one or many tips in the chat is distributed at once
+    pub(crate) fn distribute_chat_fee(&mut self, chat_id: Option<TelegramChatId>, token_id: &TokenAccountId, treasure_fee: Balance, sender_account_id: &AccountId) {
+        if treasure_fee > 0 {
+            if let Some(chat_id_unwrapped) = chat_id {
+                if let Some(chat_settings) = self.get_chat_settings(chat_id_unwrapped) {
+                    self.distribute_tiptokens(chat_id_unwrapped, chat_settings.admin_account_id, chat_settings.fee_split, token_id.clone(), treasure_fee, sender_account_id.clone());
+                }
+            }
+        }
     }
 
     /* WITHDRAW */
 
     // centralized tips withdraw, with master_account authorisation
+    // amount includes the commission, whole balance is withdrawn if it's missing
     pub fn withdraw_from_telegram(&mut self,
                                   telegram_account: TelegramAccountId,
                                   account_id: ValidAccountId,
-                                  token_id: Option<TokenAccountId>) -> Promise { // TODO FT ft_on_transfer
+                                  token_id: Option<TokenAccountId>,
+                                  amount: Option<WrappedBalance>) -> Promise { // TODO FT ft_on_transfer
         self.assert_withdraw_available();
         self.assert_master_account_id();
 
         let balance: Balance = self.get_balance(telegram_account, token_id.clone()).0;
+        let withdraw_amount: Balance = NearTips::get_withdraw_amount(balance, amount);
 
         let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
 
-        let amount: Balance;
+        let commission: Balance = self.get_withdraw_commission(&token_id_unwrapped, withdraw_amount);
+        assert!(withdraw_amount > commission, "Not enough tokens to pay withdraw commission");
+        let amount: Balance = withdraw_amount - commission;
 
-        if token_id_unwrapped == NEAR {
-            assert!(balance > WITHDRAW_COMMISSION, "Not enough tokens to pay withdraw commission");
-            amount = balance - WITHDRAW_COMMISSION;
-            Promise::new(self.master_account_id.to_string()).transfer(WITHDRAW_COMMISSION);
-        } else {  // TODO COMMISSION IN NEAR?
-            amount = balance;
-        }
+        self.increase_commission(&token_id_unwrapped, commission);
+        let claimed_expiring_tips = self.decrease_balance(telegram_account, token_id_unwrapped.clone(), withdraw_amount);
 
-        self.set_balance_to_zero(telegram_account, token_id_unwrapped.clone());
+        EventKind::Withdraw(vec![WithdrawEvent {
+            account_id: account_id.to_string(),
+            telegram_account: Some(telegram_account),
+            token_id: token_id_unwrapped.clone(),
+            amount: amount.into(),
+            fee: commission.into(),
+        }]).emit();
 
-        env::log(format!("@{} is withdrawing {} of {:?} from telegram account {}",
-                         account_id, amount, token_id_unwrapped, telegram_account).as_bytes());
+        NearTips::transfer_tokens(account_id.into(), amount, &token_id_unwrapped)
+            .then(ext_self::after_ft_transfer_balance(
+                telegram_account,
+                amount.into(),
+                token_id_unwrapped,
+                Some(commission.into()),
+                Some(claimed_expiring_tips),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_AFTER_FT_TRANSFER,
+            ))
+    }
 
-        if token_id_unwrapped == NEAR {
-            Promise::new(account_id.into()).transfer(amount)
+    // NEAR transfers are followed by the same callbacks as ft_transfer, failed transfers are rolled back
+    pub(crate) fn transfer_tokens(account_id: AccountId, amount: Balance, token_id: &TokenAccountId) -> Promise {
+        if token_id == NEAR {
+            Promise::new(account_id).transfer(amount)
         } else {
             ext_fungible_token::ft_transfer(
-                account_id.into(),
+                account_id,
                 amount.into(),
-                Some(format!(
-                    "Claiming tips: {} of {:?} from @{}",
-                    amount,
-                    token_id_unwrapped,
-                    env::current_account_id()
-                )),
-                &token_id_unwrapped,
+                Some(format!("Claiming tips: {} of {:?} from @{}", amount, token_id, env::current_account_id())),
+                token_id,
                 ONE_YOCTO,
                 GAS_FOR_FT_TRANSFER,
             )
-                .then(ext_self::after_ft_transfer_balance(
-                    telegram_account,
-                    amount.into(),
-                    token_id_unwrapped,
-                    &env::current_account_id(),
-                    NO_DEPOSIT,
-                    GAS_FOR_AFTER_FT_TRANSFER,
-                ))
         }
     }
 
@@ -486,6 +905,8 @@ impl NearTips {
         telegram_account: TelegramAccountId,
         amount: WrappedBalance,
         token_account_id: TokenAccountId,
+        commission: Option<WrappedBalance>,
+        expiring_tips: Option<Vec<ExpiringTipOutput>>,
     ) -> bool {
         assert_eq!(
             env::predecessor_account_id(),
@@ -494,11 +915,30 @@ impl NearTips {
         );
 
         let promise_success = is_promise_success();
-        if !is_promise_success() {
+        if !promise_success {
+            // commission of the failed withdrawal goes back to the telegram account as well
+            let commission_refund = self.refund_commission(&token_account_id, commission.map(|c| c.0).unwrap_or(0));
+            let amount_to_recharge: Balance = amount.0 + commission_refund;
+
             log!("Token {} withdraw by telegram account {} failed. Amount to recharge: {}",
-                 token_account_id, telegram_account, amount.0);
+                 token_account_id, telegram_account, amount_to_recharge);
+
+            self.increase_balance(telegram_account, token_account_id.clone(), amount_to_recharge);
+            // missing for the withdrawals made before the expiring tips were returned to the callback
+            self.restore_expiring_tips(
+                &TokenByTelegramAccount {
+                    telegram_account,
+                    token_account_id: token_account_id.clone(),
+                },
+                expiring_tips.unwrap_or_default().into_iter().map(ExpiringTip::from).collect(),
+            );
 
-            self.increase_balance(telegram_account, token_account_id, amount.0);
+            EventKind::WithdrawFailed(vec![WithdrawFailedEvent {
+                account_id: None,
+                telegram_account: Some(telegram_account),
+                token_id: token_account_id,
+                amount: amount_to_recharge.into(),
+            }]).emit();
         }
         promise_success
     }
@@ -516,7 +956,7 @@ impl NearTips {
         );
 
         let promise_success = is_promise_success();
-        if !is_promise_success() {
+        if !promise_success {
             log!(
                 "Token {} withdraw for user {} failed. Amount to recharge: {}",
                 token_account_id,
@@ -524,48 +964,50 @@ impl NearTips {
                 amount.0
             );
 
-            self.increase_deposit(account_id, token_account_id, amount.0);
+            self.increase_deposit(account_id.clone(), token_account_id.clone(), amount.0);
+
+            EventKind::WithdrawFailed(vec![WithdrawFailedEvent {
+                account_id: Some(account_id),
+                telegram_account: None,
+                token_id: token_account_id,
+                amount,
+            }]).emit();
         }
         promise_success
     }
 
-    // withdraw from deposit
-    pub fn withdraw(&mut self, token_id: Option<TokenAccountId>) -> Promise {
+    // withdraw from deposit, whole deposit is withdrawn if amount is missing
+    pub fn withdraw(&mut self, token_id: Option<TokenAccountId>, amount: Option<WrappedBalance>) -> Promise {
         self.assert_withdraw_available();
-        self.assert_check_whitelisted_token(&token_id);
 
         let account_id = env::predecessor_account_id();
         let account_id_prepared: ValidAccountId = ValidAccountId::try_from(account_id.clone()).unwrap();
         let deposit: Balance = self.get_deposit(account_id_prepared, token_id.clone()).0;
 
         assert!(deposit > 0, "Missing deposit");
+        let deposit: Balance = NearTips::get_withdraw_amount(deposit, amount);
 
         let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
 
-        self.set_deposit_to_zero(account_id.clone(), token_id_unwrapped.clone());
+        self.decrease_deposit(account_id.clone(), token_id_unwrapped.clone(), deposit);
 
-        env::log(format!("@{} withdrew {} of {:?} from internal deposit", account_id, deposit, token_id_unwrapped).as_bytes());
+        EventKind::Withdraw(vec![WithdrawEvent {
+            account_id: account_id.clone(),
+            telegram_account: None,
+            token_id: token_id_unwrapped.clone(),
+            amount: deposit.into(),
+            fee: 0.into(),
+        }]).emit();
 
-        if token_id_unwrapped == NEAR {
-            Promise::new(account_id).transfer(deposit)
-        } else {
-            ext_fungible_token::ft_transfer(
-                account_id.clone(),
+        NearTips::transfer_tokens(account_id.clone(), deposit, &token_id_unwrapped)
+            .then(ext_self::after_ft_transfer_deposit(
+                account_id,
                 deposit.into(),
-                Some(format!("Claiming tips: {} of {:?} from @{}", deposit, token_id_unwrapped, env::current_account_id())),
-                &token_id_unwrapped,
-                ONE_YOCTO,
-                GAS_FOR_FT_TRANSFER,
-            )
-                .then(ext_self::after_ft_transfer_deposit(
-                    account_id,
-                    deposit.into(),
-                    token_id_unwrapped,
-                    &env::current_account_id(),
-                    NO_DEPOSIT,
-                    GAS_FOR_AFTER_FT_TRANSFER,
-                ))
-        }
+                token_id_unwrapped,
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_AFTER_FT_TRANSFER,
+            ))
     }
 
     pub fn withdraw_linkdrop(&mut self, public_key: String, telegram_account: TelegramAccountId) -> Promise {
@@ -573,16 +1015,20 @@ impl NearTips {
         self.assert_master_account_id();
         // TODO Linkdrop for NOT NEAR???
         let balance: Balance = NearTips::get_balance(self, telegram_account, Some(NEAR.to_string())).0;
-        assert!(balance > WITHDRAW_COMMISSION + ACCESS_KEY_ALLOWANCE, "Not enough tokens to pay for key allowance and withdraw commission");
+        let withdraw_commission = self.get_withdraw_commission(&NEAR.to_string(), balance);
+        assert!(balance > withdraw_commission + ACCESS_KEY_ALLOWANCE, "Not enough tokens to pay for key allowance and withdraw commission");
 
-        let amount = balance - WITHDRAW_COMMISSION;
+        let amount = balance - withdraw_commission;
 
         self.set_balance_to_zero(telegram_account, NEAR.to_string());
+        self.increase_commission(&NEAR.to_string(), withdraw_commission);
 
-        Promise::new(self.master_account_id.to_string()).transfer(WITHDRAW_COMMISSION);
-
-        env::log(format!("Telegram account {} withdrew {} yNEAR with linkDrop for public key {}. Withdraw commission: {} yNEAR",
-                         telegram_account, amount, public_key, WITHDRAW_COMMISSION).as_bytes());
+        EventKind::WithdrawLinkdrop(vec![WithdrawLinkdropEvent {
+            telegram_account,
+            public_key: public_key.clone(),
+            amount: amount.into(),
+            fee: withdraw_commission.into(),
+        }]).emit();
 
         linkdrop::send(public_key, &self.linkdrop_account_id, amount, BASE_GAS)
     }
@@ -638,14 +1084,16 @@ impl NearTips {
     }
 
 
-    pub fn whitelist_token(&mut self, token_id: TokenAccountId) {
-        self.assert_master_account_id();
-
-        self.whitelisted_tokens.insert(&token_id);
-    }
-
-    pub fn is_whitelisted_token(&self, token_id: TokenAccountId) -> bool {
-        self.whitelisted_tokens.contains(&token_id)
+    pub(crate) fn get_withdraw_amount(balance: Balance, amount: Option<WrappedBalance>) -> Balance {
+        let amount: Balance = match amount {
+            Some(amount) => {
+                assert!(amount.0 > 0, "Positive amount needed");
+                amount.0
+            }
+            None => balance
+        };
+        assert!(amount <= balance, "Not enough tokens to withdraw (Balance: {}. Requested: {})", balance, amount);
+        amount
     }
 
     pub(crate) fn unwrap_token_id(token_id: &Option<TokenAccountId>) -> TokenAccountId {
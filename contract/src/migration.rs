@@ -2,6 +2,198 @@ use crate::*;
 
 #[near_bindgen]
 impl NearTips {
+    #[init(ignore_state)]
+    #[allow(dead_code)]
+    pub fn migrate_state_5(token_ids: Vec<TokenAccountId>) -> Self { // token_configs, commissions, storage_accounts, fee ledgers, tiptoken_rates, indices, liabilities, giveaways, expiring_tips, tip_history, subscriptions, reservations, identity_providers, contact_owners added
+        let migration_version: u16 = 5;
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Private function");
+
+        #[derive(BorshDeserialize)]
+        struct OldContract {
+            deposits: LookupMap<TokenByNearAccount, Balance>,
+            telegram_tips: LookupMap<TokenByTelegramAccount, Balance>,
+            tips: LookupMap<AccountId, Vec<Tip>>,
+            telegram_users_in_chats: LookupSet<TelegramUserInChat>,
+            chat_points_v1: LookupMap<TokenByTelegramChat, RewardPoint>,
+            whitelisted_tokens: LookupSet<TokenAccountId>,
+            version: u16,
+            withdraw_available: bool,
+            tip_available: bool,
+            generic_tips_available: bool,
+
+            telegram_tips_v1: HashMap<String, Balance>,
+            chat_settings: LookupMap<TelegramChatId, ChatSettingsV1>,
+            treasure: LookupMap<TokenAccountId, Balance>,
+            chat_points: LookupMap<TelegramChatId, RewardPoint>,
+
+            user_tokens_to_claim: LookupMap<TokenByNearAccount, Balance>,
+            master_account_id: AccountId,
+            linkdrop_account_id: AccountId,
+            auth_account_id: AccountId,
+            tiptoken_account_id: TokenAccountId,
+            total_tiptokens: Balance,
+            tiptokens_burned: Balance,
+        }
+
+        let mut old_contract: OldContract = env::state_read().expect("Old state doesn't exist");
+
+        // whitelisted_tokens is not iterable, so existing tokens are provided to get default configs
+        let mut token_configs = UnorderedMap::new(StorageKey::TokenConfigsUnorderedMap);
+        // whole fees were kept in the treasure before the split, they back existing reward points
+        let mut reward_pool = LookupMap::new(StorageKey::RewardPoolLookupMap);
+        // deposits, telegram balances and generic tips are added by index_existing_records
+        let mut liabilities = LookupMap::new(StorageKey::LiabilitiesLookupMap);
+        for token_id in token_ids {
+            if old_contract.whitelisted_tokens.contains(&token_id) {
+                token_configs.insert(&token_id, &TokenConfig::default_for(&token_id));
+            }
+            if let Some(treasure_balance) = old_contract.treasure.remove(&token_id) {
+                reward_pool.insert(&token_id, &treasure_balance);
+                liabilities.insert(&token_id, &Liabilities {
+                    reward_pool: treasure_balance,
+                    ..Default::default()
+                });
+            }
+        }
+
+        Self {
+            deposits: old_contract.deposits,
+            telegram_tips: old_contract.telegram_tips,
+            tips: old_contract.tips,
+            telegram_users_in_chats: old_contract.telegram_users_in_chats,
+            chat_points_v1: old_contract.chat_points_v1,
+            whitelisted_tokens: old_contract.whitelisted_tokens,
+            version: migration_version,
+            withdraw_available: old_contract.withdraw_available,
+            tip_available: old_contract.tip_available,
+            generic_tips_available: old_contract.generic_tips_available,
+
+            telegram_tips_v1: old_contract.telegram_tips_v1,
+            chat_settings_v1: old_contract.chat_settings,
+            treasure: old_contract.treasure,
+            chat_points: old_contract.chat_points,
+
+            user_tokens_to_claim: old_contract.user_tokens_to_claim,
+            master_account_id: old_contract.master_account_id,
+            linkdrop_account_id: old_contract.linkdrop_account_id,
+            auth_account_id: old_contract.auth_account_id,
+            tiptoken_account_id: old_contract.tiptoken_account_id,
+            total_tiptokens: old_contract.total_tiptokens,
+            tiptokens_burned: old_contract.tiptokens_burned,
+
+            token_configs,
+            commissions: LookupMap::new(StorageKey::CommissionsLookupMap),
+            storage_accounts: LookupMap::new(StorageKey::StorageAccountsLookupMap),
+
+            chat_settings: LookupMap::new(StorageKey::ChatSettingsV2LookupMap),
+            reward_pool,
+            fee_stats: LookupMap::new(StorageKey::FeeStatsLookupMap),
+            tiptoken_rates: LookupMap::new(StorageKey::TiptokenRatesLookupMap),
+
+            // filled by index_existing_records
+            deposit_holders: UnorderedSet::new(StorageKey::DepositHoldersUnorderedSet),
+            account_tokens: LookupMap::new(StorageKey::AccountTokensLookupMap),
+            tip_holders: LookupSet::new(StorageKey::TipHoldersLookupSet),
+            telegram_accounts_per_token: LookupMap::new(StorageKey::TelegramAccountsPerTokenLookupMap),
+            chats: UnorderedSet::new(StorageKey::ChatsUnorderedSet),
+
+            liabilities,
+
+            giveaways: UnorderedMap::new(StorageKey::GiveawaysUnorderedMap),
+            chat_giveaways: LookupMap::new(StorageKey::ChatGiveawaysLookupMap),
+            next_giveaway_id: 0,
+            open_giveaways_per_account: LookupMap::new(StorageKey::OpenGiveawaysPerAccountLookupMap),
+
+            expiring_tips: LookupMap::new(StorageKey::ExpiringTipsLookupMap),
+
+            tip_history: LookupMap::new(StorageKey::TipHistoryLookupMap),
+            tip_history_range: TipHistoryRange::default(),
+            tip_history_limit: DEFAULT_TIP_HISTORY_LIMIT,
+            tip_history_indices: LookupMap::new(StorageKey::TipHistoryIndicesLookupMap),
+            tip_history_index_entries: LookupMap::new(StorageKey::TipHistoryIndexEntriesLookupMap),
+
+            subscriptions: UnorderedMap::new(StorageKey::SubscriptionsUnorderedMap),
+            subscriptions_per_account: LookupMap::new(StorageKey::SubscriptionsPerAccountLookupMap),
+            next_subscription_id: 0,
+            subscriptions_cursor: 0,
+
+            reservations: LookupMap::new(StorageKey::ReservationsLookupMap),
+            next_reservation_id: 0,
+            locked_balances: LookupMap::new(StorageKey::LockedBalancesLookupMap),
+
+            identity_providers: UnorderedMap::new(StorageKey::IdentityProvidersUnorderedMap),
+
+            contact_owners: LookupMap::new(StorageKey::ContactOwnersLookupMap),
+            contact_owner_ttl: DEFAULT_CONTACT_OWNER_TTL,
+        }
+    }
+
+    // LookupMaps are not iterable, so records created before the indices are provided by master in batches
+    // (e.g. collected from the events), only records with positive balances or existing settings are indexed,
+    // account_ids include the receivers of generic tips.
+    // Balances of newly indexed records are added to the liabilities, so it should run before new tips are enabled
+    pub fn index_existing_records(&mut self,
+                                  account_ids: Vec<AccountId>,
+                                  telegram_accounts: Vec<TelegramAccountId>,
+                                  chat_ids: Vec<TelegramChatId>,
+                                  token_ids: Vec<TokenAccountId>) {
+        self.assert_master_account_id();
+
+        for account_id in account_ids {
+            // tracked tokens of the account are already in the liabilities
+            for token_id in &token_ids {
+                let key = TokenByNearAccount {
+                    account_id: account_id.clone(),
+                    token_account_id: token_id.clone(),
+                };
+                let deposit = self.deposits.get(&key).unwrap_or(0);
+                let tracked = self.account_tokens.get(&account_id).unwrap_or_default().contains(token_id);
+                if deposit > 0 && !tracked {
+                    self.deposit_holders.insert(&account_id);
+                    self.update_account_tokens(&key);
+                    self.update_liabilities(token_id, |l| l.deposits += deposit);
+                }
+            }
+
+            // generic tips are NEAR only
+            if let Some(tips) = self.tips.get(&account_id) {
+                if self.tip_holders.insert(&account_id) {
+                    let tips_amount: Balance = tips.iter().map(|tip| tip.amount).sum();
+                    self.update_liabilities(&NEAR.to_string(), |l| l.tips += tips_amount);
+                }
+            }
+        }
+
+        for telegram_account in telegram_accounts {
+            for token_id in &token_ids {
+                let balance = self.get_balance(telegram_account, Some(token_id.clone())).0;
+                if balance > 0 && self.add_telegram_account_to_index(telegram_account, token_id) {
+                    self.update_liabilities(token_id, |l| l.telegram_balances += balance);
+                }
+            }
+        }
+
+        for chat_id in chat_ids {
+            if self.get_chat_settings(chat_id).is_some() {
+                self.chats.insert(&chat_id);
+            }
+        }
+    }
+
+    // UPDATE BEFORE DEPLOYMENT
+    /*
+    const MASTER_ACCOUNT_ID: &str = "nearup_bot.app.near";
+    const LINKDROP_ACCOUNT_ID: &str = "near";
+    const AUTH_ACCOUNT_ID: &str = "auth.name.near";
+    const TREASURE_ACCOUNT_ID: &str =
+
+
+    const MASTER_ACCOUNT_ID: &str = "zavodil.testnet";
+    const LINKDROP_ACCOUNT_ID: &str = "linkdrop.zavodil.testnet";
+    const AUTH_ACCOUNT_ID: &str = "dev-1625611642901-32969379055293";
+    */
+
+    /*
     #[init(ignore_state)]
     #[allow(dead_code)]
     pub fn migrate_state_4() -> Self { // RewardPoint type updated , total_chat_points added
@@ -55,20 +247,6 @@ impl NearTips {
         }
     }
 
-    // UPDATE BEFORE DEPLOYMENT
-    /*
-    const MASTER_ACCOUNT_ID: &str = "nearup_bot.app.near";
-    const LINKDROP_ACCOUNT_ID: &str = "near";
-    const AUTH_ACCOUNT_ID: &str = "auth.name.near";
-    const TREASURE_ACCOUNT_ID: &str =
-
-
-    const MASTER_ACCOUNT_ID: &str = "zavodil.testnet";
-    const LINKDROP_ACCOUNT_ID: &str = "linkdrop.zavodil.testnet";
-    const AUTH_ACCOUNT_ID: &str = "dev-1625611642901-32969379055293";
-    */
-
-    /*
   #[init(ignore_state)]
   #[allow(dead_code)]
   pub fn migrate_state_3_1() -> Self { // add telegram_users_in_chats, Migration to token balances / deposits
@@ -0,0 +1,149 @@
+use crate::*;
+use near_sdk::{Duration, Timestamp};
+
+/* RESERVATIONS, funds locked while a cross-contract call is in flight */
+pub type ReservationId = u64;
+
+// callbacks are executed within a few blocks, older reservations belong to failed callbacks
+const RESERVATION_TIMEOUT: Duration = 60 * 60 * 1_000_000_000; // 1 hour
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationSource {
+    Deposit,
+    // generic tip for the contact, NEAR only
+    Tip(Contact),
+}
+
+// short-lived records, storage is paid by the contract
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Reservation {
+    pub account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: Balance,
+    pub source: ReservationSource,
+    pub created_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReservationOutput {
+    pub reservation_id: ReservationId,
+    pub account_id: AccountId,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub source: ReservationSource,
+    pub created_at: WrappedTimestamp,
+}
+
+#[near_bindgen]
+impl NearTips {
+    // part of the deposit and generic tips waiting for the callbacks
+    pub fn get_locked_balance(&self, account_id: ValidAccountId, token_id: Option<TokenAccountId>) -> WrappedBalance {
+        self.locked_balances.get(
+            &TokenByNearAccount {
+                account_id: account_id.into(),
+                token_account_id: NearTips::unwrap_token_id(&token_id),
+            }).unwrap_or(0).into()
+    }
+
+    pub fn get_reservation(&self, reservation_id: ReservationId) -> Option<ReservationOutput> {
+        self.reservations.get(&reservation_id).map(|reservation| ReservationOutput {
+            reservation_id,
+            account_id: reservation.account_id,
+            token_id: reservation.token_id,
+            amount: reservation.amount.into(),
+            source: reservation.source,
+            created_at: reservation.created_at.into(),
+        })
+    }
+
+    // reservation left by a failed callback returns to its source, anyone can release it after the timeout
+    pub fn release_expired_reservation(&mut self, reservation_id: ReservationId) -> WrappedBalance {
+        let reservation = self.reservations.get(&reservation_id).expect("Reservation not found");
+        assert!(env::block_timestamp() >= reservation.created_at + RESERVATION_TIMEOUT, "Reservation is not expired yet");
+
+        self.release_reservation(reservation_id).amount.into()
+    }
+}
+
+impl NearTips {
+    pub(crate) fn reserve_deposit(&mut self, account_id: &AccountId, token_id: &TokenAccountId, amount: Balance) -> ReservationId {
+        self.decrease_deposit(account_id.clone(), token_id.clone(), amount);
+        self.add_reservation(account_id, token_id, amount, ReservationSource::Deposit)
+    }
+
+    pub(crate) fn reserve_tip(&mut self, account_id: &AccountId, contact: Contact, amount: Balance) -> ReservationId {
+        self.decrease_tip(account_id, &contact, amount);
+        self.add_reservation(account_id, &NEAR.to_string(), amount, ReservationSource::Tip(contact))
+    }
+
+    // funds have left the reservation, the caller moves them further
+    pub(crate) fn commit_reservation(&mut self, reservation_id: ReservationId) -> Reservation {
+        self.remove_reservation(reservation_id)
+    }
+
+    // funds return to the source of the reservation
+    pub(crate) fn release_reservation(&mut self, reservation_id: ReservationId) -> Reservation {
+        let reservation = self.remove_reservation(reservation_id);
+        match &reservation.source {
+            ReservationSource::Deposit => {
+                self.increase_deposit(reservation.account_id.clone(), reservation.token_id.clone(), reservation.amount);
+            }
+            ReservationSource::Tip(contact) => {
+                self.increase_tip(&reservation.account_id, contact, reservation.amount);
+            }
+        }
+        reservation
+    }
+
+    fn add_reservation(&mut self, account_id: &AccountId, token_id: &TokenAccountId, amount: Balance, source: ReservationSource) -> ReservationId {
+        let key = TokenByNearAccount {
+            account_id: account_id.clone(),
+            token_account_id: token_id.clone(),
+        };
+        let locked_balance = self.locked_balances.get(&key).unwrap_or(0);
+        self.locked_balances.insert(&key, &(locked_balance + amount));
+        self.update_account_tokens(&key);
+        self.update_liabilities(token_id, |l| l.reserved += amount);
+
+        let reservation_id = self.next_reservation_id;
+        self.next_reservation_id += 1;
+        self.reservations.insert(&reservation_id, &Reservation {
+            account_id: account_id.clone(),
+            token_id: token_id.clone(),
+            amount,
+            source,
+            created_at: env::block_timestamp(),
+        });
+        reservation_id
+    }
+
+    fn remove_reservation(&mut self, reservation_id: ReservationId) -> Reservation {
+        let reservation = self.reservations.remove(&reservation_id).expect("Reservation not found");
+        let key = TokenByNearAccount {
+            account_id: reservation.account_id.clone(),
+            token_account_id: reservation.token_id.clone(),
+        };
+        let locked_balance = self.locked_balances.get(&key).unwrap_or(0).saturating_sub(reservation.amount);
+        if locked_balance == 0 {
+            self.locked_balances.remove(&key);
+        } else {
+            self.locked_balances.insert(&key, &locked_balance);
+        }
+        self.update_account_tokens(&key);
+        self.update_liabilities(&reservation.token_id, |l| l.reserved = l.reserved.saturating_sub(reservation.amount));
+        reservation
+    }
+
+    pub(crate) fn has_locked_balance(&self, account_id: &AccountId) -> bool {
+        self.account_tokens.get(account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .any(|token_id| self.locked_balances.contains_key(&TokenByNearAccount {
+                account_id: account_id.clone(),
+                token_account_id: token_id,
+            }))
+    }
+}
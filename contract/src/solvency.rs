@@ -0,0 +1,135 @@
+use crate::*;
+
+/* SOLVENCY, running totals of everything the contract owes in each token */
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Liabilities {
+    pub deposits: Balance,
+    pub telegram_balances: Balance,
+    // generic tips, NEAR only
+    pub tips: Balance,
+    pub treasure: Balance,
+    pub reward_pool: Balance,
+    pub commissions: Balance,
+    // NEP-145 storage balances, NEAR only
+    pub storage: Balance,
+    // unclaimed amounts of open giveaways
+    pub giveaways: Balance,
+    // deposits and generic tips locked while the callbacks are pending
+    pub reserved: Balance,
+}
+
+impl Liabilities {
+    pub(crate) fn total(&self) -> Balance {
+        self.deposits + self.telegram_balances + self.tips + self.treasure + self.reward_pool + self.commissions + self.storage + self.giveaways + self.reserved
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LiabilitiesBreakdown {
+    pub token_id: TokenAccountId,
+    pub deposits: WrappedBalance,
+    pub telegram_balances: WrappedBalance,
+    pub tips: WrappedBalance,
+    pub treasure: WrappedBalance,
+    pub reward_pool: WrappedBalance,
+    pub commissions: WrappedBalance,
+    pub storage: WrappedBalance,
+    pub giveaways: WrappedBalance,
+    pub reserved: WrappedBalance,
+    pub total: WrappedBalance,
+}
+
+#[near_bindgen]
+impl NearTips {
+    pub fn get_liabilities(&self, token_id: Option<TokenAccountId>) -> LiabilitiesBreakdown {
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        let liabilities = self.liabilities.get(&token_id_unwrapped).unwrap_or_default();
+
+        LiabilitiesBreakdown {
+            token_id: token_id_unwrapped,
+            deposits: liabilities.deposits.into(),
+            telegram_balances: liabilities.telegram_balances.into(),
+            tips: liabilities.tips.into(),
+            treasure: liabilities.treasure.into(),
+            reward_pool: liabilities.reward_pool.into(),
+            commissions: liabilities.commissions.into(),
+            storage: liabilities.storage.into(),
+            giveaways: liabilities.giveaways.into(),
+            reserved: liabilities.reserved.into(),
+            total: liabilities.total().into(),
+        }
+    }
+
+    // NEAR is checked right away, fungible tokens after their ft_balance_of,
+    // pages follow get_whitelisted_tokens to stay in the gas limit
+    pub fn check_solvency(&mut self, from_index: Option<u64>, limit: Option<u64>) -> bool {
+        self.assert_master_account_id();
+
+        let mut solvent = true;
+        for (token_id, _config) in self.get_whitelisted_tokens(from_index, limit) {
+            if token_id == NEAR {
+                // attached deposit of this call isn't owed to anybody
+                let balance = env::account_balance() - env::attached_deposit();
+                solvent &= self.assert_solvency(&token_id, balance);
+            } else {
+                ext_fungible_token::ft_balance_of(
+                    env::current_account_id(),
+                    &token_id,
+                    NO_DEPOSIT,
+                    GAS_FOR_FT_BALANCE_OF,
+                )
+                    .then(ext_self::on_ft_balance_of_check_solvency(
+                        token_id,
+                        &env::current_account_id(),
+                        NO_DEPOSIT,
+                        GAS_FOR_AFTER_FT_BALANCE_OF,
+                    ));
+            }
+        }
+        solvent
+    }
+
+    #[private]
+    pub fn on_ft_balance_of_check_solvency(&mut self, token_id: TokenAccountId) -> bool {
+        match get_promise_value::<WrappedBalance>() {
+            Some(balance) => self.assert_solvency(&token_id, balance.0),
+            None => {
+                log!("Token {} balance is not available", token_id);
+                false
+            }
+        }
+    }
+
+    // emits the alert instead of panicking, so the shortfall is recorded
+    pub(crate) fn assert_solvency(&self, token_id: &TokenAccountId, balance: Balance) -> bool {
+        let liabilities = self.liabilities.get(token_id).unwrap_or_default().total();
+        if balance < liabilities {
+            EventKind::SolvencyAlert(vec![SolvencyAlertEvent {
+                token_id: token_id.clone(),
+                balance: balance.into(),
+                liabilities: liabilities.into(),
+                shortfall: (liabilities - balance).into(),
+            }]).emit();
+            false
+        } else {
+            true
+        }
+    }
+}
+
+impl NearTips {
+    pub(crate) fn update_liabilities(&mut self, token_id: &TokenAccountId, update: impl FnOnce(&mut Liabilities)) {
+        let mut liabilities = self.liabilities.get(token_id).unwrap_or_default();
+        update(&mut liabilities);
+        self.liabilities.insert(token_id, &liabilities);
+    }
+}
+
+fn get_promise_value<T: near_sdk::serde::de::DeserializeOwned>() -> Option<T> {
+    assert_eq!(env::promise_results_count(), 1, "Contract expected a result on the callback");
+    match env::promise_result(0) {
+        PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<T>(&value).ok(),
+        _ => None,
+    }
+}
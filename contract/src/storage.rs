@@ -0,0 +1,261 @@
+use crate::*;
+use near_contract_standards::storage_management::{StorageBalance, StorageBalanceBounds, StorageManagement};
+use near_sdk::StorageUsage;
+
+/* NEP-145 STORAGE MANAGEMENT */
+
+// 0.01 NEAR, covers 1 kb: storage account itself and a few deposits or tips
+const STORAGE_BALANCE_MIN_BOUND: Balance = 10_000_000_000_000_000_000_000;
+// estimates of the records created by tips and deposits, measured with 64 chars ids
+const DEPOSIT_RECORD_STORAGE: StorageUsage = 200;
+const ACCOUNT_TOKEN_STORAGE: StorageUsage = 80; // token in account_tokens of the holder
+const DEPOSIT_HOLDER_STORAGE: StorageUsage = 360; // deposit_holders entry and account_tokens record
+const TELEGRAM_BALANCE_RECORD_STORAGE: StorageUsage = 140;
+const TELEGRAM_ACCOUNT_INDEX_STORAGE: StorageUsage = 190;
+const TOKEN_INDEX_STORAGE: StorageUsage = 210; // first telegram account of the token
+const CHAT_POINT_STORAGE: StorageUsage = 130;
+const TIPTOKEN_RECORD_STORAGE: StorageUsage = 200;
+// first records of the token
+const LIABILITIES_STORAGE: StorageUsage = 230;
+const FEE_LEDGERS_STORAGE: StorageUsage = 400; // reward_pool, treasure and fee_stats entries
+pub(crate) const EXPIRING_TIP_STORAGE: StorageUsage = 230;
+pub(crate) const GIVEAWAY_STORAGE: StorageUsage = 700; // with the chat index and open giveaways counter
+// estimates are checked with the margin, charge_storage takes the actual usage
+const STORAGE_MARGIN: StorageUsage = 100;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountStorage {
+    pub balance: Balance,
+    pub used_bytes: StorageUsage,
+}
+
+impl AccountStorage {
+    fn get_used_balance(&self) -> Balance {
+        Balance::from(self.used_bytes) * env::storage_byte_cost()
+    }
+
+    fn get_available_balance(&self) -> Balance {
+        self.balance.saturating_sub(self.get_used_balance())
+    }
+
+    fn to_storage_balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: self.balance.into(),
+            available: self.get_available_balance().into(),
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for NearTips {
+    #[payable]
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>) -> StorageBalance {
+        let amount: Balance = env::attached_deposit();
+        let account_id: AccountId = account_id.map(|a| a.into()).unwrap_or_else(env::predecessor_account_id);
+
+        if let Some(mut account_storage) = self.storage_accounts.get(&account_id) {
+            if registration_only.unwrap_or(false) {
+                if amount > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
+            } else {
+                account_storage.balance += amount;
+                self.storage_accounts.insert(&account_id, &account_storage);
+                self.update_liabilities(&NEAR.to_string(), |l| l.storage += amount);
+            }
+            account_storage.to_storage_balance()
+        } else {
+            assert!(amount >= STORAGE_BALANCE_MIN_BOUND, "The attached deposit is less than the minimum storage balance");
+
+            let balance = if registration_only.unwrap_or(false) {
+                let refund = amount - STORAGE_BALANCE_MIN_BOUND;
+                if refund > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(refund);
+                }
+                STORAGE_BALANCE_MIN_BOUND
+            } else {
+                amount
+            };
+
+            let initial_storage_usage = env::storage_usage();
+            let mut account_storage = AccountStorage { balance, used_bytes: 0 };
+            self.storage_accounts.insert(&account_id, &account_storage);
+
+            // the storage account pays for itself
+            account_storage.used_bytes = env::storage_usage() - initial_storage_usage;
+            self.storage_accounts.insert(&account_id, &account_storage);
+            self.update_liabilities(&NEAR.to_string(), |l| l.storage += balance);
+
+            account_storage.to_storage_balance()
+        }
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut account_storage = self.storage_accounts.get(&account_id).expect("Account is not registered");
+
+        let available = account_storage.get_available_balance();
+        let amount: Balance = amount.map(|a| a.0).unwrap_or(available);
+        assert!(amount <= available, "Not enough storage balance to withdraw (Available: {}. Requested: {})", available, amount);
+
+        account_storage.balance -= amount;
+        self.storage_accounts.insert(&account_id, &account_storage);
+        self.update_liabilities(&NEAR.to_string(), |l| l.storage = l.storage.saturating_sub(amount));
+
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+
+        account_storage.to_storage_balance()
+    }
+
+    // deposits are never burned, so they have to be withdrawn before the unregistration
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        assert!(!force.unwrap_or(false), "Force unregistration is not supported");
+
+        let account_id = env::predecessor_account_id();
+        if let Some(account_storage) = self.storage_accounts.get(&account_id) {
+            assert!(!self.has_positive_deposit(&account_id), "Can't unregister the account with the positive deposit");
+            assert!(!self.has_open_giveaways(&account_id), "Can't unregister the account with open giveaways");
+            assert!(!self.has_subscriptions(&account_id), "Can't unregister the account with subscriptions");
+            assert!(!self.has_locked_balance(&account_id), "Can't unregister the account with locked funds");
+
+            let initial_storage_usage = env::storage_usage();
+            self.storage_accounts.remove(&account_id);
+            self.deposit_holders.remove(&account_id);
+            self.update_liabilities(&NEAR.to_string(), |l| l.storage = l.storage.saturating_sub(account_storage.balance));
+            let released_bytes = initial_storage_usage - env::storage_usage();
+
+            // storage of tips sent by the account is still in use and is not refunded
+            let still_used_balance = Balance::from(account_storage.used_bytes.saturating_sub(released_bytes)) * env::storage_byte_cost();
+            let refund = account_storage.balance.saturating_sub(still_used_balance);
+            if refund > 0 {
+                Promise::new(account_id).transfer(refund);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: STORAGE_BALANCE_MIN_BOUND.into(),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.storage_accounts.get(account_id.as_ref()).map(|account_storage| account_storage.to_storage_balance())
+    }
+}
+
+impl NearTips {
+    // bytes written since initial_storage_usage are paid from the storage balance of account_id,
+    // released bytes are returned to it
+    pub(crate) fn charge_storage(&mut self, account_id: &AccountId, initial_storage_usage: StorageUsage) {
+        let storage_usage = env::storage_usage();
+
+        if storage_usage > initial_storage_usage {
+            let mut account_storage = self.storage_accounts.get(account_id)
+                .unwrap_or_else(|| env::panic(format!("Account {} is not registered", account_id).as_bytes()));
+            account_storage.used_bytes += storage_usage - initial_storage_usage;
+            assert!(
+                account_storage.get_used_balance() <= account_storage.balance,
+                "Not enough storage balance for account {} (Balance: {}. Required: {})",
+                account_id, account_storage.balance, account_storage.get_used_balance()
+            );
+            self.storage_accounts.insert(account_id, &account_storage);
+        } else if storage_usage < initial_storage_usage {
+            if let Some(mut account_storage) = self.storage_accounts.get(account_id) {
+                account_storage.used_bytes = account_storage.used_bytes.saturating_sub(initial_storage_usage - storage_usage);
+                self.storage_accounts.insert(account_id, &account_storage);
+            }
+        }
+    }
+
+    // for the checks made before any state is changed, charge_storage would panic without the balance
+    pub(crate) fn has_available_storage(&self, account_id: &AccountId, bytes: StorageUsage) -> bool {
+        bytes == 0 || self.storage_accounts.get(account_id)
+            .map(|account_storage| account_storage.get_available_balance() >= Balance::from(bytes + STORAGE_MARGIN) * env::storage_byte_cost())
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn get_new_deposit_storage(&self, account_id: &AccountId, token_id: &TokenAccountId) -> StorageUsage {
+        let key = TokenByNearAccount {
+            account_id: account_id.clone(),
+            token_account_id: token_id.clone(),
+        };
+        let tokens = self.account_tokens.get(account_id).unwrap_or_default();
+        let mut bytes = 0;
+        if !self.deposits.contains_key(&key) {
+            bytes += DEPOSIT_RECORD_STORAGE;
+        }
+        if !tokens.contains(token_id) {
+            bytes += ACCOUNT_TOKEN_STORAGE;
+        }
+        if !self.deposit_holders.contains(account_id) {
+            bytes += DEPOSIT_HOLDER_STORAGE;
+        }
+        if !self.liabilities.contains_key(token_id) {
+            bytes += LIABILITIES_STORAGE;
+        }
+        bytes
+    }
+
+    // chat points and tiptokens of the tip are paid by the sender as well
+    pub(crate) fn get_new_telegram_tip_storage(&self, sender_account_id: &AccountId, telegram_account: TelegramAccountId, token_id: &TokenAccountId, chat_id: Option<TelegramChatId>) -> StorageUsage {
+        let key = TokenByTelegramAccount {
+            telegram_account,
+            token_account_id: token_id.clone(),
+        };
+        let mut bytes = 0;
+        if !self.telegram_tips.contains_key(&key) {
+            bytes += TELEGRAM_BALANCE_RECORD_STORAGE;
+        }
+        match self.telegram_accounts_per_token.get(token_id) {
+            Some(telegram_accounts) if telegram_accounts.contains(&telegram_account) => {}
+            Some(_) => bytes += TELEGRAM_ACCOUNT_INDEX_STORAGE,
+            None => bytes += TELEGRAM_ACCOUNT_INDEX_STORAGE + TOKEN_INDEX_STORAGE,
+        }
+
+        let chat = chat_id.filter(|chat_id| *chat_id > 0)
+            .and_then(|chat_id| self.get_chat_settings(chat_id).map(|chat_settings| (chat_id, chat_settings)));
+        if let Some((chat_id, chat_settings)) = chat {
+            let user_in_chat = TelegramUserInChat {
+                telegram_id: telegram_account,
+                chat_id,
+            };
+            if chat_settings.track_chat_points && !self.telegram_users_in_chats.contains(&user_in_chat) {
+                bytes += CHAT_POINT_STORAGE;
+            }
+            if chat_settings.treasure_fee_numerator > 0 {
+                if !self.fee_stats.contains_key(token_id) {
+                    bytes += FEE_LEDGERS_STORAGE;
+                }
+                for account_id in [&chat_settings.admin_account_id, sender_account_id] {
+                    let tiptokens_key = TokenByNearAccount {
+                        account_id: account_id.clone(),
+                        token_account_id: token_id.clone(),
+                    };
+                    if !self.user_tokens_to_claim.contains_key(&tiptokens_key) {
+                        bytes += TIPTOKEN_RECORD_STORAGE;
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    pub(crate) fn has_storage_for_deposit(&self, payer_account_id: &AccountId, account_id: &AccountId, token_id: &TokenAccountId) -> bool {
+        self.has_available_storage(payer_account_id, self.get_new_deposit_storage(account_id, token_id))
+    }
+
+    pub(crate) fn has_storage_for_telegram_tip(&self, payer_account_id: &AccountId, telegram_account: TelegramAccountId, token_id: &TokenAccountId, chat_id: Option<TelegramChatId>) -> bool {
+        self.has_available_storage(payer_account_id, self.get_new_telegram_tip_storage(payer_account_id, telegram_account, token_id, chat_id))
+    }
+}
@@ -0,0 +1,295 @@
+use crate::*;
+use near_sdk::{Duration, Timestamp};
+
+/* SUBSCRIPTIONS, recurring tips paid from the sender's deposit by keepers */
+pub type SubscriptionId = u64;
+
+const MIN_SUBSCRIPTION_PERIOD: Duration = 60 * 60 * 1_000_000_000; // 1 hour
+// subscriptions checked by a single execute_due_subscriptions, keeps gas and log size in limits
+const MAX_SUBSCRIPTIONS_TO_EXECUTE: u64 = 20;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionRecipient {
+    Telegram(TelegramAccountId),
+    // tips go to the deposit of the account
+    Account(AccountId),
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionSkipReason {
+    InsufficientDeposit,
+    TokenNotWhitelisted,
+    TokenPaused,
+    BelowMinTip,
+    // sender can't pay for the new record of the recipient
+    InsufficientStorage,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Subscription {
+    pub sender_account_id: AccountId,
+    pub recipient: SubscriptionRecipient,
+    pub token_id: TokenAccountId,
+    pub amount: Balance,
+    pub period: Duration,
+    pub max_payments: u32,
+    pub payments_made: u32,
+    pub next_payment_at: Timestamp,
+    // last due payment was skipped, cleared by the next successful one
+    pub insufficient_deposit: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SubscriptionOutput {
+    pub subscription_id: SubscriptionId,
+    pub sender_account_id: AccountId,
+    pub recipient: SubscriptionRecipient,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub period: U64,
+    pub max_payments: u32,
+    pub payments_made: u32,
+    pub next_payment_at: WrappedTimestamp,
+    pub insufficient_deposit: bool,
+}
+
+#[near_bindgen]
+impl NearTips {
+    // first payment is due at first_payment_at (in nanoseconds) or right away
+    pub fn create_subscription(&mut self,
+                               recipient: SubscriptionRecipient,
+                               amount: WrappedBalance,
+                               token_id: Option<TokenAccountId>,
+                               period: U64,
+                               max_payments: u32,
+                               first_payment_at: Option<WrappedTimestamp>) -> SubscriptionId {
+        self.assert_tip_available();
+        self.assert_token_available(&token_id);
+        assert!(amount.0 > 0, "Positive amount needed");
+        assert!(period.0 >= MIN_SUBSCRIPTION_PERIOD, "Minimum subscription period is {}", MIN_SUBSCRIPTION_PERIOD);
+        assert!(max_payments > 0, "Positive number of payments needed");
+
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        self.assert_min_tip(&token_id_unwrapped, amount.0);
+        if let SubscriptionRecipient::Account(account_id) = &recipient {
+            assert!(self.storage_accounts.get(account_id).is_some(), "Account {} is not registered", account_id);
+        }
+
+        let sender_account_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+
+        let subscription_id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+        self.subscriptions.insert(&subscription_id, &Subscription {
+            sender_account_id: sender_account_id.clone(),
+            recipient: recipient.clone(),
+            token_id: token_id_unwrapped.clone(),
+            amount: amount.0,
+            period: period.0,
+            max_payments,
+            payments_made: 0,
+            next_payment_at: first_payment_at.map(|t| t.0).unwrap_or_else(env::block_timestamp),
+            insufficient_deposit: false,
+        });
+
+        let mut subscription_ids = self.subscriptions_per_account.get(&sender_account_id).unwrap_or_else(|| {
+            UnorderedSet::new(StorageKey::SubscriptionsPerAccount { account_id_hash: env::sha256(sender_account_id.as_bytes()) })
+        });
+        subscription_ids.insert(&subscription_id);
+        self.subscriptions_per_account.insert(&sender_account_id, &subscription_ids);
+        self.charge_storage(&sender_account_id, initial_storage_usage);
+
+        EventKind::CreateSubscription(vec![SubscriptionEvent {
+            subscription_id,
+            sender_account_id,
+            recipient,
+            token_id: token_id_unwrapped,
+            amount,
+            period,
+            max_payments,
+        }]).emit();
+
+        subscription_id
+    }
+
+    pub fn cancel_subscription(&mut self, subscription_id: SubscriptionId) {
+        let subscription = self.subscriptions.get(&subscription_id).expect("Subscription not found");
+        assert_eq!(env::predecessor_account_id(), subscription.sender_account_id, "No access");
+
+        self.close_subscription(subscription_id, &subscription);
+    }
+
+    // checks the next `limit` subscriptions after the previous call, so every subscription is reached
+    // by the subsequent calls. Returns the number of paid subscriptions
+    pub fn execute_due_subscriptions(&mut self, limit: Option<u64>) -> u32 {
+        self.assert_tip_available();
+
+        let count = self.subscriptions.len();
+        let limit = std::cmp::min(std::cmp::min(limit.unwrap_or(MAX_SUBSCRIPTIONS_TO_EXECUTE), MAX_SUBSCRIPTIONS_TO_EXECUTE), count);
+        let now = env::block_timestamp();
+
+        let mut subscription_ids: Vec<SubscriptionId> = vec![];
+        for index in 0..limit {
+            subscription_ids.push(self.subscriptions.keys_as_vector().get((self.subscriptions_cursor + index) % count).unwrap());
+        }
+        if count > 0 {
+            self.subscriptions_cursor = (self.subscriptions_cursor + limit) % count;
+        }
+
+        let mut paid = 0;
+        let mut skipped: Vec<SkipSubscriptionEvent> = vec![];
+        for subscription_id in subscription_ids {
+            let mut subscription = self.subscriptions.get(&subscription_id).unwrap();
+            if subscription.next_payment_at > now {
+                continue;
+            }
+
+            if let Some(reason) = self.get_subscription_skip_reason(&subscription) {
+                if reason == SubscriptionSkipReason::InsufficientDeposit {
+                    subscription.insufficient_deposit = true;
+                    self.subscriptions.insert(&subscription_id, &subscription);
+                }
+                skipped.push(SkipSubscriptionEvent { subscription_id, reason });
+                continue;
+            }
+
+            self.pay_subscription(&subscription);
+            paid += 1;
+
+            subscription.payments_made += 1;
+            subscription.insufficient_deposit = false;
+            // missed periods are not paid twice
+            subscription.next_payment_at += ((now - subscription.next_payment_at) / subscription.period + 1) * subscription.period;
+
+            if subscription.payments_made == subscription.max_payments {
+                self.close_subscription(subscription_id, &subscription);
+            } else {
+                self.subscriptions.insert(&subscription_id, &subscription);
+            }
+        }
+
+        if !skipped.is_empty() {
+            EventKind::SkipSubscription(skipped).emit();
+        }
+        paid
+    }
+
+    pub fn get_subscription(&self, subscription_id: SubscriptionId) -> Option<SubscriptionOutput> {
+        self.subscriptions.get(&subscription_id).map(|subscription| NearTips::subscription_output(subscription_id, subscription))
+    }
+
+    pub fn get_subscriptions(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<SubscriptionOutput> {
+        NearTips::paginate(self.subscriptions.keys_as_vector(), from_index, limit)
+            .into_iter()
+            .filter_map(|subscription_id| self.get_subscription(subscription_id))
+            .collect()
+    }
+
+    pub fn get_account_subscriptions(&self, account_id: ValidAccountId) -> Vec<SubscriptionOutput> {
+        match self.subscriptions_per_account.get(account_id.as_ref()) {
+            Some(subscription_ids) => subscription_ids
+                .iter()
+                .filter_map(|subscription_id| self.get_subscription(subscription_id))
+                .collect(),
+            None => vec![]
+        }
+    }
+}
+
+impl NearTips {
+    // checks everything pay_subscription would assert, so one broken subscription doesn't revert the batch
+    fn get_subscription_skip_reason(&self, subscription: &Subscription) -> Option<SubscriptionSkipReason> {
+        let config = self.get_token_config_or_default(&subscription.token_id);
+        let has_storage = match &subscription.recipient {
+            SubscriptionRecipient::Telegram(telegram_account) =>
+                self.has_storage_for_telegram_tip(&subscription.sender_account_id, *telegram_account, &subscription.token_id, None),
+            SubscriptionRecipient::Account(account_id) =>
+                self.has_storage_for_deposit(&subscription.sender_account_id, account_id, &subscription.token_id),
+        };
+        if !self.whitelisted_tokens.contains(&subscription.token_id) {
+            Some(SubscriptionSkipReason::TokenNotWhitelisted)
+        } else if config.paused {
+            Some(SubscriptionSkipReason::TokenPaused)
+        } else if subscription.amount < config.min_tip.0 {
+            Some(SubscriptionSkipReason::BelowMinTip)
+        } else if self.get_deposit_for_account_id_and_token_id(&subscription.sender_account_id, &subscription.token_id) < subscription.amount {
+            Some(SubscriptionSkipReason::InsufficientDeposit)
+        } else if !has_storage {
+            Some(SubscriptionSkipReason::InsufficientStorage)
+        } else {
+            None
+        }
+    }
+
+    fn pay_subscription(&mut self, subscription: &Subscription) {
+        let sender_account_id = subscription.sender_account_id.clone();
+        let token_id = subscription.token_id.clone();
+
+        match &subscription.recipient {
+            SubscriptionRecipient::Telegram(telegram_account) => {
+                self.send_tip_to_telegram_from_account(sender_account_id, *telegram_account, subscription.amount.into(), None, Some(token_id), None);
+            }
+            SubscriptionRecipient::Account(account_id) => {
+                let initial_storage_usage = env::storage_usage();
+                self.decrease_deposit(sender_account_id.clone(), token_id.clone(), subscription.amount);
+                self.increase_deposit(account_id.clone(), token_id.clone(), subscription.amount);
+                self.charge_storage(&sender_account_id, initial_storage_usage);
+
+                self.record_tips(vec![TipEvent {
+                    sender_account_id,
+                    telegram_account: None,
+                    receiver_account_id: Some(account_id.clone()),
+                    contact: None,
+                    token_id,
+                    amount: subscription.amount.into(),
+                    fee: 0.into(),
+                    chat_id: None,
+                    expires_at: None,
+                }]);
+            }
+        }
+    }
+
+    fn close_subscription(&mut self, subscription_id: SubscriptionId, subscription: &Subscription) {
+        let initial_storage_usage = env::storage_usage();
+        self.subscriptions.remove(&subscription_id);
+        if let Some(mut subscription_ids) = self.subscriptions_per_account.get(&subscription.sender_account_id) {
+            subscription_ids.remove(&subscription_id);
+            if subscription_ids.is_empty() {
+                self.subscriptions_per_account.remove(&subscription.sender_account_id);
+            } else {
+                self.subscriptions_per_account.insert(&subscription.sender_account_id, &subscription_ids);
+            }
+        }
+        self.charge_storage(&subscription.sender_account_id, initial_storage_usage);
+
+        EventKind::CloseSubscription(vec![CloseSubscriptionEvent {
+            subscription_id,
+            payments_made: subscription.payments_made,
+        }]).emit();
+    }
+
+    pub(crate) fn has_subscriptions(&self, account_id: &AccountId) -> bool {
+        self.subscriptions_per_account.get(account_id).is_some()
+    }
+
+    fn subscription_output(subscription_id: SubscriptionId, subscription: Subscription) -> SubscriptionOutput {
+        SubscriptionOutput {
+            subscription_id,
+            sender_account_id: subscription.sender_account_id,
+            recipient: subscription.recipient,
+            token_id: subscription.token_id,
+            amount: subscription.amount.into(),
+            period: subscription.period.into(),
+            max_payments: subscription.max_payments,
+            payments_made: subscription.payments_made,
+            next_payment_at: subscription.next_payment_at.into(),
+            insufficient_deposit: subscription.insufficient_deposit,
+        }
+    }
+}
@@ -0,0 +1,2376 @@
+use super::*;
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+use near_sdk::{testing_env, MockedBlockchain};
+use crate::auth_tips::telegram_contact;
+
+const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+const ONE_DAI: Balance = 1_000_000_000_000_000_000;
+const DAI: &str = "dai.near";
+const TIPTOKEN: &str = "tiptoken.near";
+const STORAGE_DEPOSIT: Balance = ONE_NEAR / 10;
+const TELEGRAM_ACCOUNT: TelegramAccountId = 123;
+const CHAT_ID: TelegramChatId = 42;
+
+fn contract_account() -> ValidAccountId {
+    ValidAccountId::try_from("tipbot.near").unwrap()
+}
+
+fn master() -> ValidAccountId {
+    accounts(0)
+}
+
+fn chat_admin() -> ValidAccountId {
+    accounts(3)
+}
+
+fn setup() -> (VMContextBuilder, NearTips) {
+    let mut context = VMContextBuilder::new();
+    testing_env!(context
+        .current_account_id(contract_account())
+        .predecessor_account_id(master())
+        .build());
+
+    let mut contract = NearTips::new(
+        master(),
+        ValidAccountId::try_from("linkdrop.near").unwrap(),
+        ValidAccountId::try_from("auth.near").unwrap(),
+        ValidAccountId::try_from(TIPTOKEN).unwrap(),
+    );
+    contract.whitelist_token(NEAR.to_string());
+    contract.whitelist_token(DAI.to_string());
+    contract.whitelist_token(TIPTOKEN.to_string());
+
+    for account_id in [accounts(1), accounts(2), chat_admin()] {
+        set_predecessor(&mut context, account_id, STORAGE_DEPOSIT);
+        contract.storage_deposit(None, None);
+    }
+    set_predecessor(&mut context, master(), 0);
+
+    (context, contract)
+}
+
+fn set_predecessor(context: &mut VMContextBuilder, account_id: ValidAccountId, attached_deposit: Balance) {
+    testing_env!(context
+        .predecessor_account_id(account_id)
+        .attached_deposit(attached_deposit)
+        .build());
+}
+
+fn set_callback_result(context: &mut VMContextBuilder, result: PromiseResult) {
+    testing_env!(context
+        .predecessor_account_id(contract_account())
+        .attached_deposit(0)
+        .build(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        vec![result]);
+}
+
+fn deposit_near(context: &mut VMContextBuilder, contract: &mut NearTips, account_id: ValidAccountId, amount: Balance) {
+    set_predecessor(context, account_id, amount);
+    contract.deposit(None);
+}
+
+fn deposit_ft(context: &mut VMContextBuilder, contract: &mut NearTips, token_id: &str, account_id: ValidAccountId, amount: Balance) {
+    set_predecessor(context, ValidAccountId::try_from(token_id).unwrap(), 0);
+    contract.ft_on_transfer(account_id, amount.into(), "".to_string());
+}
+
+fn add_chat(context: &mut VMContextBuilder, contract: &mut NearTips, treasure_fee_numerator: TreasureFeeNumerator) {
+    set_predecessor(context, master(), 0);
+    contract.add_chat_settings(CHAT_ID, chat_admin(), treasure_fee_numerator, true, None);
+}
+
+fn event_logs() -> Vec<String> {
+    get_logs().into_iter().filter(|log| log.starts_with("EVENT_JSON:")).collect()
+}
+
+#[test]
+fn test_deposit_near() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_deposit(accounts(1), Some(NEAR.to_string())).0, ONE_NEAR);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"deposit","data":[{"account_id":"bob","token_id":"near","amount":"1000000000000000000000000"}]}"#]
+    );
+}
+
+#[test]
+fn test_deposit_for_another_account() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), ONE_NEAR);
+    contract.deposit(Some(accounts(2)));
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "Minimum deposit is 100000000000000000000000")]
+fn test_deposit_below_minimum() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR / 100);
+}
+
+#[test]
+#[should_panic(expected = "Withdrawals paused")]
+fn test_deposit_when_paused() {
+    let (mut context, mut contract) = setup();
+    contract.set_withdraw_available(false);
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+}
+
+#[test]
+fn test_ft_on_transfer() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 5 * ONE_DAI);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+}
+
+fn ft_transfer_call(context: &mut VMContextBuilder, contract: &mut NearTips, token_id: &str, account_id: ValidAccountId, amount: Balance, msg: &str) -> Balance {
+    set_predecessor(context, ValidAccountId::try_from(token_id).unwrap(), 0);
+    match contract.ft_on_transfer(account_id, amount.into(), msg.to_string()) {
+        PromiseOrValue::Value(unused) => unused.0,
+        PromiseOrValue::Promise(_) => panic!("Unexpected promise"),
+    }
+}
+
+#[test]
+fn test_ft_on_transfer_deposit_to_another_account() {
+    let (mut context, mut contract) = setup();
+    let msg = format!(r#"{{"action":"deposit","account_id":"{}"}}"#, accounts(2));
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI, &msg), 0);
+
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+    assert_eq!(contract.get_deposit(accounts(2), Some(DAI.to_string())).0, 5 * ONE_DAI);
+}
+
+#[test]
+fn test_ft_on_transfer_tip_telegram() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    let msg = format!(r#"{{"action":"tip_telegram","telegram_account":{},"chat_id":{}}}"#, TELEGRAM_ACCOUNT, CHAT_ID);
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), 10 * ONE_DAI, &msg), 0);
+
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 9 * ONE_DAI);
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), Some(DAI.to_string())).0, 4 * ONE_DAI / 10);
+}
+
+#[test]
+fn test_ft_on_transfer_invalid_msg_is_refunded() {
+    let (mut context, mut contract) = setup();
+    for msg in ["not a json", r#"{"action":"unknown"}"#, r#"{"action":"tip_telegram"}"#] {
+        assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI, msg), 5 * ONE_DAI);
+    }
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+}
+
+#[test]
+fn test_ft_on_transfer_not_whitelisted() {
+    let (mut context, mut contract) = setup();
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, "usdc.near", accounts(1), 5 * ONE_DAI, ""), 5 * ONE_DAI);
+    assert_eq!(get_logs(), vec![format!(
+        r#"EVENT_JSON:{{"standard":"tipbot","version":"1.0.0","event":"transfer_rejected","data":[{{"sender_account_id":"{}","token_id":"usdc.near","amount":"{}","reason":"token_not_whitelisted"}}]}}"#,
+        accounts(1), 5 * ONE_DAI)]);
+    assert_eq!(contract.get_deposit(accounts(1), Some("usdc.near".to_string())).0, 0);
+}
+
+fn assert_transfer_rejected(context: &mut VMContextBuilder, contract: &mut NearTips, account_id: ValidAccountId, amount: Balance, msg: &str, reason: &str) {
+    assert_eq!(ft_transfer_call(context, contract, DAI, account_id.clone(), amount, msg), amount);
+    assert!(get_logs()[0].contains(&format!(r#""reason":"{}""#, reason)));
+    assert_eq!(contract.get_deposit(account_id, Some(DAI.to_string())).0, 0);
+}
+
+#[test]
+fn test_ft_on_transfer_rejections() {
+    let (mut context, mut contract) = setup();
+    let tip_msg = format!(r#"{{"action":"tip_telegram","telegram_account":{}}}"#, TELEGRAM_ACCOUNT);
+
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, "not a json", "invalid_message");
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), MIN_DEPOSIT_FT - 1, "", "below_min_deposit");
+    assert_transfer_rejected(&mut context, &mut contract, accounts(4), ONE_DAI, "", "account_not_registered");
+    let msg = format!(r#"{{"action":"deposit","account_id":"{}"}}"#, accounts(4));
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, &msg, "account_not_registered");
+
+    set_predecessor(&mut context, master(), 0);
+    contract.update_token(DAI.to_string(), TokenConfig { min_tip: (2 * ONE_DAI).into(), ..TokenConfig::default_for(&DAI.to_string()) });
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, &tip_msg, "below_min_tip");
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tip_available(false);
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), 5 * ONE_DAI, &tip_msg, "tips_paused");
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_withdraw_available(false);
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, "", "deposits_paused");
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_token_paused(DAI.to_string(), true);
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, "", "token_paused");
+}
+
+#[test]
+fn test_send_tip_to_telegram() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 2 * ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"tip","data":[{"sender_account_id":"bob","telegram_account":123,"token_id":"near","amount":"1000000000000000000000000","fee":"0"}]}"#]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens deposited to tip")]
+fn test_send_tip_exceeding_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (2 * ONE_NEAR).into(), None, None, None);
+}
+
+#[test]
+#[should_panic(expected = "Tips paused")]
+fn test_send_tip_when_paused() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tip_available(false);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+}
+
+#[test]
+fn test_send_tip_with_chat_fee() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 9 * ONE_NEAR);
+    assert_eq!(contract.get_treasure_balance(None).0, 2 * ONE_NEAR / 10);
+    assert_eq!(contract.get_reward_pool_balance(None).0, 8 * ONE_NEAR / 10);
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), None).0, 4 * ONE_NEAR / 10);
+    assert_eq!(contract.get_chat_tokens(CHAT_ID, None).0, 4 * ONE_NEAR / 10);
+    assert_eq!(contract.get_chat_points(CHAT_ID), 1);
+    assert!(contract.get_telegram_users_in_chats(TELEGRAM_ACCOUNT, CHAT_ID));
+
+    // the same user doesn't bring a second point to the chat
+    deposit_near(&mut context, &mut contract, accounts(2), ONE_NEAR);
+    set_predecessor(&mut context, accounts(2), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), Some(CHAT_ID), None, None);
+    assert_eq!(contract.get_chat_points(CHAT_ID), 1);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 9 * ONE_NEAR + 9 * ONE_NEAR / 10);
+}
+
+#[test]
+fn test_send_tip_to_unknown_chat_has_no_fee() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), Some(CHAT_ID), None, None);
+
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert_eq!(contract.get_treasure_balance(None).0, 0);
+    assert_eq!(contract.get_chat_points(CHAT_ID), 0);
+}
+
+#[test]
+fn test_send_tips_to_telegram() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tips_to_telegram(vec![(TELEGRAM_ACCOUNT, ONE_NEAR.into()), (TELEGRAM_ACCOUNT + 1, ONE_NEAR.into())], None, None);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT + 1, None).0, ONE_NEAR);
+    assert_eq!(
+        event_logs(),
+        vec![
+            r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"batch_tip","data":[{"sender_account_id":"bob","token_id":"near","recipients_count":2,"amount":"2000000000000000000000000","fee":"0"}]}"#,
+            r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"tip","data":[{"sender_account_id":"bob","telegram_account":123,"token_id":"near","amount":"1000000000000000000000000","fee":"0"},{"sender_account_id":"bob","telegram_account":124,"token_id":"near","amount":"1000000000000000000000000","fee":"0"}]}"#,
+        ]
+    );
+}
+
+#[test]
+fn test_send_tips_to_telegram_with_chat_fee() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tips_to_telegram(vec![(TELEGRAM_ACCOUNT, (4 * ONE_NEAR).into()), (TELEGRAM_ACCOUNT + 1, (6 * ONE_NEAR).into())], Some(CHAT_ID), None);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 36 * ONE_NEAR / 10);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT + 1, None).0, 54 * ONE_NEAR / 10);
+    assert_eq!(contract.get_treasure_balance(None).0, 2 * ONE_NEAR / 10);
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), None).0, 4 * ONE_NEAR / 10);
+    assert_eq!(contract.get_chat_tokens(CHAT_ID, None).0, 4 * ONE_NEAR / 10);
+    assert_eq!(contract.get_chat_points(CHAT_ID), 2);
+    let liabilities = contract.get_liabilities(None);
+    assert_eq!(liabilities.deposits.0, 0);
+    assert_eq!(liabilities.telegram_balances.0, 9 * ONE_NEAR);
+    assert_eq!(liabilities.total.0, 10 * ONE_NEAR + 3 * STORAGE_DEPOSIT);
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens deposited to tip")]
+fn test_send_tips_exceeding_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tips_to_telegram(vec![(TELEGRAM_ACCOUNT, ONE_NEAR.into()), (TELEGRAM_ACCOUNT + 1, ONE_NEAR.into())], None, None);
+}
+
+#[test]
+#[should_panic(expected = "Too many recipients, max 25")]
+fn test_send_tips_too_many_recipients() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 30 * ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    let recipients = (0..26).map(|i| (TELEGRAM_ACCOUNT + i, ONE_NEAR.into())).collect();
+    contract.send_tips_to_telegram(recipients, None, None);
+}
+
+fn add_active_chat_members(context: &mut VMContextBuilder, contract: &mut NearTips, telegram_accounts: &[TelegramAccountId]) {
+    add_chat(context, contract, 0);
+    deposit_near(context, contract, accounts(2), telegram_accounts.len() as Balance * ONE_NEAR);
+    set_predecessor(context, accounts(2), 0);
+    let recipients = telegram_accounts.iter().map(|telegram_account| (*telegram_account, ONE_NEAR.into())).collect();
+    contract.send_tips_to_telegram(recipients, Some(CHAT_ID), None);
+}
+
+#[test]
+fn test_rain_tip() {
+    let (mut context, mut contract) = setup();
+    add_active_chat_members(&mut context, &mut contract, &[TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT + 1, TELEGRAM_ACCOUNT + 2]);
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+
+    // inactive and duplicated members are skipped, the remainder of the split stays in the deposit
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.rain_tip(CHAT_ID, (7 * ONE_NEAR + 1).into(), None, 2, vec![999, TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT + 1, TELEGRAM_ACCOUNT + 2]);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 3 * ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR + 315 * ONE_NEAR / 100);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT + 1, None).0, ONE_NEAR + 315 * ONE_NEAR / 100);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT + 2, None).0, ONE_NEAR);
+    assert_eq!(contract.get_balance(999, None).0, 0);
+    assert_eq!(contract.get_treasure_balance(None).0, 14 * ONE_NEAR / 100);
+    assert_eq!(
+        event_logs()[1],
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"rain_tip","data":[{"sender_account_id":"bob","token_id":"near","chat_id":42,"recipients_count":2,"amount":"6300000000000000000000000","fee":"700000000000000000000000","remainder":"1"}]}"#
+    );
+}
+
+#[test]
+#[should_panic(expected = "Not enough active chat members (Found: 1. Requested: 2)")]
+fn test_rain_tip_not_enough_members() {
+    let (mut context, mut contract) = setup();
+    add_active_chat_members(&mut context, &mut contract, &[TELEGRAM_ACCOUNT]);
+    deposit_near(&mut context, &mut contract, accounts(1), 2 * ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.rain_tip(CHAT_ID, (2 * ONE_NEAR).into(), None, 2, vec![TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT + 1]);
+}
+
+#[test]
+#[should_panic(expected = "Minimum tip is 1000000000000000000")]
+fn test_rain_tip_below_min_tip() {
+    let (mut context, mut contract) = setup();
+    add_active_chat_members(&mut context, &mut contract, &[TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT + 1]);
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 2 * ONE_DAI);
+    set_predecessor(&mut context, master(), 0);
+    contract.update_token(DAI.to_string(), TokenConfig { min_tip: ONE_DAI.into(), ..TokenConfig::default_for(&DAI.to_string()) });
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.rain_tip(CHAT_ID, (ONE_DAI + 1).into(), Some(DAI.to_string()), 2, vec![TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT + 1]);
+}
+
+#[test]
+fn test_withdraw_from_telegram_near() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), None, None);
+
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 0);
+    assert_eq!(
+        event_logs(),
+        vec![format!(
+            r#"EVENT_JSON:{{"standard":"tipbot","version":"1.0.0","event":"withdraw","data":[{{"account_id":"charlie","telegram_account":123,"token_id":"near","amount":"{}","fee":"{}"}}]}}"#,
+            ONE_NEAR - WITHDRAW_COMMISSION, WITHDRAW_COMMISSION
+        )]
+    );
+    assert_eq!(contract.get_commission_balance(None).0, WITHDRAW_COMMISSION);
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_withdraw_from_telegram_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(1), None, None);
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens to pay withdraw commission")]
+fn test_withdraw_from_telegram_without_balance() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(1), None, None);
+}
+
+#[test]
+fn test_withdraw_from_telegram_partially() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    // commission is charged from every withdrawal
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), None, Some((ONE_NEAR / 2).into()));
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), None, Some((ONE_NEAR / 4).into()));
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR / 4);
+    assert_eq!(contract.get_commission_balance(None).0, 2 * WITHDRAW_COMMISSION);
+    assert_eq!(contract.get_telegram_balances(None, None, None), vec![(TELEGRAM_ACCOUNT, (ONE_NEAR / 4).into())]);
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens to pay withdraw commission")]
+fn test_withdraw_from_telegram_partially_below_commission() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), None, Some(WITHDRAW_COMMISSION.into()));
+}
+
+#[test]
+fn test_withdraw_from_telegram_ft_partial_rollback() {
+    let (mut context, mut contract) = setup();
+    set_dai_commission(&mut context, &mut contract, WithdrawCommission::BasisPoints(100));
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), Some(DAI.to_string()), Some((2 * ONE_DAI).into()));
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 3 * ONE_DAI);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    contract.after_ft_transfer_balance(TELEGRAM_ACCOUNT, (2 * ONE_DAI - 2 * ONE_DAI / 100).into(), DAI.to_string(), Some((2 * ONE_DAI / 100).into()), None);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 5 * ONE_DAI);
+    assert_eq!(contract.get_commission_balance(Some(DAI.to_string())).0, 0);
+}
+
+#[test]
+fn test_withdraw_from_telegram_ft_rollback() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), Some(DAI.to_string()), None);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 0);
+
+    set_callback_result(&mut context, PromiseResult::Successful(vec![]));
+    assert!(contract.after_ft_transfer_balance(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), DAI.to_string(), None, None));
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 0);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_balance(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), DAI.to_string(), None, None));
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 5 * ONE_DAI);
+}
+
+#[test]
+#[should_panic(expected = "Callback can only be called from the contract")]
+fn test_after_ft_transfer_balance_is_private() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.after_ft_transfer_balance(TELEGRAM_ACCOUNT, ONE_DAI.into(), DAI.to_string(), None, None);
+}
+
+#[test]
+fn test_transfer_tips_to_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.transfer_tips_to_deposit(TELEGRAM_ACCOUNT, accounts(2), None);
+
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 0);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, ONE_NEAR - WITHDRAW_COMMISSION);
+}
+
+#[test]
+fn test_withdraw_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(None, None);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+}
+
+#[test]
+fn test_withdraw_deposit_partially() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(None, Some((ONE_NEAR / 4).into()));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 3 * ONE_NEAR / 4);
+    assert_eq!(contract.get_liabilities(None).deposits.0, 3 * ONE_NEAR / 4);
+    assert_eq!(contract.get_deposit_holders(None, None), vec![accounts(1).to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens to withdraw (Balance: 1000000000000000000000000. Requested: 1000000000000000000000001)")]
+fn test_withdraw_deposit_above_balance() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(None, Some((ONE_NEAR + 1).into()));
+}
+
+#[test]
+fn test_withdraw_deposit_near_rollback() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(None, None);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_deposit(accounts(1).into(), ONE_NEAR.into(), NEAR.to_string()));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_liabilities(None).deposits.0, ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "Missing deposit")]
+fn test_withdraw_empty_deposit() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(None, None);
+}
+
+#[test]
+fn test_withdraw_deposit_ft_rollback() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(Some(DAI.to_string()), None);
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_deposit(accounts(1).into(), (5 * ONE_DAI).into(), DAI.to_string()));
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 5 * ONE_DAI);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"withdraw_failed","data":[{"account_id":"bob","token_id":"dai.near","amount":"5000000000000000000"}]}"#]
+    );
+}
+
+#[test]
+fn test_withdraw_linkdrop() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 2 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (2 * ONE_NEAR).into(), None, None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_linkdrop("ed25519:key".to_string(), TELEGRAM_ACCOUNT);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens to pay for key allowance and withdraw commission")]
+fn test_withdraw_linkdrop_below_allowance() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR / 2);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (ONE_NEAR / 2).into(), None, None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_linkdrop("ed25519:key".to_string(), TELEGRAM_ACCOUNT);
+}
+
+#[test]
+fn test_claim_tiptokens_rollback() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+
+    let points = contract.get_user_tokens(accounts(1).into(), None).0;
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(None);
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), None).0, 0);
+    assert_eq!(contract.get_total_tiptokens().0, points);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_claim_tiptokens(accounts(1).into(), points.into(), NEAR.to_string(), Some(0)));
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), None).0, points);
+    assert_eq!(contract.get_total_tiptokens().0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Nothing to claim")]
+fn test_claim_tiptokens_without_points() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(None);
+}
+
+#[test]
+fn test_claim_tiptokens_for_chat() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+
+    set_predecessor(&mut context, chat_admin(), 1);
+    contract.claim_tiptokens_for_chat(CHAT_ID, None);
+    assert_eq!(contract.get_chat_tokens(CHAT_ID, None).0, 0);
+    assert_eq!(contract.get_total_tiptokens().0, 4 * ONE_NEAR / 10);
+}
+
+#[test]
+fn test_redeem_tiptokens() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(None);
+    let tiptokens = contract.get_total_tiptokens().0;
+
+    // claimed TipTokens come back to the contract as a deposit
+    deposit_ft(&mut context, &mut contract, TIPTOKEN, accounts(1), tiptokens);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.redeem_tiptokens(vec![NEAR.to_string()], None);
+
+    assert_eq!(contract.get_reward_pool_balance(None).0, 0);
+    assert_eq!(contract.get_treasure_balance(None).0, 2 * ONE_NEAR / 10);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 8 * ONE_NEAR / 10);
+    assert_eq!(contract.get_deposit(accounts(1), Some(TIPTOKEN.to_string())).0, 0);
+    assert_eq!(contract.get_tiptokens_burned().0, tiptokens);
+    assert_eq!(contract.get_circulating_tiptokens().0, 0);
+}
+
+fn claim_and_deposit_tiptokens(context: &mut VMContextBuilder, contract: &mut NearTips) -> Balance {
+    add_chat(context, contract, 10);
+    deposit_near(context, contract, accounts(1), 10 * ONE_NEAR);
+    set_predecessor(context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+
+    set_predecessor(context, accounts(1), 1);
+    contract.claim_tiptokens(None);
+    let tiptokens = contract.get_total_tiptokens().0;
+    deposit_ft(context, contract, TIPTOKEN, accounts(1), tiptokens);
+    tiptokens
+}
+
+#[test]
+fn test_redeem_tiptokens_partially() {
+    let (mut context, mut contract) = setup();
+    let tiptokens = claim_and_deposit_tiptokens(&mut context, &mut contract);
+
+    // duplicated tokens are paid out once
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.redeem_tiptokens(vec![NEAR.to_string(), NEAR.to_string()], Some((tiptokens / 4).into()));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 2 * ONE_NEAR / 10);
+    assert_eq!(contract.get_reward_pool_balance(None).0, 6 * ONE_NEAR / 10);
+    assert_eq!(contract.get_deposit(accounts(1), Some(TIPTOKEN.to_string())).0, 3 * tiptokens / 4);
+    assert_eq!(contract.get_circulating_tiptokens().0, 3 * tiptokens / 4);
+
+    // the share is computed against the circulating supply
+    contract.redeem_tiptokens(vec![NEAR.to_string()], Some((tiptokens / 4).into()));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 4 * ONE_NEAR / 10);
+    assert_eq!(contract.get_reward_pool_balance(None).0, 4 * ONE_NEAR / 10);
+}
+
+#[test]
+#[should_panic(expected = "Nothing to redeem")]
+fn test_redeem_tiptokens_twice() {
+    let (mut context, mut contract) = setup();
+    claim_and_deposit_tiptokens(&mut context, &mut contract);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.redeem_tiptokens(vec![NEAR.to_string()], None);
+    contract.redeem_tiptokens(vec![NEAR.to_string()], None);
+}
+
+#[test]
+#[should_panic(expected = "Not enough TipTokens deposited to redeem")]
+fn test_redeem_tiptokens_above_deposit() {
+    let (mut context, mut contract) = setup();
+    let tiptokens = claim_and_deposit_tiptokens(&mut context, &mut contract);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.redeem_tiptokens(vec![NEAR.to_string()], Some((tiptokens + 1).into()));
+}
+
+#[test]
+fn test_redeem_small_share_of_tiptokens() {
+    let (mut context, mut contract) = setup();
+    let tiptokens = claim_and_deposit_tiptokens(&mut context, &mut contract);
+
+    // share below min_deposit and paused tokens are paid out as well
+    set_predecessor(&mut context, master(), 0);
+    contract.set_token_paused(NEAR.to_string(), true);
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.redeem_tiptokens(vec![NEAR.to_string()], Some((tiptokens / 16).into()));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR / 20);
+    assert_eq!(contract.get_reward_pool_balance(None).0, 15 * ONE_NEAR / 20);
+}
+
+#[test]
+#[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+fn test_redeem_tiptokens_without_yocto() {
+    let (mut context, mut contract) = setup();
+    claim_and_deposit_tiptokens(&mut context, &mut contract);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.redeem_tiptokens(vec![NEAR.to_string()], None);
+}
+
+fn usdc_config() -> TokenConfig {
+    TokenConfig {
+        decimals: 6,
+        min_deposit: 100_000.into(),
+        min_tip: 10_000.into(),
+        withdraw_commission: WithdrawCommission::Fixed(0.into()),
+        paused: false,
+    }
+}
+
+#[test]
+fn test_get_whitelisted_tokens() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, master(), 0);
+    contract.add_token("usdc.near".to_string(), usdc_config());
+
+    let tokens: Vec<TokenAccountId> = contract.get_whitelisted_tokens(None, None).into_iter().map(|(token_id, _)| token_id).collect();
+    assert_eq!(tokens, vec![NEAR, DAI, TIPTOKEN, "usdc.near"]);
+
+    let page = contract.get_whitelisted_tokens(Some(1), Some(2));
+    assert_eq!(page.len(), 2);
+    assert_eq!(page[0].0, DAI);
+    assert_eq!(page[0].1.min_deposit.0, MIN_DEPOSIT_FT);
+    assert_eq!(page[1].0, TIPTOKEN);
+    assert!(contract.get_whitelisted_tokens(Some(10), None).is_empty());
+
+    let near_config = contract.get_token_config(None).unwrap();
+    assert_eq!(near_config.decimals, 24);
+    assert_eq!(near_config.withdraw_commission, WithdrawCommission::Fixed(WITHDRAW_COMMISSION.into()));
+    assert!(contract.get_token_config(Some("unknown.near".to_string())).is_none());
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_add_token_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.add_token("usdc.near".to_string(), usdc_config());
+}
+
+#[test]
+#[should_panic(expected = "Token already added")]
+fn test_add_token_twice() {
+    let (_context, mut contract) = setup();
+    contract.add_token(DAI.to_string(), usdc_config());
+}
+
+#[test]
+fn test_deposit_below_token_minimum() {
+    let (mut context, mut contract) = setup();
+    contract.add_token("usdc.near".to_string(), usdc_config());
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, "usdc.near", accounts(1), 99_999, ""), 99_999);
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, "usdc.near", accounts(1), 100_000, ""), 0);
+    assert_eq!(contract.get_deposit(accounts(1), Some("usdc.near".to_string())).0, 100_000);
+}
+
+#[test]
+#[should_panic(expected = "Minimum tip is 10000")]
+fn test_send_tip_below_token_minimum() {
+    let (mut context, mut contract) = setup();
+    contract.add_token("usdc.near".to_string(), usdc_config());
+    deposit_ft(&mut context, &mut contract, "usdc.near", accounts(1), 100_000);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, 9_999.into(), None, Some("usdc.near".to_string()), None);
+}
+
+#[test]
+#[should_panic(expected = "Token is paused")]
+fn test_send_tip_with_paused_token() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_token_paused(DAI.to_string(), true);
+    assert!(contract.get_token_config(Some(DAI.to_string())).unwrap().paused);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_DAI.into(), None, Some(DAI.to_string()), None);
+}
+
+#[test]
+fn test_removed_token_stays_withdrawable() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_DAI.into(), None, Some(DAI.to_string()), None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.remove_token(DAI.to_string());
+    assert!(!contract.is_whitelisted_token(DAI.to_string()));
+    assert!(contract.get_token_config(Some(DAI.to_string())).is_none());
+
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), Some(DAI.to_string()), None);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 0);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(Some(DAI.to_string()), None);
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+}
+
+#[test]
+fn test_update_token_withdraw_commission() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    let mut config = contract.get_token_config(None).unwrap();
+    config.withdraw_commission = WithdrawCommission::Fixed((ONE_NEAR / 100).into());
+    contract.update_token(NEAR.to_string(), config);
+
+    contract.transfer_tips_to_deposit(TELEGRAM_ACCOUNT, accounts(2), None);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, ONE_NEAR - ONE_NEAR / 100);
+    assert_eq!(contract.get_commission_balance(None).0, ONE_NEAR / 100);
+}
+
+fn set_dai_commission(context: &mut VMContextBuilder, contract: &mut NearTips, withdraw_commission: WithdrawCommission) {
+    set_predecessor(context, master(), 0);
+    let mut config = contract.get_token_config(Some(DAI.to_string())).unwrap();
+    config.withdraw_commission = withdraw_commission;
+    contract.update_token(DAI.to_string(), config);
+}
+
+#[test]
+fn test_withdraw_from_telegram_ft_commission_rollback() {
+    let (mut context, mut contract) = setup();
+    set_dai_commission(&mut context, &mut contract, WithdrawCommission::BasisPoints(100));
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), Some(DAI.to_string()), None);
+    assert_eq!(contract.get_commission_balance(Some(DAI.to_string())).0, 5 * ONE_DAI / 100);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"withdraw","data":[{"account_id":"charlie","telegram_account":123,"token_id":"dai.near","amount":"4950000000000000000","fee":"50000000000000000"}]}"#]
+    );
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_balance(TELEGRAM_ACCOUNT, (5 * ONE_DAI - 5 * ONE_DAI / 100).into(), DAI.to_string(), Some((5 * ONE_DAI / 100).into()), None));
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 5 * ONE_DAI);
+    assert_eq!(contract.get_commission_balance(Some(DAI.to_string())).0, 0);
+}
+
+#[test]
+fn test_transfer_tips_to_deposit_ft_commission() {
+    let (mut context, mut contract) = setup();
+    set_dai_commission(&mut context, &mut contract, WithdrawCommission::Fixed(ONE_DAI.into()));
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.transfer_tips_to_deposit(TELEGRAM_ACCOUNT, accounts(2), Some(DAI.to_string()));
+    assert_eq!(contract.get_deposit(accounts(2), Some(DAI.to_string())).0, 4 * ONE_DAI);
+    assert_eq!(contract.get_commission_balance(Some(DAI.to_string())).0, ONE_DAI);
+}
+
+#[test]
+#[should_panic(expected = "Withdraw commission can't be greater then 100%")]
+fn test_withdraw_commission_above_100_percent() {
+    let (mut context, mut contract) = setup();
+    set_dai_commission(&mut context, &mut contract, WithdrawCommission::BasisPoints(10_001));
+}
+
+#[test]
+fn test_withdraw_commission() {
+    let (mut context, mut contract) = setup();
+    set_dai_commission(&mut context, &mut contract, WithdrawCommission::Fixed(ONE_DAI.into()));
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), None);
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), Some(DAI.to_string()), None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_commission(Some(DAI.to_string()));
+    assert_eq!(contract.get_commission_balance(Some(DAI.to_string())).0, 0);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"withdraw_commission","data":[{"account_id":"alice","token_id":"dai.near","amount":"1000000000000000000","fee":"0"}]}"#]
+    );
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_commission(ONE_DAI.into(), DAI.to_string()));
+    assert_eq!(contract.get_commission_balance(Some(DAI.to_string())).0, ONE_DAI);
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_withdraw_commission_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw_commission(None);
+}
+
+#[test]
+fn test_storage_deposit() {
+    let (mut context, mut contract) = setup();
+    assert!(contract.storage_balance_of(accounts(4)).is_none());
+
+    set_predecessor(&mut context, accounts(4), ONE_NEAR);
+    let storage_balance = contract.storage_deposit(None, Some(true));
+    assert_eq!(storage_balance.total, contract.storage_balance_bounds().min);
+    assert!(storage_balance.available.0 < storage_balance.total.0);
+
+    // registered account gets the whole deposit back with registration_only
+    let storage_balance = contract.storage_deposit(Some(accounts(4)), Some(true));
+    assert_eq!(storage_balance.total, contract.storage_balance_bounds().min);
+
+    let storage_balance = contract.storage_deposit(Some(accounts(4)), None);
+    assert_eq!(storage_balance.total.0, contract.storage_balance_bounds().min.0 + ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "Account eugene is not registered")]
+fn test_deposit_for_unregistered_account() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), ONE_NEAR);
+    contract.deposit(Some(accounts(4)));
+}
+
+#[test]
+fn test_tip_storage_is_paid_by_sender() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    let available = contract.storage_balance_of(accounts(1)).unwrap().available.0;
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (ONE_NEAR / 2).into(), None, None, None);
+    assert!(contract.storage_balance_of(accounts(1)).unwrap().available.0 < available);
+    let available = contract.storage_balance_of(accounts(1)).unwrap().available.0;
+
+    // existing telegram balance doesn't need new storage
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (ONE_NEAR / 4).into(), None, None, None);
+    assert_eq!(contract.storage_balance_of(accounts(1)).unwrap().available.0, available);
+}
+
+#[test]
+#[should_panic(expected = "Not enough storage balance for account eugene")]
+fn test_tips_exceeding_storage_balance() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(4), contract.storage_balance_bounds().min.0);
+    contract.storage_deposit(None, None);
+    deposit_near(&mut context, &mut contract, accounts(4), ONE_NEAR);
+
+    for telegram_account in 1..100 {
+        set_predecessor(&mut context, accounts(4), 0);
+        contract.send_tip_to_telegram(telegram_account, 1.into(), None, None, None);
+    }
+}
+
+#[test]
+fn test_storage_withdraw() {
+    let (mut context, mut contract) = setup();
+    let available = contract.storage_balance_of(accounts(1)).unwrap().available.0;
+
+    set_predecessor(&mut context, accounts(1), 1);
+    let storage_balance = contract.storage_withdraw(Some(1000.into()));
+    assert_eq!(storage_balance.available.0, available - 1000);
+
+    let storage_balance = contract.storage_withdraw(None);
+    assert_eq!(storage_balance.available.0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Not enough storage balance to withdraw")]
+fn test_storage_withdraw_above_available() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_withdraw(Some(STORAGE_DEPOSIT.into()));
+}
+
+#[test]
+fn test_storage_unregister() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw(None, None);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    assert!(contract.storage_unregister(None));
+    assert!(contract.storage_balance_of(accounts(1)).is_none());
+    assert!(contract.get_deposit_holders(None, None).is_empty());
+    assert!(!contract.storage_unregister(None));
+}
+
+#[test]
+#[should_panic(expected = "Can't unregister the account with the positive deposit")]
+fn test_storage_unregister_with_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), ONE_DAI);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_unregister(None);
+}
+
+#[test]
+#[should_panic(expected = "Can't unregister the account with the positive deposit")]
+fn test_storage_unregister_with_deposit_of_removed_token() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), ONE_DAI);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.remove_token(DAI.to_string());
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_unregister(None);
+}
+
+#[test]
+fn test_custom_fee_split() {
+    let (mut context, mut contract) = setup();
+    contract.add_chat_settings(CHAT_ID, chat_admin(), 10, true, Some(FeeSplit {
+        chat_admin: 5000,
+        sender: 0,
+        treasury: 5000,
+    }));
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 10 * ONE_DAI);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_DAI).into(), Some(CHAT_ID), Some(DAI.to_string()), None);
+
+    assert_eq!(contract.get_chat_tokens(CHAT_ID, Some(DAI.to_string())).0, ONE_DAI / 2);
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), Some(DAI.to_string())).0, 0);
+
+    let fee_breakdown = contract.get_fee_breakdown(Some(DAI.to_string()));
+    assert_eq!(fee_breakdown.token_id, DAI);
+    assert_eq!(fee_breakdown.chat_admin_collected.0, ONE_DAI / 2);
+    assert_eq!(fee_breakdown.sender_collected.0, 0);
+    assert_eq!(fee_breakdown.treasury_collected.0, ONE_DAI / 2);
+    assert_eq!(fee_breakdown.reward_pool.0, ONE_DAI / 2);
+    assert_eq!(fee_breakdown.treasury.0, ONE_DAI / 2);
+    assert_eq!(contract.get_fee_breakdown(None).treasury_collected.0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Fee split should sum up to 100%")]
+fn test_invalid_fee_split() {
+    let (_context, mut contract) = setup();
+    contract.add_chat_settings(CHAT_ID, chat_admin(), 10, true, Some(FeeSplit {
+        chat_admin: 5000,
+        sender: 5000,
+        treasury: 1,
+    }));
+}
+
+#[test]
+fn test_legacy_chat_settings_use_default_fee_split() {
+    let (_context, mut contract) = setup();
+    contract.chat_settings_v1.insert(&CHAT_ID, &ChatSettingsV1 {
+        admin_account_id: chat_admin().into(),
+        treasure_fee_numerator: 5,
+        track_chat_points: false,
+    });
+
+    let settings = contract.get_chat_settings(CHAT_ID).unwrap();
+    assert_eq!(settings.treasure_fee_numerator, 5);
+    assert_eq!(settings.fee_split, FeeSplit::default());
+
+    contract.delete_chat_settings(CHAT_ID);
+    assert!(contract.get_chat_settings(CHAT_ID).is_none());
+}
+
+fn tip_dai_in_chat(context: &mut VMContextBuilder, contract: &mut NearTips) -> Balance {
+    add_chat(context, contract, 10);
+    deposit_ft(context, contract, DAI, accounts(1), 10 * ONE_DAI);
+    set_predecessor(context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_DAI).into(), Some(CHAT_ID), Some(DAI.to_string()), None);
+    contract.get_user_tokens(accounts(1).into(), Some(DAI.to_string())).0
+}
+
+#[test]
+fn test_claim_tiptokens_for_ft() {
+    let (mut context, mut contract) = setup();
+    let points = tip_dai_in_chat(&mut context, &mut contract);
+    assert_eq!(points, 4 * ONE_DAI / 10);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tiptoken_rate(DAI.to_string(), 3.into(), 2.into());
+    assert_eq!(contract.convert_reward_points_to_tiptoken(points.into(), DAI.to_string()).0, 6 * ONE_DAI / 10);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(Some(DAI.to_string()));
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), Some(DAI.to_string())).0, 0);
+    assert_eq!(contract.get_total_tiptokens().0, 6 * ONE_DAI / 10);
+}
+
+#[test]
+fn test_claim_tiptokens_keeps_rounding_remainder() {
+    let (mut context, mut contract) = setup();
+    let points = tip_dai_in_chat(&mut context, &mut contract);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tiptoken_rate(DAI.to_string(), 1.into(), 3.into());
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(Some(DAI.to_string()));
+    assert_eq!(contract.get_total_tiptokens().0, points / 3);
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), Some(DAI.to_string())).0, points % 3);
+}
+
+#[test]
+fn test_claim_tiptokens_rollback_after_first_near_rate() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+    let points = contract.get_user_tokens(accounts(1).into(), None).0;
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(None);
+
+    // claim was made with the implicit 1:1 rate, it stays version 0
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tiptoken_rate(NEAR.to_string(), 1.into(), 2.into());
+    assert_eq!(contract.get_tiptoken_rate(None).unwrap().version, 1);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_claim_tiptokens(accounts(1).into(), points.into(), NEAR.to_string(), Some(0)));
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), None).0, points);
+}
+
+#[test]
+fn test_claim_tiptokens_rollback_uses_claim_rate() {
+    let (mut context, mut contract) = setup();
+    let points = tip_dai_in_chat(&mut context, &mut contract);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tiptoken_rate(DAI.to_string(), 3.into(), 2.into());
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(Some(DAI.to_string()));
+    let tiptokens = contract.get_total_tiptokens().0;
+
+    // the rate changes while the transfer is in flight
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tiptoken_rate(DAI.to_string(), 1.into(), 1.into());
+    assert_eq!(contract.get_tiptoken_rate(Some(DAI.to_string())).unwrap().version, 1);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.after_ft_transfer_claim_tiptokens(accounts(1).into(), tiptokens.into(), DAI.to_string(), Some(0)));
+    assert_eq!(contract.get_user_tokens(accounts(1).into(), Some(DAI.to_string())).0, points);
+    assert_eq!(contract.get_total_tiptokens().0, 0);
+}
+
+#[test]
+#[should_panic(expected = "TipToken rate for dai.near is not set")]
+fn test_claim_tiptokens_without_rate() {
+    let (mut context, mut contract) = setup();
+    tip_dai_in_chat(&mut context, &mut contract);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.claim_tiptokens(Some(DAI.to_string()));
+}
+
+#[test]
+#[should_panic(expected = "Positive rate needed")]
+fn test_set_zero_tiptoken_rate() {
+    let (_context, mut contract) = setup();
+    contract.set_tiptoken_rate(DAI.to_string(), 0.into(), 1.into());
+}
+
+#[test]
+fn test_get_deposit_holders() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    deposit_ft(&mut context, &mut contract, DAI, accounts(2), ONE_DAI);
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+
+    assert_eq!(contract.get_deposit_holders_count(), 2);
+    assert_eq!(contract.get_deposit_holders(None, None), vec![accounts(1).to_string(), accounts(2).to_string()]);
+    assert_eq!(contract.get_deposit_holders(Some(1), Some(10)), vec![accounts(2).to_string()]);
+    assert!(contract.get_deposit_holders(Some(2), None).is_empty());
+}
+
+#[test]
+fn test_get_telegram_balances() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT + 1, (2 * ONE_NEAR).into(), None, None, None);
+
+    assert_eq!(contract.get_telegram_balances(None, None, None),
+               vec![(TELEGRAM_ACCOUNT, ONE_NEAR.into()), (TELEGRAM_ACCOUNT + 1, (2 * ONE_NEAR).into())]);
+    assert_eq!(contract.get_telegram_balances(None, Some(1), Some(1)), vec![(TELEGRAM_ACCOUNT + 1, (2 * ONE_NEAR).into())]);
+    assert!(contract.get_telegram_balances(Some(DAI.to_string()), None, None).is_empty());
+
+    // claimed balances are removed from the index
+    set_predecessor(&mut context, master(), 0);
+    contract.transfer_tips_to_deposit(TELEGRAM_ACCOUNT, accounts(2), None);
+    assert_eq!(contract.get_telegram_balances(None, None, None), vec![(TELEGRAM_ACCOUNT + 1, (2 * ONE_NEAR).into())]);
+}
+
+#[test]
+fn test_get_chats() {
+    let (_context, mut contract) = setup();
+    contract.add_chat_settings(CHAT_ID, chat_admin(), 10, true, None);
+    contract.add_chat_settings(CHAT_ID + 1, chat_admin(), 5, false, None);
+    contract.add_chat_settings(CHAT_ID, chat_admin(), 8, true, None);
+
+    let chats = contract.get_chats(None, None);
+    assert_eq!(contract.get_chats_count(), 2);
+    assert_eq!(chats.iter().map(|(chat_id, settings)| (*chat_id, settings.treasure_fee_numerator)).collect::<Vec<_>>(),
+               vec![(CHAT_ID, 8), (CHAT_ID + 1, 5)]);
+
+    contract.delete_chat_settings(CHAT_ID);
+    assert_eq!(contract.get_chats(None, None).iter().map(|(chat_id, _)| *chat_id).collect::<Vec<_>>(), vec![CHAT_ID + 1]);
+}
+
+#[test]
+fn test_index_existing_records() {
+    let (_context, mut contract) = setup();
+    // records written before the indices were introduced
+    contract.deposits.insert(&TokenByNearAccount { account_id: accounts(1).into(), token_account_id: DAI.to_string() }, &ONE_DAI);
+    contract.telegram_tips.insert(&TokenByTelegramAccount { telegram_account: TELEGRAM_ACCOUNT, token_account_id: NEAR.to_string() }, &ONE_NEAR);
+    contract.chat_settings_v1.insert(&CHAT_ID, &ChatSettingsV1 {
+        admin_account_id: chat_admin().into(),
+        treasure_fee_numerator: 5,
+        track_chat_points: false,
+    });
+
+    contract.index_existing_records(
+        vec![accounts(1).into(), accounts(2).into()],
+        vec![TELEGRAM_ACCOUNT, TELEGRAM_ACCOUNT + 1],
+        vec![CHAT_ID, CHAT_ID + 1],
+        vec![NEAR.to_string(), DAI.to_string()],
+    );
+
+    assert_eq!(contract.get_deposit_holders(None, None), vec![accounts(1).to_string()]);
+    assert_eq!(contract.get_telegram_balances(None, None, None), vec![(TELEGRAM_ACCOUNT, ONE_NEAR.into())]);
+    assert_eq!(contract.get_chats(None, None).len(), 1);
+}
+
+#[test]
+fn test_index_existing_records_in_batches() {
+    let (_context, mut contract) = setup();
+    contract.deposits.insert(&TokenByNearAccount { account_id: accounts(1).into(), token_account_id: NEAR.to_string() }, &ONE_NEAR);
+    contract.deposits.insert(&TokenByNearAccount { account_id: accounts(1).into(), token_account_id: DAI.to_string() }, &ONE_DAI);
+    contract.tips.insert(&accounts(2).into(), &vec![Tip { contact: telegram_contact(TELEGRAM_ACCOUNT), amount: ONE_NEAR }]);
+
+    contract.index_existing_records(vec![accounts(1).into(), accounts(2).into()], vec![], vec![], vec![NEAR.to_string()]);
+    // the token missed by the first batch is added, the indexed records are not counted twice
+    contract.index_existing_records(vec![accounts(1).into(), accounts(2).into()], vec![], vec![], vec![NEAR.to_string(), DAI.to_string()]);
+
+    assert_eq!(contract.get_liabilities(None).deposits.0, ONE_NEAR);
+    assert_eq!(contract.get_liabilities(Some(DAI.to_string())).deposits.0, ONE_DAI);
+    assert_eq!(contract.get_liabilities(None).tips.0, ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_index_existing_records_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.index_existing_records(vec![], vec![], vec![], vec![]);
+}
+
+#[test]
+fn test_liabilities() {
+    let (mut context, mut contract) = setup();
+    add_chat(&mut context, &mut contract, 10);
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_NEAR).into(), Some(CHAT_ID), None, None);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.transfer_tips_to_deposit(TELEGRAM_ACCOUNT, accounts(2), None);
+
+    let liabilities = contract.get_liabilities(None);
+    assert_eq!(liabilities.deposits.0, 5 * ONE_NEAR + 45 * ONE_NEAR / 10 - WITHDRAW_COMMISSION);
+    assert_eq!(liabilities.telegram_balances.0, 0);
+    assert_eq!(liabilities.reward_pool.0, 4 * ONE_NEAR / 10);
+    assert_eq!(liabilities.treasure.0, ONE_NEAR / 10);
+    assert_eq!(liabilities.commissions.0, WITHDRAW_COMMISSION);
+    assert_eq!(liabilities.storage.0, 3 * STORAGE_DEPOSIT);
+    assert_eq!(liabilities.total.0, 10 * ONE_NEAR + 3 * STORAGE_DEPOSIT);
+}
+
+#[test]
+fn test_check_solvency_near() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 10 * ONE_NEAR);
+
+    testing_env!(context.predecessor_account_id(master()).attached_deposit(0).account_balance(20 * ONE_NEAR).build());
+    assert!(contract.check_solvency(Some(0), Some(1)));
+    assert!(get_logs().is_empty());
+
+    testing_env!(context.account_balance(10 * ONE_NEAR).build());
+    assert!(!contract.check_solvency(Some(0), Some(1)));
+    assert_eq!(get_logs(), vec![format!(
+        r#"EVENT_JSON:{{"standard":"tipbot","version":"1.0.0","event":"solvency_alert","data":[{{"token_id":"near","balance":"{}","liabilities":"{}","shortfall":"{}"}}]}}"#,
+        10 * ONE_NEAR, 10 * ONE_NEAR + 3 * STORAGE_DEPOSIT, 3 * STORAGE_DEPOSIT)]);
+}
+
+#[test]
+fn test_check_solvency_ft_callback() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 10 * ONE_DAI);
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&U128(10 * ONE_DAI)).unwrap()));
+    assert!(contract.on_ft_balance_of_check_solvency(DAI.to_string()));
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&U128(ONE_DAI)).unwrap()));
+    assert!(!contract.on_ft_balance_of_check_solvency(DAI.to_string()));
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.on_ft_balance_of_check_solvency(DAI.to_string()));
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_check_solvency_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.check_solvency(None, None);
+}
+
+fn create_giveaway(context: &mut VMContextBuilder, contract: &mut NearTips, amount: Balance, slots: u32, split: GiveawaySplit, expires_at: Option<u64>) -> GiveawayId {
+    deposit_ft(context, contract, DAI, accounts(1), amount);
+    set_predecessor(context, accounts(1), 0);
+    contract.create_giveaway(amount.into(), slots, Some(DAI.to_string()), split, Some(CHAT_ID), expires_at.map(|t| t.into()))
+}
+
+fn claim_giveaway(context: &mut VMContextBuilder, contract: &mut NearTips, giveaway_id: GiveawayId, telegram_account: TelegramAccountId) -> Balance {
+    set_predecessor(context, master(), 0);
+    contract.claim_giveaway(giveaway_id, telegram_account, Some(CHAT_ID)).0
+}
+
+#[test]
+fn test_giveaway_equal_split() {
+    let (mut context, mut contract) = setup();
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 3, GiveawaySplit::Equal, None);
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+    assert_eq!(contract.get_liabilities(Some(DAI.to_string())).giveaways.0, 10 * ONE_DAI);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"create_giveaway","data":[{"giveaway_id":0,"sender_account_id":"bob","token_id":"dai.near","amount":"10000000000000000000","slots":3,"chat_id":42}]}"#]
+    );
+    assert_eq!(contract.get_chat_giveaways(CHAT_ID, None, None).len(), 1);
+
+    assert_eq!(claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT), 10 * ONE_DAI / 3);
+    assert_eq!(claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT + 1), 10 * ONE_DAI / 3);
+    assert_eq!(contract.get_giveaway(giveaway_id).unwrap().claimed, 2);
+    // the last slot takes the rounding remainder
+    assert_eq!(claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT + 2), 10 * ONE_DAI - 2 * (10 * ONE_DAI / 3));
+
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, 10 * ONE_DAI / 3);
+    assert!(contract.get_giveaway(giveaway_id).is_none());
+    assert!(contract.get_chat_giveaways(CHAT_ID, None, None).is_empty());
+    let liabilities = contract.get_liabilities(Some(DAI.to_string()));
+    assert_eq!(liabilities.giveaways.0, 0);
+    assert_eq!(liabilities.telegram_balances.0, 10 * ONE_DAI);
+}
+
+#[test]
+fn test_giveaway_random_split() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, master(), 0);
+    contract.update_token(DAI.to_string(), TokenConfig { min_tip: ONE_DAI.into(), ..TokenConfig::default_for(&DAI.to_string()) });
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 5, GiveawaySplit::Random, None);
+
+    let mut total: Balance = 0;
+    for telegram_account in TELEGRAM_ACCOUNT..TELEGRAM_ACCOUNT + 5 {
+        let amount = claim_giveaway(&mut context, &mut contract, giveaway_id, telegram_account);
+        assert!(amount >= ONE_DAI);
+        assert_eq!(contract.get_balance(telegram_account, Some(DAI.to_string())).0, amount);
+        total += amount;
+    }
+    assert_eq!(total, 10 * ONE_DAI);
+}
+
+#[test]
+fn test_giveaway_random_split_without_min_tip() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    let giveaway_id = contract.create_giveaway(5.into(), 5, Some(DAI.to_string()), GiveawaySplit::Random, Some(CHAT_ID), None);
+
+    for telegram_account in TELEGRAM_ACCOUNT..TELEGRAM_ACCOUNT + 5 {
+        assert_eq!(claim_giveaway(&mut context, &mut contract, giveaway_id, telegram_account), 1);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens for 3 slots")]
+fn test_giveaway_with_less_tokens_than_slots() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.create_giveaway(2.into(), 3, Some(DAI.to_string()), GiveawaySplit::Equal, None, None);
+}
+
+#[test]
+#[should_panic(expected = "Minimum tip is 1000000000000000000")]
+fn test_giveaway_below_min_tip() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, master(), 0);
+    contract.update_token(DAI.to_string(), TokenConfig { min_tip: ONE_DAI.into(), ..TokenConfig::default_for(&DAI.to_string()) });
+    create_giveaway(&mut context, &mut contract, 2 * ONE_DAI, 3, GiveawaySplit::Equal, None);
+}
+
+#[test]
+#[should_panic(expected = "Giveaway was already claimed by 123")]
+fn test_claim_giveaway_twice() {
+    let (mut context, mut contract) = setup();
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 3, GiveawaySplit::Equal, None);
+    claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT);
+    claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT);
+}
+
+#[test]
+#[should_panic(expected = "Giveaway is restricted to chat 42")]
+fn test_claim_giveaway_in_another_chat() {
+    let (mut context, mut contract) = setup();
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 3, GiveawaySplit::Equal, None);
+    set_predecessor(&mut context, master(), 0);
+    contract.claim_giveaway(giveaway_id, TELEGRAM_ACCOUNT, Some(CHAT_ID + 1));
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_claim_giveaway_by_stranger() {
+    let (mut context, mut contract) = setup();
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 3, GiveawaySplit::Equal, None);
+    set_predecessor(&mut context, accounts(2), 0);
+    contract.claim_giveaway(giveaway_id, TELEGRAM_ACCOUNT, Some(CHAT_ID));
+}
+
+#[test]
+fn test_close_expired_giveaway() {
+    let (mut context, mut contract) = setup();
+    testing_env!(context.block_timestamp(1_000).build());
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 2, GiveawaySplit::Equal, Some(2_000));
+    claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT);
+
+    testing_env!(context.block_timestamp(2_000).build());
+    assert!(contract.get_chat_giveaways(CHAT_ID, None, None).is_empty());
+    assert_eq!(contract.get_giveaways(None, None).len(), 1);
+
+    // anyone can return the remainder of the expired giveaway to the sender
+    set_predecessor(&mut context, accounts(2), 0);
+    assert_eq!(contract.close_giveaway(giveaway_id).0, 5 * ONE_DAI);
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 5 * ONE_DAI);
+    assert!(contract.get_giveaways(None, None).is_empty());
+    assert_eq!(contract.get_liabilities(Some(DAI.to_string())).giveaways.0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Giveaway is not expired yet")]
+fn test_close_giveaway_before_expiry() {
+    let (mut context, mut contract) = setup();
+    testing_env!(context.block_timestamp(1_000).build());
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 2, GiveawaySplit::Equal, Some(2_000));
+    contract.close_giveaway(giveaway_id);
+}
+
+#[test]
+#[should_panic(expected = "Giveaway expired")]
+fn test_claim_expired_giveaway() {
+    let (mut context, mut contract) = setup();
+    testing_env!(context.block_timestamp(1_000).build());
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 2, GiveawaySplit::Equal, Some(2_000));
+    testing_env!(context.block_timestamp(2_000).build());
+    claim_giveaway(&mut context, &mut contract, giveaway_id, TELEGRAM_ACCOUNT);
+}
+
+#[test]
+fn test_close_giveaway_without_expiry_by_sender() {
+    let (mut context, mut contract) = setup();
+    let giveaway_id = create_giveaway(&mut context, &mut contract, 10 * ONE_DAI, 2, GiveawaySplit::Equal, None);
+    assert_eq!(contract.close_giveaway(giveaway_id).0, 10 * ONE_DAI);
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 10 * ONE_DAI);
+}
+
+#[test]
+fn test_ft_on_transfer_fund_giveaway() {
+    let (mut context, mut contract) = setup();
+    let msg = format!(r#"{{"action":"fund_giveaway","slots":2,"split":"random","chat_id":{}}}"#, CHAT_ID);
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), 5 * ONE_DAI, &msg), 0);
+
+    let giveaway = contract.get_giveaway(0).unwrap();
+    assert_eq!(giveaway.sender_account_id, accounts(1).to_string());
+    assert_eq!(giveaway.amount.0, 5 * ONE_DAI);
+    assert_eq!(giveaway.split, GiveawaySplit::Random);
+    assert_eq!(contract.get_deposit(accounts(1), Some(DAI.to_string())).0, 0);
+
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, r#"{"action":"fund_giveaway","slots":0,"split":"equal"}"#, "invalid_giveaway");
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, r#"{"action":"fund_giveaway","slots":101,"split":"equal"}"#, "invalid_giveaway");
+}
+
+fn send_expiring_tip(context: &mut VMContextBuilder, contract: &mut NearTips, amount: Balance, expires_at: u64) {
+    testing_env!(context.block_timestamp(1_000).build());
+    deposit_near(context, contract, accounts(1), amount);
+    set_predecessor(context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, amount.into(), None, None, Some(expires_at.into()));
+}
+
+#[test]
+fn test_reclaim_expired_tips() {
+    let (mut context, mut contract) = setup();
+    send_expiring_tip(&mut context, &mut contract, 2 * ONE_NEAR, 2_000);
+    deposit_near(&mut context, &mut contract, accounts(2), ONE_NEAR);
+    set_predecessor(&mut context, accounts(2), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+
+    let tips = contract.get_expiring_tips(TELEGRAM_ACCOUNT, None);
+    assert_eq!(tips.len(), 1);
+    assert_eq!(tips[0].sender_account_id, accounts(1).to_string());
+    assert_eq!(tips[0].amount.0, 2 * ONE_NEAR);
+    assert_eq!(tips[0].created_at.0, 1_000);
+
+    testing_env!(context.block_timestamp(2_000).build());
+    set_predecessor(&mut context, accounts(1), 0);
+    assert_eq!(contract.reclaim_expired_tips(TELEGRAM_ACCOUNT, None).0, 2 * ONE_NEAR);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert!(contract.get_expiring_tips(TELEGRAM_ACCOUNT, None).is_empty());
+    assert_eq!(contract.get_liabilities(None).telegram_balances.0, ONE_NEAR);
+    assert_eq!(
+        event_logs(),
+        vec![r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"reclaim_tips","data":[{"sender_account_id":"bob","telegram_account":123,"token_id":"near","amount":"2000000000000000000000000"}]}"#]
+    );
+}
+
+#[test]
+fn test_withdraw_claims_expiring_tips() {
+    let (mut context, mut contract) = setup();
+    send_expiring_tip(&mut context, &mut contract, 2 * ONE_NEAR, 2_000);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), None, Some(ONE_NEAR.into()));
+    assert_eq!(contract.get_expiring_tips(TELEGRAM_ACCOUNT, None)[0].amount.0, ONE_NEAR);
+
+    testing_env!(context.block_timestamp(2_000).build());
+    set_predecessor(&mut context, accounts(1), 0);
+    assert_eq!(contract.reclaim_expired_tips(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 0);
+}
+
+#[test]
+fn test_withdraw_rollback_restores_expiring_tips() {
+    let (mut context, mut contract) = setup();
+    send_expiring_tip(&mut context, &mut contract, 2 * ONE_NEAR, 2_000);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.withdraw_from_telegram(TELEGRAM_ACCOUNT, accounts(2), None, Some(ONE_NEAR.into()));
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    let claimed_tips = vec![ExpiringTipOutput {
+        sender_account_id: accounts(1).into(),
+        amount: ONE_NEAR.into(),
+        created_at: 1_000.into(),
+        expires_at: 2_000.into(),
+    }];
+    assert!(!contract.after_ft_transfer_balance(TELEGRAM_ACCOUNT, ONE_NEAR.into(), NEAR.to_string(), Some(0.into()), Some(claimed_tips)));
+    let tips = contract.get_expiring_tips(TELEGRAM_ACCOUNT, None);
+    assert_eq!(tips.len(), 1);
+    assert_eq!(tips[0].amount.0, 2 * ONE_NEAR);
+
+    testing_env!(context.block_timestamp(2_000).build());
+    set_predecessor(&mut context, accounts(1), 0);
+    assert_eq!(contract.reclaim_expired_tips(TELEGRAM_ACCOUNT, None).0, 2 * ONE_NEAR);
+}
+
+#[test]
+fn test_transfer_tips_to_deposit_claims_expiring_tips() {
+    let (mut context, mut contract) = setup();
+    send_expiring_tip(&mut context, &mut contract, 2 * ONE_NEAR, 2_000);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.transfer_tips_to_deposit(TELEGRAM_ACCOUNT, accounts(2), None);
+    assert!(contract.get_expiring_tips(TELEGRAM_ACCOUNT, None).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "No expired tips to reclaim")]
+fn test_reclaim_tips_before_expiry() {
+    let (mut context, mut contract) = setup();
+    send_expiring_tip(&mut context, &mut contract, 2 * ONE_NEAR, 2_000);
+    contract.reclaim_expired_tips(TELEGRAM_ACCOUNT, None);
+}
+
+#[test]
+fn test_ft_on_transfer_without_storage() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_withdraw(None);
+
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, "", "insufficient_storage");
+}
+
+#[test]
+fn test_ft_on_transfer_chat_tip_storage_estimate() {
+    let (mut context, mut contract) = setup();
+    contract.add_chat_settings(CHAT_ID, chat_admin(), 10, true, None);
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), ONE_DAI);
+
+    // enough for the telegram balance, not for the chat points and tiptokens of the chat tip
+    set_predecessor(&mut context, accounts(1), 1);
+    let available = contract.storage_balance_of(accounts(1)).unwrap().available.0;
+    contract.storage_withdraw(Some((available - 800 * env::storage_byte_cost()).into()));
+
+    let chat_tip_msg = format!(r#"{{"action":"tip_telegram","telegram_account":{},"chat_id":{}}}"#, TELEGRAM_ACCOUNT, CHAT_ID);
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), ONE_DAI, &chat_tip_msg), ONE_DAI);
+    assert!(get_logs()[0].contains(r#""reason":"insufficient_storage""#));
+
+    let tip_msg = format!(r#"{{"action":"tip_telegram","telegram_account":{}}}"#, TELEGRAM_ACCOUNT);
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), ONE_DAI, &tip_msg), 0);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string())).0, ONE_DAI);
+}
+
+#[test]
+fn test_ft_on_transfer_expiring_tip_rejections() {
+    let (mut context, mut contract) = setup();
+    testing_env!(context.block_timestamp(1_000).build());
+    let msg = |expires_at: u64| format!(r#"{{"action":"tip_telegram","telegram_account":{},"expires_at":"{}"}}"#, TELEGRAM_ACCOUNT, expires_at);
+
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, &msg(1_000), "invalid_expiration");
+
+    for _ in 0..10 {
+        assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(1), ONE_DAI, &msg(2_000)), 0);
+    }
+    assert_transfer_rejected(&mut context, &mut contract, accounts(1), ONE_DAI, &msg(2_000), "too_many_expiring_tips");
+
+    // the limit is per sender
+    assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, accounts(2), ONE_DAI, &msg(2_000)), 0);
+    assert_eq!(contract.get_expiring_tips(TELEGRAM_ACCOUNT, Some(DAI.to_string())).len(), 11);
+}
+
+#[test]
+fn test_ft_on_transfer_expiring_tips_total_limit() {
+    let (mut context, mut contract) = setup();
+    testing_env!(context.block_timestamp(1_000).build());
+    let msg = format!(r#"{{"action":"tip_telegram","telegram_account":{},"expires_at":"2000"}}"#, TELEGRAM_ACCOUNT);
+    for account_id in [accounts(0), accounts(4), accounts(5)] {
+        set_predecessor(&mut context, account_id, STORAGE_DEPOSIT);
+        contract.storage_deposit(None, None);
+    }
+
+    for account_id in [accounts(0), accounts(1), accounts(2), accounts(3), accounts(4)] {
+        for _ in 0..10 {
+            assert_eq!(ft_transfer_call(&mut context, &mut contract, DAI, account_id.clone(), ONE_DAI, &msg), 0);
+        }
+    }
+    assert_transfer_rejected(&mut context, &mut contract, accounts(5), ONE_DAI, &msg, "too_many_expiring_tips");
+    assert_eq!(contract.get_expiring_tips(TELEGRAM_ACCOUNT, Some(DAI.to_string())).len(), 50);
+}
+
+#[test]
+#[should_panic(expected = "Tip expiration should be in the future")]
+fn test_tip_expiration_in_the_past() {
+    let (mut context, mut contract) = setup();
+    send_expiring_tip(&mut context, &mut contract, 2 * ONE_NEAR, 1_000);
+}
+
+#[test]
+fn test_tip_history() {
+    let (mut context, mut contract) = setup();
+    testing_env!(context.block_timestamp(1_000).build());
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    deposit_near(&mut context, &mut contract, accounts(2), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+    contract.send_tips_to_telegram(vec![(TELEGRAM_ACCOUNT, ONE_NEAR.into()), (TELEGRAM_ACCOUNT + 1, ONE_NEAR.into())], Some(CHAT_ID), None);
+    set_predecessor(&mut context, accounts(2), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT + 1, ONE_NEAR.into(), None, None, None);
+
+    assert_eq!(contract.get_tip_history(None, None).len(), 4);
+    let tips = contract.get_tips_sent(accounts(1), None, None);
+    assert_eq!(tips.iter().map(|tip| tip.tip_id).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(tips[1].telegram_account, Some(TELEGRAM_ACCOUNT));
+    assert_eq!(tips[1].chat_id, Some(CHAT_ID));
+    assert_eq!(tips[1].amount.0, ONE_NEAR);
+    assert_eq!(tips[1].timestamp.0, 1_000);
+
+    let tips = contract.get_tips_received(TELEGRAM_ACCOUNT + 1, Some(1), Some(1));
+    assert_eq!(tips.len(), 1);
+    assert_eq!(tips[0].tip_id, 3);
+    assert_eq!(tips[0].sender_account_id, accounts(2).to_string());
+    assert!(contract.get_tips_received(TELEGRAM_ACCOUNT + 2, None, None).is_empty());
+}
+
+#[test]
+fn test_tip_history_limit() {
+    let (mut context, mut contract) = setup();
+    contract.set_tip_history_limit(2);
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    for telegram_account in TELEGRAM_ACCOUNT..TELEGRAM_ACCOUNT + 3 {
+        contract.send_tip_to_telegram(telegram_account, ONE_NEAR.into(), None, None, None);
+    }
+
+    assert_eq!(contract.get_tip_history(None, None).iter().map(|tip| tip.tip_id).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(contract.get_tips_sent(accounts(1), None, None).len(), 2);
+    assert!(contract.get_tips_received(TELEGRAM_ACCOUNT, None, None).is_empty());
+
+    // disabled history removes existing records and their indices
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tip_history_limit(0);
+    assert!(contract.get_tip_history(None, None).is_empty());
+    assert!(contract.get_tips_sent(accounts(1), None, None).is_empty());
+    assert!(contract.get_tips_received(TELEGRAM_ACCOUNT + 2, None, None).is_empty());
+
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+    assert!(contract.get_tip_history(None, None).is_empty());
+}
+
+#[test]
+fn test_disabled_tip_history_is_pruned_by_tips() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 200 * ONE_NEAR);
+    for _ in 0..104 {
+        set_predecessor(&mut context, accounts(1), 0);
+        contract.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None, None);
+    }
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tip_history_limit(0);
+    assert_eq!(contract.get_tip_history(None, Some(10)).len(), 4);
+
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tips_to_telegram(vec![(TELEGRAM_ACCOUNT, (ONE_NEAR / 2).into()), (TELEGRAM_ACCOUNT + 1, (ONE_NEAR / 2).into())], None, None);
+    assert!(contract.get_tip_history(None, None).is_empty());
+    assert!(contract.get_tips_received(TELEGRAM_ACCOUNT, None, None).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_set_tip_history_limit_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.set_tip_history_limit(2);
+}
+
+const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+fn create_subscription(context: &mut VMContextBuilder, contract: &mut NearTips, recipient: SubscriptionRecipient, max_payments: u32) -> SubscriptionId {
+    testing_env!(context.block_timestamp(HOUR).build());
+    set_predecessor(context, accounts(1), 0);
+    contract.create_subscription(recipient, ONE_NEAR.into(), None, HOUR.into(), max_payments, None)
+}
+
+#[test]
+fn test_execute_due_subscriptions() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    let subscription_id = create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), 2);
+
+    set_predecessor(&mut context, accounts(2), 0);
+    assert_eq!(contract.execute_due_subscriptions(None), 1);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert_eq!(contract.get_tips_sent(accounts(1), None, None).len(), 1);
+
+    // not due yet
+    assert_eq!(contract.execute_due_subscriptions(None), 0);
+
+    // missed periods are skipped
+    testing_env!(context.block_timestamp(4 * HOUR + 1).build());
+    assert_eq!(contract.execute_due_subscriptions(None), 1);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert!(contract.get_subscription(subscription_id).is_none());
+    assert!(contract.get_account_subscriptions(accounts(1)).is_empty());
+    assert_eq!(
+        event_logs().last().unwrap(),
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"close_subscription","data":[{"subscription_id":0,"payments_made":2}]}"#
+    );
+}
+
+#[test]
+fn test_subscription_to_account() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    deposit_near(&mut context, &mut contract, accounts(2), ONE_NEAR);
+    create_subscription(&mut context, &mut contract, SubscriptionRecipient::Account(accounts(2).to_string()), 3);
+
+    assert_eq!(contract.execute_due_subscriptions(Some(5)), 1);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 0);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_tips_sent(accounts(1), None, None)[0].receiver_account_id, Some(accounts(2).to_string()));
+}
+
+#[test]
+fn test_subscription_with_insufficient_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR / 2);
+    let subscription_id = create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), 2);
+
+    assert_eq!(contract.execute_due_subscriptions(None), 0);
+    let subscription = contract.get_subscription(subscription_id).unwrap();
+    assert!(subscription.insufficient_deposit);
+    assert_eq!(subscription.payments_made, 0);
+    assert_eq!(
+        event_logs().last().unwrap(),
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"skip_subscription","data":[{"subscription_id":0,"reason":"insufficient_deposit"}]}"#
+    );
+
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    assert_eq!(contract.execute_due_subscriptions(None), 1);
+    let subscription = contract.get_subscription(subscription_id).unwrap();
+    assert!(!subscription.insufficient_deposit);
+    assert_eq!(subscription.next_payment_at.0, 2 * HOUR);
+}
+
+#[test]
+fn test_execute_due_subscriptions_limit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    for telegram_account in TELEGRAM_ACCOUNT..TELEGRAM_ACCOUNT + 3 {
+        create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(telegram_account), 1);
+    }
+
+    assert_eq!(contract.execute_due_subscriptions(Some(2)), 2);
+    assert_eq!(contract.get_subscriptions(None, None).len(), 1);
+    assert_eq!(contract.execute_due_subscriptions(Some(2)), 1);
+    assert!(contract.get_subscriptions(None, None).is_empty());
+}
+
+#[test]
+fn test_cancel_subscription() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    let subscription_id = create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), 2);
+    assert_eq!(contract.get_account_subscriptions(accounts(1)).len(), 1);
+
+    contract.cancel_subscription(subscription_id);
+    assert!(contract.get_subscription(subscription_id).is_none());
+    assert_eq!(contract.execute_due_subscriptions(None), 0);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_cancel_subscription_by_stranger() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    let subscription_id = create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), 2);
+    set_predecessor(&mut context, accounts(2), 0);
+    contract.cancel_subscription(subscription_id);
+}
+
+#[test]
+#[should_panic(expected = "Account eugene is not registered")]
+fn test_subscription_to_unregistered_account() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    create_subscription(&mut context, &mut contract, SubscriptionRecipient::Account(accounts(4).to_string()), 2);
+}
+
+fn send_tip_with_auth(context: &mut VMContextBuilder, contract: &mut NearTips, amount: Balance) {
+    set_predecessor(context, accounts(1), 0);
+    contract.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, amount.into(), None, None);
+}
+
+#[test]
+fn test_send_tip_with_auth_locks_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, 2 * ONE_NEAR);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_liabilities(None).reserved.0, 2 * ONE_NEAR);
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(accounts(2).to_string())).unwrap()));
+    contract.on_get_contact_owner_on_send_tip_to_telegram_with_auth(0, TELEGRAM_ACCOUNT, None);
+
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_liabilities(None).reserved.0, 0);
+    assert!(contract.get_reservation(0).is_none());
+}
+
+#[test]
+#[should_panic(expected = "Not enough tokens to tip")]
+fn test_send_tip_with_auth_double_spend() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, 2 * ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, 2 * ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "Can't unregister the account with locked funds")]
+fn test_storage_unregister_with_locked_balance_of_removed_token() {
+    let (mut context, mut contract) = setup();
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, ONE_DAI.into(), None, Some(DAI.to_string()));
+
+    set_predecessor(&mut context, master(), 0);
+    contract.remove_token(DAI.to_string());
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_unregister(None);
+}
+
+#[test]
+fn test_send_tip_with_auth_failure_falls_back_to_telegram_tip() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, 2 * ONE_NEAR);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    contract.on_get_contact_owner_on_send_tip_to_telegram_with_auth(0, TELEGRAM_ACCOUNT, None);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 2 * ONE_NEAR);
+    assert_eq!(
+        event_logs()[0],
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"auth_failed","data":[{"method":"send_tip_to_telegram_with_auth","account_id":"bob","contact":{"category":"Telegram","value":"","account_id":123},"reason":"call_failed"}]}"#
+    );
+}
+
+#[test]
+fn test_tip_contact_to_deposit_with_malformed_auth_response() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None);
+
+    set_callback_result(&mut context, PromiseResult::Successful(b"42".to_vec()));
+    assert!(!contract.on_get_contact_owner_on_tip_contact_to_deposit(0, Contact {
+        category: ContactCategories::Telegram,
+        value: "".to_string(),
+        account_id: Some(TELEGRAM_ACCOUNT),
+    }));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 3 * ONE_NEAR);
+    assert!(event_logs()[0].contains(r#""reason":"malformed_response""#));
+}
+
+#[test]
+fn test_withdraw_with_auth_failure() {
+    let (mut context, mut contract) = setup();
+    set_callback_result(&mut context, PromiseResult::Failed);
+    let result = contract.on_get_contact_owner_on_withdraw_from_telegram_with_auth(accounts(1).to_string(), Contact {
+        category: ContactCategories::Telegram,
+        value: "".to_string(),
+        account_id: Some(TELEGRAM_ACCOUNT),
+    }, None, None);
+    assert!(matches!(result, PromiseOrValue::Value(false)));
+    assert!(event_logs()[0].contains(r#""method":"withdraw_from_telegram_with_auth""#));
+}
+
+#[test]
+fn test_tip_contact_to_deposit_without_owner_releases_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, ONE_NEAR);
+
+    assert_eq!(contract.get_reservation(0).unwrap().amount.0, ONE_NEAR);
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&None::<AccountId>).unwrap()));
+    assert!(!contract.on_get_contact_owner_on_tip_contact_to_deposit(0, Contact {
+        category: ContactCategories::Telegram,
+        value: "".to_string(),
+        account_id: Some(TELEGRAM_ACCOUNT),
+    }));
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 3 * ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+}
+
+#[test]
+fn test_release_expired_reservation() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, 2 * ONE_NEAR);
+
+    testing_env!(context.block_timestamp(HOUR).predecessor_account_id(accounts(2)).build());
+    assert_eq!(contract.release_expired_reservation(0).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, 3 * ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+}
+
+#[test]
+#[should_panic(expected = "Reservation is not expired yet")]
+fn test_release_reservation_before_expiry() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, 2 * ONE_NEAR);
+    contract.release_expired_reservation(0);
+}
+
+#[test]
+fn test_withdraw_tip_reserves_tip() {
+    let (mut context, mut contract) = setup();
+    contract.set_generic_tips_available(true);
+    let contact = Contact {
+        category: ContactCategories::Github,
+        value: "bob".to_string(),
+        account_id: None,
+    };
+    set_predecessor(&mut context, accounts(2), ONE_NEAR);
+    contract.tip_with_attached_tokens(accounts(1).to_string(), contact.clone());
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw_tip(contact.clone());
+    assert_eq!(contract.get_tip_by_contact(accounts(1).to_string(), contact.clone()).0, 0);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, ONE_NEAR);
+    // nothing is left for a concurrent withdrawal
+    assert!(matches!(contract.withdraw_tip(contact.clone()), PromiseOrValue::Value(false)));
+
+    // failed transfer returns the tip
+    set_callback_result(&mut context, PromiseResult::Failed);
+    assert!(!contract.on_withdraw_tip(0, accounts(1).to_string()));
+    assert_eq!(contract.get_tip_by_contact(accounts(1).to_string(), contact).0, ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+    assert_eq!(contract.get_liabilities(None).tips.0, ONE_NEAR);
+}
+
+#[test]
+fn test_identity_providers() {
+    let (_context, mut contract) = setup();
+    assert_eq!(contract.get_identity_provider(ContactCategories::Github), "auth.near");
+
+    contract.set_identity_provider(ContactCategories::Github, accounts(4));
+    assert_eq!(contract.get_identity_provider(ContactCategories::Github), accounts(4).to_string());
+    assert_eq!(contract.get_identity_provider(ContactCategories::Telegram), "auth.near");
+    assert_eq!(contract.get_identity_providers(), vec![(ContactCategories::Github, accounts(4).to_string())]);
+
+    contract.remove_identity_provider(ContactCategories::Github);
+    assert_eq!(contract.get_identity_provider(ContactCategories::Github), "auth.near");
+    assert!(contract.get_identity_providers().is_empty());
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_set_identity_provider_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.set_identity_provider(ContactCategories::Github, accounts(4));
+}
+
+fn resolve_contact_owner(context: &mut VMContextBuilder, contract: &mut NearTips, owner: Option<ValidAccountId>) {
+    send_tip_with_auth(context, contract, ONE_NEAR);
+    set_callback_result(context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&owner.map(String::from)).unwrap()));
+    contract.on_get_contact_owner_on_send_tip_to_telegram_with_auth(0, TELEGRAM_ACCOUNT, None);
+}
+
+#[test]
+fn test_send_tip_with_auth_to_cached_owner() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    resolve_contact_owner(&mut context, &mut contract, Some(accounts(2)));
+    assert_eq!(contract.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT)), Some(accounts(2).to_string()));
+
+    send_tip_with_auth(&mut context, &mut contract, ONE_NEAR);
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, 2 * ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+    assert!(contract.get_reservation(1).is_none());
+}
+
+#[test]
+fn test_cached_contact_owner_expires() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    set_predecessor(&mut context, master(), 0);
+    contract.set_contact_owner_ttl(HOUR.into());
+    resolve_contact_owner(&mut context, &mut contract, Some(accounts(2)));
+
+    testing_env!(context.block_timestamp(HOUR).build());
+    assert!(contract.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT)).is_none());
+
+    // stale owner is checked by the auth contract again
+    send_tip_with_auth(&mut context, &mut contract, ONE_NEAR);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, ONE_NEAR);
+    assert!(contract.get_reservation(1).is_some());
+}
+
+#[test]
+fn test_missing_contact_owner_drops_cached_one() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    resolve_contact_owner(&mut context, &mut contract, Some(accounts(2)));
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None);
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&None::<String>).unwrap()));
+    contract.on_get_contact_owner_on_tip_contact_to_deposit(1, telegram_contact(TELEGRAM_ACCOUNT));
+
+    assert!(contract.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT)).is_none());
+}
+
+#[test]
+fn test_identity_provider_change_drops_cached_owner() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    resolve_contact_owner(&mut context, &mut contract, Some(accounts(2)));
+
+    set_predecessor(&mut context, master(), 0);
+    contract.set_identity_provider(ContactCategories::Telegram, accounts(4));
+    assert!(contract.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT)).is_none());
+}
+
+#[test]
+fn test_contact_owner_cache_disabled() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    set_predecessor(&mut context, master(), 0);
+    contract.set_contact_owner_ttl(0.into());
+    resolve_contact_owner(&mut context, &mut contract, Some(accounts(2)));
+
+    assert!(contract.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT)).is_none());
+}
+
+#[test]
+fn test_notify_contact_changed() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    resolve_contact_owner(&mut context, &mut contract, Some(accounts(2)));
+
+    set_predecessor(&mut context, ValidAccountId::try_from("auth.near").unwrap(), 0);
+    assert!(contract.notify_contact_changed(telegram_contact(TELEGRAM_ACCOUNT)));
+    assert!(!contract.notify_contact_changed(telegram_contact(TELEGRAM_ACCOUNT)));
+    assert!(contract.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT)).is_none());
+}
+
+#[test]
+#[should_panic(expected = "No access")]
+fn test_notify_contact_changed_by_stranger() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.notify_contact_changed(telegram_contact(TELEGRAM_ACCOUNT));
+}
+
+#[test]
+fn test_subscription_with_removed_token_is_skipped() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    deposit_ft(&mut context, &mut contract, DAI, accounts(1), 3 * ONE_DAI);
+    set_predecessor(&mut context, accounts(1), 0);
+    let broken_subscription_id = contract.create_subscription(SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), ONE_DAI.into(), Some(DAI.to_string()), HOUR.into(), 2, None);
+    create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), 2);
+
+    set_predecessor(&mut context, master(), 0);
+    contract.remove_token(DAI.to_string());
+
+    assert_eq!(contract.execute_due_subscriptions(None), 1);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, ONE_NEAR);
+    assert_eq!(contract.get_subscription(broken_subscription_id).unwrap().payments_made, 0);
+    assert_eq!(
+        event_logs().last().unwrap(),
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"skip_subscription","data":[{"subscription_id":0,"reason":"token_not_whitelisted"}]}"#
+    );
+}
+
+#[test]
+fn test_subscription_without_storage_is_skipped() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), 3 * ONE_NEAR);
+    deposit_near(&mut context, &mut contract, accounts(2), 3 * ONE_NEAR);
+    create_subscription(&mut context, &mut contract, SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT), 2);
+    testing_env!(context.predecessor_account_id(accounts(2)).build());
+    contract.create_subscription(SubscriptionRecipient::Telegram(TELEGRAM_ACCOUNT + 1), ONE_NEAR.into(), None, HOUR.into(), 2, None);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_withdraw(None);
+
+    assert_eq!(contract.execute_due_subscriptions(None), 1);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 0);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT + 1, None).0, ONE_NEAR);
+    assert_eq!(
+        event_logs().last().unwrap(),
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"skip_subscription","data":[{"subscription_id":0,"reason":"insufficient_storage"}]}"#
+    );
+}
+
+#[test]
+fn test_send_tip_with_auth_below_min_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    send_tip_with_auth(&mut context, &mut contract, ONE_NEAR / 100);
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(accounts(2).to_string())).unwrap()));
+    contract.on_get_contact_owner_on_send_tip_to_telegram_with_auth(0, TELEGRAM_ACCOUNT, None);
+
+    assert_eq!(contract.get_deposit(accounts(2), None).0, ONE_NEAR / 100);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+
+    // cached owner gets the same tip without the callback
+    send_tip_with_auth(&mut context, &mut contract, ONE_NEAR / 100);
+    assert_eq!(contract.get_deposit(accounts(2), None).0, 2 * ONE_NEAR / 100);
+}
+
+#[test]
+fn test_tip_contact_to_deposit_without_storage_releases_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_withdraw(None);
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None);
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(accounts(4).to_string())).unwrap()));
+    assert!(!contract.on_get_contact_owner_on_tip_contact_to_deposit(0, telegram_contact(TELEGRAM_ACCOUNT)));
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_deposit(accounts(4), None).0, 0);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+}
+
+#[test]
+fn test_send_tip_with_auth_fallback_without_storage_releases_deposit() {
+    let (mut context, mut contract) = setup();
+    deposit_near(&mut context, &mut contract, accounts(1), ONE_NEAR);
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_withdraw(None);
+    send_tip_with_auth(&mut context, &mut contract, ONE_NEAR);
+
+    set_callback_result(&mut context, PromiseResult::Failed);
+    contract.on_get_contact_owner_on_send_tip_to_telegram_with_auth(0, TELEGRAM_ACCOUNT, None);
+
+    assert_eq!(contract.get_deposit(accounts(1), None).0, ONE_NEAR);
+    assert_eq!(contract.get_balance(TELEGRAM_ACCOUNT, None).0, 0);
+    assert_eq!(contract.get_locked_balance(accounts(1), None).0, 0);
+}
+
+#[test]
+fn test_attached_tokens_recorded_after_tips_paused() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, master(), 0);
+    contract.set_tip_available(false);
+
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(accounts(2).to_string())).unwrap()));
+    assert!(contract.on_get_contact_owner_on_tip_contact_with_attached_tokens(accounts(1).to_string(), telegram_contact(TELEGRAM_ACCOUNT), ONE_NEAR));
+
+    assert_eq!(contract.get_tips(accounts(2).to_string()).unwrap()[0].amount, ONE_NEAR);
+    assert_eq!(contract.get_liabilities(None).tips.0, ONE_NEAR);
+}
+
+#[test]
+#[should_panic(expected = "Can't unregister the account with open giveaways")]
+fn test_storage_unregister_with_open_giveaway() {
+    let (mut context, mut contract) = setup();
+    create_giveaway(&mut context, &mut contract, 2 * ONE_DAI, 2, GiveawaySplit::Equal, None);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    contract.storage_unregister(None);
+}
+
+#[test]
+fn test_storage_unregister_after_giveaway_is_claimed() {
+    let (mut context, mut contract) = setup();
+    let first_giveaway_id = create_giveaway(&mut context, &mut contract, 2 * ONE_DAI, 1, GiveawaySplit::Equal, None);
+    let second_giveaway_id = create_giveaway(&mut context, &mut contract, 2 * ONE_DAI, 1, GiveawaySplit::Equal, None);
+    claim_giveaway(&mut context, &mut contract, first_giveaway_id, TELEGRAM_ACCOUNT);
+    claim_giveaway(&mut context, &mut contract, second_giveaway_id, TELEGRAM_ACCOUNT);
+
+    set_predecessor(&mut context, accounts(1), 1);
+    assert!(contract.storage_unregister(None));
+}
+
+#[test]
+fn test_withdraw_tip_for_undefined_account_event() {
+    let (mut context, mut contract) = setup();
+    set_predecessor(&mut context, master(), 0);
+    contract.set_generic_tips_available(true);
+    let contact = Contact {
+        category: ContactCategories::Github,
+        value: "bob".to_string(),
+        account_id: None,
+    };
+    // tip is parked for the undefined account
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&None::<String>).unwrap()));
+    contract.on_get_contact_owner_on_tip_contact_with_attached_tokens(accounts(2).to_string(), contact.clone(), ONE_NEAR);
+
+    set_predecessor(&mut context, accounts(1), 0);
+    contract.withdraw_tip(contact.clone());
+    set_callback_result(&mut context, PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(accounts(1).to_string())).unwrap()));
+    contract.on_get_contact_owner_on_withdraw_tip_for_undefined_account(0, accounts(1).to_string(), contact);
+
+    set_callback_result(&mut context, PromiseResult::Successful(vec![]));
+    assert!(contract.on_withdraw_tip(0, accounts(1).to_string()));
+    assert_eq!(
+        event_logs().last().unwrap(),
+        r#"EVENT_JSON:{"standard":"tipbot","version":"1.0.0","event":"withdraw","data":[{"account_id":"bob","token_id":"near","amount":"1000000000000000000000000","fee":"0"}]}"#
+    );
+}
@@ -0,0 +1,194 @@
+use crate::*;
+use near_sdk::Timestamp;
+
+/* TIP HISTORY, append-only log of the latest tips with per-sender and per-recipient indices */
+pub type TipRecordId = u64;
+
+pub(crate) const DEFAULT_TIP_HISTORY_LIMIT: u64 = 10_000;
+// oldest records removed by a single tip, lowered retention limit is reached over the next tips
+const MAX_PRUNED_TIP_RECORDS: u64 = 2;
+// oldest records removed by set_tip_history_limit, the rest is pruned by the next tips
+const MAX_PRUNED_TIP_RECORDS_ON_LIMIT_UPDATE: u64 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TipRecord {
+    pub sender_account_id: AccountId,
+    pub telegram_account: Option<TelegramAccountId>,
+    pub receiver_account_id: Option<AccountId>,
+    pub contact: Option<Contact>,
+    pub token_id: TokenAccountId,
+    pub amount: Balance,
+    pub fee: Balance,
+    pub chat_id: Option<TelegramChatId>,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TipRecordOutput {
+    pub tip_id: TipRecordId,
+    pub sender_account_id: AccountId,
+    pub telegram_account: Option<TelegramAccountId>,
+    pub receiver_account_id: Option<AccountId>,
+    pub contact: Option<Contact>,
+    pub token_id: TokenAccountId,
+    pub amount: WrappedBalance,
+    pub fee: WrappedBalance,
+    pub chat_id: Option<TelegramChatId>,
+    pub timestamp: WrappedTimestamp,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone)]
+pub enum TipHistoryIndex {
+    Sent(AccountId),
+    Received(TelegramAccountId),
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TipHistoryIndexEntry {
+    pub index: TipHistoryIndex,
+    pub position: u64,
+}
+
+// positions of the retained records, start is the oldest one
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct TipHistoryRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl TipHistoryRange {
+    fn len(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+#[near_bindgen]
+impl NearTips {
+    // storage of the history is paid by the contract and bounded by the limit, 0 disables the history
+    pub fn set_tip_history_limit(&mut self, limit: u64) {
+        self.assert_master_account_id();
+        self.tip_history_limit = limit;
+        self.prune_tip_history(MAX_PRUNED_TIP_RECORDS_ON_LIMIT_UPDATE);
+    }
+
+    pub fn get_tip_history_limit(&self) -> u64 {
+        self.tip_history_limit
+    }
+
+    // oldest retained tips first
+    pub fn get_tip_history(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<TipRecordOutput> {
+        let range = &self.tip_history_range;
+        NearTips::get_page(range, from_index, limit)
+            .filter_map(|tip_id| self.get_tip_record(tip_id))
+            .collect()
+    }
+
+    pub fn get_tips_sent(&self, account_id: ValidAccountId, from_index: Option<u64>, limit: Option<u64>) -> Vec<TipRecordOutput> {
+        self.get_indexed_tips(TipHistoryIndex::Sent(account_id.into()), from_index, limit)
+    }
+
+    pub fn get_tips_received(&self, telegram_account: TelegramAccountId, from_index: Option<u64>, limit: Option<u64>) -> Vec<TipRecordOutput> {
+        self.get_indexed_tips(TipHistoryIndex::Received(telegram_account), from_index, limit)
+    }
+}
+
+impl NearTips {
+    // adds tips to the history and emits the tip event
+    pub(crate) fn record_tips(&mut self, tips: Vec<TipEvent>) {
+        for tip in &tips {
+            if self.tip_history_limit > 0 {
+                self.add_tip_record(TipRecord {
+                    sender_account_id: tip.sender_account_id.clone(),
+                    telegram_account: tip.telegram_account,
+                    receiver_account_id: tip.receiver_account_id.clone(),
+                    contact: tip.contact.clone(),
+                    token_id: tip.token_id.clone(),
+                    amount: tip.amount.0,
+                    fee: tip.fee.0,
+                    chat_id: tip.chat_id,
+                    timestamp: env::block_timestamp(),
+                });
+            }
+            // disabled history is pruned as well
+            self.prune_tip_history(MAX_PRUNED_TIP_RECORDS);
+        }
+        EventKind::Tip(tips).emit();
+    }
+
+    fn add_tip_record(&mut self, tip: TipRecord) {
+        let tip_id = self.tip_history_range.end;
+        self.push_to_tip_history_index(TipHistoryIndex::Sent(tip.sender_account_id.clone()), tip_id);
+        if let Some(telegram_account) = tip.telegram_account {
+            self.push_to_tip_history_index(TipHistoryIndex::Received(telegram_account), tip_id);
+        }
+        self.tip_history.insert(&tip_id, &tip);
+        self.tip_history_range.end += 1;
+    }
+
+    fn prune_tip_history(&mut self, max_records: u64) {
+        let mut pruned = 0;
+        while self.tip_history_range.len() > self.tip_history_limit && pruned < max_records {
+            let oldest_tip_id = self.tip_history_range.start;
+            if let Some(oldest_tip) = self.tip_history.remove(&oldest_tip_id) {
+                self.pop_from_tip_history_index(TipHistoryIndex::Sent(oldest_tip.sender_account_id));
+                if let Some(telegram_account) = oldest_tip.telegram_account {
+                    self.pop_from_tip_history_index(TipHistoryIndex::Received(telegram_account));
+                }
+            }
+            self.tip_history_range.start += 1;
+            pruned += 1;
+        }
+    }
+
+    fn push_to_tip_history_index(&mut self, index: TipHistoryIndex, tip_id: TipRecordId) {
+        let mut range = self.tip_history_indices.get(&index).unwrap_or_default();
+        self.tip_history_index_entries.insert(&TipHistoryIndexEntry { index: index.clone(), position: range.end }, &tip_id);
+        range.end += 1;
+        self.tip_history_indices.insert(&index, &range);
+    }
+
+    // the oldest record of the history is the oldest one of its indices as well
+    fn pop_from_tip_history_index(&mut self, index: TipHistoryIndex) {
+        if let Some(mut range) = self.tip_history_indices.get(&index) {
+            self.tip_history_index_entries.remove(&TipHistoryIndexEntry { index: index.clone(), position: range.start });
+            range.start += 1;
+            if range.len() == 0 {
+                self.tip_history_indices.remove(&index);
+            } else {
+                self.tip_history_indices.insert(&index, &range);
+            }
+        }
+    }
+
+    fn get_indexed_tips(&self, index: TipHistoryIndex, from_index: Option<u64>, limit: Option<u64>) -> Vec<TipRecordOutput> {
+        match self.tip_history_indices.get(&index) {
+            Some(range) => NearTips::get_page(&range, from_index, limit)
+                .filter_map(|position| self.tip_history_index_entries.get(&TipHistoryIndexEntry { index: index.clone(), position }))
+                .filter_map(|tip_id| self.get_tip_record(tip_id))
+                .collect(),
+            None => vec![]
+        }
+    }
+
+    fn get_page(range: &TipHistoryRange, from_index: Option<u64>, limit: Option<u64>) -> std::ops::Range<u64> {
+        let from = range.start.saturating_add(from_index.unwrap_or(0));
+        let to = std::cmp::min(from.saturating_add(limit.unwrap_or(DEFAULT_PAGE_LIMIT)), range.end);
+        from..std::cmp::max(from, to)
+    }
+
+    fn get_tip_record(&self, tip_id: TipRecordId) -> Option<TipRecordOutput> {
+        self.tip_history.get(&tip_id).map(|tip| TipRecordOutput {
+            tip_id,
+            sender_account_id: tip.sender_account_id,
+            telegram_account: tip.telegram_account,
+            receiver_account_id: tip.receiver_account_id,
+            contact: tip.contact,
+            token_id: tip.token_id,
+            amount: tip.amount.into(),
+            fee: tip.fee.into(),
+            chat_id: tip.chat_id,
+            timestamp: tip.timestamp.into(),
+        })
+    }
+}
@@ -2,6 +2,30 @@ use crate::*;
 
 // 100 M TipTokens
 const MAX_TIPTOKEN_DISTRIBUTION: Balance = 100_000_000_000_000_000_000_000_000_000_000;
+const FEE_SPLIT_DENOMINATOR: u128 = 10_000;
+const ONE_TIPTOKEN_RATE: Balance = 1;
+
+// `reward_points` of the token are converted to `tiptokens` TipTokens
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TiptokenRate {
+    pub version: u32,
+    pub tiptokens: WrappedBalance,
+    pub reward_points: WrappedBalance,
+}
+
+impl TiptokenRate {
+    pub(crate) fn reward_points_to_tiptokens(&self, reward_points: Balance) -> Balance {
+        (U256::from(reward_points) * U256::from(self.tiptokens.0) / U256::from(self.reward_points.0)).as_u128()
+    }
+
+    // rounded up, so the points spent on a claim are restored exactly on its refund
+    pub(crate) fn tiptokens_to_reward_points(&self, tiptokens: Balance) -> Balance {
+        let numerator = U256::from(tiptokens) * U256::from(self.reward_points.0);
+        let denominator = U256::from(self.tiptokens.0);
+        ((numerator + denominator - 1) / denominator).as_u128()
+    }
+}
 
 #[near_bindgen]
 impl NearTips {
@@ -14,9 +38,11 @@ impl NearTips {
         assert_eq!(admin_account_id, account_id, "Current user is not a chat admin");
     }
 
-    pub(crate) fn distribute_tiptokens(&mut self, chat_id: TelegramChatId, chat_admin_account_id: AccountId, token_id: TokenAccountId, treasure_fee: Balance, sender_account_id: AccountId) {
-        let tokens_for_chat: Balance = treasure_fee * 4 / 10;
-        let tokens_for_sender: Balance = treasure_fee * 4 / 10;
+    // chat admin and sender shares go to the reward pool and are accounted as reward points,
+    // treasury share goes to the protocol treasure
+    pub(crate) fn distribute_tiptokens(&mut self, chat_id: TelegramChatId, chat_admin_account_id: AccountId, fee_split: FeeSplit, token_id: TokenAccountId, treasure_fee: Balance, sender_account_id: AccountId) {
+        let tokens_for_chat: Balance = NearTips::get_fee_share(treasure_fee, fee_split.chat_admin);
+        let tokens_for_sender: Balance = NearTips::get_fee_share(treasure_fee, fee_split.sender);
         let tokens_for_treasury: Balance = treasure_fee - tokens_for_chat - tokens_for_sender;
 
         // update chat admin tiptoken balance
@@ -37,13 +63,34 @@ impl NearTips {
         let new_user_tokens = user_tokens + tokens_for_sender;
         self.user_tokens_to_claim.insert(&token_by_sender, &new_user_tokens);
 
-        // update treasure balance
+        // update fee ledgers
+        let reward_pool_balance: Balance = self.get_reward_pool_balance_for_token(&token_id);
+        self.reward_pool.insert(&token_id, &(reward_pool_balance + tokens_for_chat + tokens_for_sender));
+
         let treasure_balance: Balance = self.get_treasure_balance_for_token(&token_id);
-        self.treasure.insert(&token_id, &(treasure_balance + treasure_fee));
+        self.treasure.insert(&token_id, &(treasure_balance + tokens_for_treasury));
+
+        self.update_liabilities(&token_id, |l| {
+            l.reward_pool += tokens_for_chat + tokens_for_sender;
+            l.treasure += tokens_for_treasury;
+        });
+
+        let mut fee_stats = self.fee_stats.get(&token_id).unwrap_or_default();
+        fee_stats.chat_admin += tokens_for_chat;
+        fee_stats.sender += tokens_for_sender;
+        fee_stats.treasury += tokens_for_treasury;
+        self.fee_stats.insert(&token_id, &fee_stats);
 
-        env::log(format!("Reward point(s): {} for {} on behalf of chat {} (total: {}), {} for sender {} (total: {}). Treasury reward: {}",
-                         tokens_for_chat, chat_admin_account_id, chat_id, new_chat_admin_tokens,
-                         tokens_for_sender, sender_account_id, new_user_tokens, tokens_for_treasury).as_bytes());
+        EventKind::DistributeTiptokens(vec![DistributeTiptokensEvent {
+            chat_id,
+            chat_admin_account_id,
+            sender_account_id,
+            token_id,
+            fee: treasure_fee.into(),
+            chat_admin_points: tokens_for_chat.into(),
+            sender_points: tokens_for_sender.into(),
+            treasury_amount: tokens_for_treasury.into(),
+        }]).emit();
     }
 
     // claim_chat_tokens TODO TEST
@@ -60,19 +107,58 @@ impl NearTips {
         self.claim_tiptokens_for_account_id(env::predecessor_account_id(), token_id)
     }
 
-    pub fn convert_reward_points_to_tiptoken(&self, amount: Balance, token_id: TokenAccountId) -> Balance {
-        if token_id == NEAR {
-            amount
-        } else {
-            0
+    // every update creates a new rate version, old versions are kept for the refunds of failed claims.
+    // NEAR 1:1 rate used before the first update is stored as version 0
+    pub fn set_tiptoken_rate(&mut self, token_id: TokenAccountId, tiptokens: WrappedBalance, reward_points: WrappedBalance) {
+        self.assert_master_account_id();
+        assert!(self.whitelisted_tokens.contains(&token_id), "Token wasn't whitelisted");
+        assert!(tiptokens.0 > 0 && reward_points.0 > 0, "Positive rate needed");
+
+        let mut rates = self.tiptoken_rates.get(&token_id)
+            .unwrap_or_else(|| self.get_tiptoken_rate_for_version(&token_id, None).into_iter().collect());
+        rates.push(TiptokenRate {
+            version: rates.len() as u32,
+            tiptokens,
+            reward_points,
+        });
+        self.tiptoken_rates.insert(&token_id, &rates);
+    }
+
+    pub fn get_tiptoken_rate(&self, token_id: Option<TokenAccountId>) -> Option<TiptokenRate> {
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        self.get_tiptoken_rate_for_version(&token_id_unwrapped, None)
+    }
+
+    // NEAR reward points are converted 1:1 until the rate is set
+    pub(crate) fn get_tiptoken_rate_for_version(&self, token_id: &TokenAccountId, version: Option<u32>) -> Option<TiptokenRate> {
+        match self.tiptoken_rates.get(token_id) {
+            Some(rates) => match version {
+                Some(version) => rates.get(version as usize).cloned(),
+                None => rates.last().cloned(),
+            },
+            None => if token_id == NEAR {
+                Some(TiptokenRate {
+                    version: 0,
+                    tiptokens: ONE_TIPTOKEN_RATE.into(),
+                    reward_points: ONE_TIPTOKEN_RATE.into(),
+                })
+            } else {
+                None
+            }
         }
     }
 
-    pub fn convert_tiptoken_to_reward_points(&self, amount: Balance, token_id: TokenAccountId) -> Balance {
-        if token_id == NEAR {
-            amount
-        } else {
-            0
+    pub fn convert_reward_points_to_tiptoken(&self, amount: WrappedBalance, token_id: TokenAccountId) -> WrappedBalance {
+        match self.get_tiptoken_rate_for_version(&token_id, None) {
+            Some(rate) => rate.reward_points_to_tiptokens(amount.0).into(),
+            None => 0.into()
+        }
+    }
+
+    pub fn convert_tiptoken_to_reward_points(&self, amount: WrappedBalance, token_id: TokenAccountId) -> WrappedBalance {
+        match self.get_tiptoken_rate_for_version(&token_id, None) {
+            Some(rate) => rate.tiptokens_to_reward_points(amount.0).into(),
+            None => 0.into()
         }
     }
 
@@ -87,13 +173,26 @@ impl NearTips {
         let user_balance: Balance = self.user_tokens_to_claim.get(&token_by_user).unwrap_or(0);
         assert!(user_balance > 0, "Nothing to claim");
 
-        let tiptoken_amount: Balance = self.convert_reward_points_to_tiptoken(user_balance, token_id_unwrapped.clone());
-        assert!(tiptoken_amount > 0, "Only claim for NEAR tips are available now");
+        let rate = self.get_tiptoken_rate_for_version(&token_id_unwrapped, None)
+            .unwrap_or_else(|| env::panic(format!("TipToken rate for {} is not set", token_id_unwrapped).as_bytes()));
+
+        let tiptoken_amount: Balance = rate.reward_points_to_tiptokens(user_balance);
+        assert!(tiptoken_amount > 0, "Not enough reward points to claim TipTokens");
+
+        // points left after the rounding stay on the balance
+        let reward_points: Balance = rate.tiptokens_to_reward_points(tiptoken_amount);
 
         self.total_tiptokens += tiptoken_amount;
         assert!(self.total_tiptokens < MAX_TIPTOKEN_DISTRIBUTION, "No more Tiptokens available");
 
-        self.user_tokens_to_claim.insert(&token_by_user, &0);
+        self.user_tokens_to_claim.insert(&token_by_user, &(user_balance - reward_points));
+
+        EventKind::ClaimTiptokens(vec![ClaimTiptokensEvent {
+            account_id: account_id.clone(),
+            token_id: token_id_unwrapped.clone(),
+            reward_points: reward_points.into(),
+            tiptoken_amount: tiptoken_amount.into(),
+        }]).emit();
 
         ext_fungible_token::ft_transfer(
             account_id.clone(),
@@ -111,6 +210,7 @@ impl NearTips {
                 account_id,
                 tiptoken_amount.into(),
                 token_id_unwrapped.clone(),
+                Some(rate.version),
                 &env::current_account_id(),
                 NO_DEPOSIT,
                 GAS_FOR_AFTER_FT_TRANSFER,
@@ -123,9 +223,10 @@ impl NearTips {
         account_id: AccountId,
         amount_redeemed: WrappedBalance,
         token_account_id: TokenAccountId,
+        rate_version: Option<u32>,
     ) -> bool {
         let promise_success = is_promise_success();
-        if !is_promise_success() {
+        if !promise_success {
             let token_by_user = TokenByNearAccount {
                 account_id: account_id.clone(),
                 token_account_id: token_account_id.clone(),
@@ -133,7 +234,10 @@ impl NearTips {
 
             let user_balance: Balance = self.user_tokens_to_claim.get(&token_by_user).unwrap_or(0);
 
-            let user_reward_points: Balance = self.convert_tiptoken_to_reward_points(amount_redeemed.0, token_account_id);
+            // claims made before rates were versioned had NEAR 1:1 rate
+            let rate = self.get_tiptoken_rate_for_version(&token_account_id, Some(rate_version.unwrap_or(0)))
+                .expect("Unknown TipToken rate version");
+            let user_reward_points: Balance = rate.tiptokens_to_reward_points(amount_redeemed.0);
             assert!(user_reward_points > 0, "Illegal redeem value");
 
             self.total_tiptokens -= amount_redeemed.0;
@@ -146,37 +250,84 @@ impl NearTips {
                 user_reward_points,
                 amount_redeemed.0
             );
+
+            EventKind::ClaimTiptokensFailed(vec![ClaimTiptokensEvent {
+                account_id,
+                token_id: token_account_id,
+                reward_points: user_reward_points.into(),
+                tiptoken_amount: amount_redeemed,
+            }]).emit();
         }
         promise_success
     }
 
+    // redeemed TipTokens are debited from the deposit and stay locked on the contract, counted in tiptokens_burned
     #[payable]
-    pub fn redeem_tiptokens(&mut self, tokens_to_claim: Vec<TokenAccountId>) {
+    pub fn redeem_tiptokens(&mut self, tokens_to_claim: Vec<TokenAccountId>, amount: Option<WrappedBalance>) {
+        assert_one_yocto();
         let account_id = env::predecessor_account_id();
 
-        let tiptoken_amount = self.get_deposit_for_account_id_and_token_id(&account_id, &self.tiptoken_account_id);
+        let tiptoken_deposit = self.get_deposit_for_account_id_and_token_id(&account_id, &self.tiptoken_account_id);
+        let tiptoken_amount: Balance = amount.map(|a| a.0).unwrap_or(tiptoken_deposit);
 
         assert!(tiptoken_amount > 0, "Nothing to redeem");
+        assert!(tiptoken_amount <= tiptoken_deposit, "Not enough TipTokens deposited to redeem (Deposit: {}. Requested: {})", tiptoken_deposit, tiptoken_amount);
+
+        let circulating_tiptokens = self.get_circulating_tiptokens_amount();
+        assert!(tiptoken_amount <= circulating_tiptokens, "Redeem amount exceeds circulating supply");
 
         let numerator = U256::from(tiptoken_amount);
-        let denominator = U256::from(self.total_tiptokens);
+        let denominator = U256::from(circulating_tiptokens);
 
+        self.decrease_deposit(account_id.clone(), self.tiptoken_account_id.clone(), tiptoken_amount);
         self.tiptokens_burned += tiptoken_amount;
 
+        // every token is paid out once
+        let mut tokens_to_claim = tokens_to_claim;
+        tokens_to_claim.sort();
+        tokens_to_claim.dedup();
+
+        let initial_storage_usage = env::storage_usage();
+        let mut redeemed: Vec<RedeemTiptokensEvent> = vec![];
         for token_account_id in tokens_to_claim {
-            let treasure_balance: Balance = self.get_treasure_balance_for_token(&token_account_id);
-            let amount = (U256::from(treasure_balance) * numerator / denominator).as_u128();
+            let reward_pool_balance: Balance = self.get_reward_pool_balance_for_token(&token_account_id);
+            let amount = (U256::from(reward_pool_balance) * numerator / denominator).as_u128();
 
             if amount > 0 {
-                let new_balance = treasure_balance.checked_sub(amount).expect("Not enough balance");
+                let new_balance = reward_pool_balance.checked_sub(amount).expect("Not enough balance");
+
+                self.reward_pool.insert(&token_account_id, &new_balance);
+                self.update_liabilities(&token_account_id, |l| l.reward_pool = l.reward_pool.saturating_sub(amount));
 
-                self.set_treasure_balance_for_token(&token_account_id, &new_balance);
+                let mut fee_stats = self.fee_stats.get(&token_account_id).unwrap_or_default();
+                fee_stats.redeemed += amount;
+                self.fee_stats.insert(&token_account_id, &fee_stats);
 
-                self.deposit_amount_to_account(&account_id, amount, Some(token_account_id.clone()));
+                // the share is paid out whatever its size, so min_deposit and paused tokens don't apply
+                self.increase_deposit(account_id.clone(), token_account_id.clone(), amount);
 
-                log!("{} of {} redeemed", amount, token_account_id);
+                redeemed.push(RedeemTiptokensEvent {
+                    account_id: account_id.clone(),
+                    tiptoken_amount: tiptoken_amount.into(),
+                    token_id: token_account_id,
+                    amount: amount.into(),
+                });
             }
         }
+
+        self.charge_storage(&account_id, initial_storage_usage);
+
+        if !redeemed.is_empty() {
+            EventKind::RedeemTiptokens(redeemed).emit();
+        }
+    }
+
+    pub(crate) fn get_circulating_tiptokens_amount(&self) -> Balance {
+        self.total_tiptokens - self.tiptokens_burned
+    }
+
+    pub fn get_circulating_tiptokens(&self) -> WrappedBalance {
+        self.get_circulating_tiptokens_amount().into()
     }
 
     pub fn get_unclaimed_tiptokens_amount(&self) -> WrappedBalance {
@@ -187,6 +338,10 @@ impl NearTips {
         self.total_tiptokens.into()
     }
 
+    pub fn get_tiptokens_burned(&self) -> WrappedBalance {
+        self.tiptokens_burned.into()
+    }
+
     pub fn get_chat_tokens(&self, chat_id: TelegramChatId, token_id: Option<TokenAccountId>) -> WrappedBalance {
         let settings = self.get_chat_settings(chat_id);
         assert!(settings.is_some(), "Unknown chat");
@@ -225,6 +380,18 @@ impl NearTips {
         (U256::from(numerator) * U256::from(value) / U256::from(100)).as_u128()
     }
 
+    pub fn assert_valid_fee_split(fee_split: &FeeSplit) {
+        assert_eq!(
+            fee_split.chat_admin as u128 + fee_split.sender as u128 + fee_split.treasury as u128,
+            FEE_SPLIT_DENOMINATOR,
+            "Fee split should sum up to 100%"
+        );
+    }
+
+    pub(crate) fn get_fee_share(fee: Balance, share: u16) -> Balance {
+        (U256::from(share) * U256::from(fee) / U256::from(FEE_SPLIT_DENOMINATOR)).as_u128()
+    }
+
 
     pub fn get_treasure_balance(&self, token_account_id: Option<TokenAccountId>) -> WrappedBalance {
         let token_id_unwrapped = NearTips::unwrap_token_id(&token_account_id);
@@ -235,8 +402,29 @@ impl NearTips {
         self.treasure.get(token_id).unwrap_or(0)
     }
 
-    pub(crate) fn set_treasure_balance_for_token(&mut self, token_id: &TokenAccountId, amount: &Balance) {
-        self.treasure.insert(token_id, amount);
+    pub fn get_reward_pool_balance(&self, token_account_id: Option<TokenAccountId>) -> WrappedBalance {
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_account_id);
+        self.get_reward_pool_balance_for_token(&token_id_unwrapped).into()
+    }
+
+    pub(crate) fn get_reward_pool_balance_for_token(&self, token_id: &TokenAccountId) -> Balance {
+        self.reward_pool.get(token_id).unwrap_or(0)
+    }
+
+    // reward_pool = chat_admin_collected + sender_collected - redeemed (plus pre-split treasure moved on migration)
+    pub fn get_fee_breakdown(&self, token_account_id: Option<TokenAccountId>) -> FeeBreakdown {
+        let token_id = NearTips::unwrap_token_id(&token_account_id);
+        let fee_stats = self.fee_stats.get(&token_id).unwrap_or_default();
+
+        FeeBreakdown {
+            chat_admin_collected: fee_stats.chat_admin.into(),
+            sender_collected: fee_stats.sender.into(),
+            treasury_collected: fee_stats.treasury.into(),
+            redeemed: fee_stats.redeemed.into(),
+            reward_pool: self.get_reward_pool_balance_for_token(&token_id).into(),
+            treasury: self.get_treasure_balance_for_token(&token_id).into(),
+            token_id,
+        }
     }
 
 
@@ -244,23 +432,34 @@ impl NearTips {
                              chat_id: TelegramChatId,
                              admin_account_id: ValidAccountId,
                              treasure_fee_numerator: TreasureFeeNumerator,
-                             track_chat_points: bool) {
+                             track_chat_points: bool,
+                             fee_split: Option<FeeSplit>) {
         self.assert_master_account_id();
+        NearTips::assert_valid_treasure_fee_numerator(treasure_fee_numerator);
+
+        let fee_split = fee_split.unwrap_or_default();
+        NearTips::assert_valid_fee_split(&fee_split);
 
+        self.chats.insert(&chat_id);
+        self.chat_settings_v1.remove(&chat_id);
         self.chat_settings.insert(&chat_id, &ChatSettings {
             admin_account_id: admin_account_id.into(),
             treasure_fee_numerator,
             track_chat_points,
+            fee_split,
         });
     }
 
     pub fn delete_chat_settings(&mut self, chat_id: TelegramChatId) {
         self.assert_master_account_id();
+        self.chats.remove(&chat_id);
+        self.chat_settings_v1.remove(&chat_id);
         self.chat_settings.remove(&chat_id);
     }
 
     pub fn get_chat_settings(&self, chat_id: TelegramChatId) -> Option<ChatSettings> {
         self.chat_settings.get(&chat_id)
+            .or_else(|| self.chat_settings_v1.get(&chat_id).map(ChatSettings::from))
     }
 
     pub fn get_chat_numerator(&self, chat_id: TelegramChatId) -> TreasureFeeNumerator {
@@ -0,0 +1,155 @@
+use crate::*;
+
+/* WHITELISTED TOKENS & PER-TOKEN CONFIGURATION */
+pub(crate) const DEFAULT_PAGE_LIMIT: u64 = 50;
+const BASIS_POINTS_DENOMINATOR: u128 = 10_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum WithdrawCommission {
+    Fixed(WrappedBalance),
+    BasisPoints(u16),
+}
+
+impl WithdrawCommission {
+    pub(crate) fn get_amount(&self, amount: Balance) -> Balance {
+        match self {
+            WithdrawCommission::Fixed(commission) => commission.0,
+            WithdrawCommission::BasisPoints(basis_points) =>
+                (U256::from(*basis_points) * U256::from(amount) / U256::from(BASIS_POINTS_DENOMINATOR)).as_u128()
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenConfig {
+    pub decimals: u8,
+    pub min_deposit: WrappedBalance,
+    pub min_tip: WrappedBalance,
+    // charged in this token on withdrawals from telegram accounts
+    pub withdraw_commission: WithdrawCommission,
+    // paused token can't be deposited or tipped, withdrawals are still available
+    pub paused: bool,
+}
+
+impl TokenConfig {
+    // used for tokens whitelisted before configs were introduced
+    pub(crate) fn default_for(token_id: &TokenAccountId) -> TokenConfig {
+        if token_id == NEAR {
+            TokenConfig {
+                decimals: 24,
+                min_deposit: MIN_DEPOSIT_NEAR.into(),
+                min_tip: 0.into(),
+                withdraw_commission: WithdrawCommission::Fixed(WITHDRAW_COMMISSION.into()),
+                paused: false,
+            }
+        } else {
+            TokenConfig {
+                decimals: 18,
+                min_deposit: MIN_DEPOSIT_FT.into(),
+                min_tip: 0.into(),
+                withdraw_commission: WithdrawCommission::Fixed(0.into()),
+                paused: false,
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl NearTips {
+    pub fn whitelist_token(&mut self, token_id: TokenAccountId) {
+        self.assert_master_account_id();
+
+        self.whitelisted_tokens.insert(&token_id);
+        if self.token_configs.get(&token_id).is_none() {
+            self.token_configs.insert(&token_id, &TokenConfig::default_for(&token_id));
+        }
+    }
+
+    pub fn add_token(&mut self, token_id: TokenAccountId, config: TokenConfig) {
+        self.assert_master_account_id();
+        NearTips::assert_valid_token_config(&config);
+        assert!(self.token_configs.get(&token_id).is_none(), "Token already added");
+
+        self.whitelisted_tokens.insert(&token_id);
+        self.token_configs.insert(&token_id, &config);
+    }
+
+    pub fn update_token(&mut self, token_id: TokenAccountId, config: TokenConfig) {
+        self.assert_master_account_id();
+        NearTips::assert_valid_token_config(&config);
+        assert!(self.whitelisted_tokens.contains(&token_id), "Token wasn't whitelisted");
+
+        self.token_configs.insert(&token_id, &config);
+    }
+
+    pub fn set_token_paused(&mut self, token_id: TokenAccountId, paused: bool) {
+        self.assert_master_account_id();
+        assert!(self.whitelisted_tokens.contains(&token_id), "Token wasn't whitelisted");
+
+        let mut config = self.get_token_config_or_default(&token_id);
+        config.paused = paused;
+        self.token_configs.insert(&token_id, &config);
+    }
+
+    // existing deposits and telegram balances of the removed token stay withdrawable
+    pub fn remove_token(&mut self, token_id: TokenAccountId) {
+        self.assert_master_account_id();
+        assert!(self.whitelisted_tokens.contains(&token_id), "Token wasn't whitelisted");
+
+        self.whitelisted_tokens.remove(&token_id);
+        self.token_configs.remove(&token_id);
+    }
+
+    pub fn is_whitelisted_token(&self, token_id: TokenAccountId) -> bool {
+        self.whitelisted_tokens.contains(&token_id)
+    }
+
+    pub fn get_token_config(&self, token_id: Option<TokenAccountId>) -> Option<TokenConfig> {
+        let token_id_unwrapped = NearTips::unwrap_token_id(&token_id);
+        if self.whitelisted_tokens.contains(&token_id_unwrapped) {
+            Some(self.get_token_config_or_default(&token_id_unwrapped))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_whitelisted_tokens(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<(TokenAccountId, TokenConfig)> {
+        let keys = self.token_configs.keys_as_vector();
+        let values = self.token_configs.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    pub(crate) fn get_withdraw_commission(&self, token_id: &TokenAccountId, amount: Balance) -> Balance {
+        self.get_token_config_or_default(token_id).withdraw_commission.get_amount(amount)
+    }
+
+    pub(crate) fn get_token_config_or_default(&self, token_id: &TokenAccountId) -> TokenConfig {
+        self.token_configs.get(token_id).unwrap_or_else(|| TokenConfig::default_for(token_id))
+    }
+
+    // deposits and tips are only available for whitelisted tokens which are not paused
+    pub(crate) fn assert_token_available(&self, token_id: &Option<TokenAccountId>) {
+        self.assert_check_whitelisted_token(token_id);
+
+        let token_id_unwrapped = NearTips::unwrap_token_id(token_id);
+        assert!(!self.get_token_config_or_default(&token_id_unwrapped).paused, "Token is paused");
+    }
+
+    pub(crate) fn assert_valid_token_config(config: &TokenConfig) {
+        if let WithdrawCommission::BasisPoints(basis_points) = config.withdraw_commission {
+            assert!(basis_points as u128 <= BASIS_POINTS_DENOMINATOR, "Withdraw commission can't be greater then 100%");
+        }
+    }
+
+    pub(crate) fn assert_min_tip(&self, token_id: &TokenAccountId, amount: Balance) {
+        let min_tip = self.get_token_config_or_default(token_id).min_tip.0;
+        assert!(amount >= min_tip, "Minimum tip is {}", min_tip);
+    }
+}
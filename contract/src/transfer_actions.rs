@@ -0,0 +1,162 @@
+use crate::*;
+
+/* FT_ON_TRANSFER ACTIONS, parsed from the msg of ft_transfer_call */
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TransferAction {
+    // deposit to account_id or to the sender
+    Deposit {
+        account_id: Option<ValidAccountId>,
+    },
+    TipTelegram {
+        telegram_account: TelegramAccountId,
+        chat_id: Option<TelegramChatId>,
+        expires_at: Option<WrappedTimestamp>,
+    },
+    // receiver is resolved by the auth contract, tokens stay in the sender's deposit if the contact wasn't found
+    TipContact {
+        contact: Contact,
+    },
+    // whole amount is locked into a new giveaway of the sender
+    FundGiveaway {
+        slots: u32,
+        split: GiveawaySplit,
+        chat_id: Option<TelegramChatId>,
+        expires_at: Option<WrappedTimestamp>,
+    },
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TransferRejectReason {
+    InvalidMessage,
+    TokenNotWhitelisted,
+    TokenPaused,
+    DepositsPaused,
+    TipsPaused,
+    BelowMinDeposit,
+    BelowMinTip,
+    AccountNotRegistered,
+    InvalidGiveaway,
+    InvalidExpiration,
+    TooManyExpiringTips,
+    // receiver can't pay for the new records
+    InsufficientStorage,
+}
+
+impl NearTips {
+    // empty msg is a deposit to the sender
+    pub(crate) fn parse_transfer_action(msg: &str) -> Option<TransferAction> {
+        if msg.is_empty() {
+            Some(TransferAction::Deposit { account_id: None })
+        } else {
+            near_sdk::serde_json::from_str(msg).ok()
+        }
+    }
+
+    // checks everything execute_transfer_action would assert before any state is changed
+    pub(crate) fn get_transfer_reject_reason(&self,
+                                             sender_account_id: &AccountId,
+                                             token_id: &TokenAccountId,
+                                             amount: Balance,
+                                             action: &TransferAction) -> Option<TransferRejectReason> {
+        if !self.whitelisted_tokens.contains(token_id) {
+            return Some(TransferRejectReason::TokenNotWhitelisted);
+        }
+        let config = self.get_token_config_or_default(token_id);
+        if config.paused {
+            return Some(TransferRejectReason::TokenPaused);
+        }
+        if !self.withdraw_available {
+            return Some(TransferRejectReason::DepositsPaused);
+        }
+        if amount < config.min_deposit.0 {
+            return Some(TransferRejectReason::BelowMinDeposit);
+        }
+
+        let receiver_account_id: &AccountId = match action {
+            TransferAction::Deposit { account_id: Some(account_id) } => account_id.as_ref(),
+            _ => sender_account_id,
+        };
+        if self.storage_accounts.get(receiver_account_id).is_none() {
+            return Some(TransferRejectReason::AccountNotRegistered);
+        }
+
+        if let TransferAction::TipTelegram { .. } | TransferAction::TipContact { .. } = action {
+            if !self.tip_available {
+                return Some(TransferRejectReason::TipsPaused);
+            }
+            if amount < config.min_tip.0 {
+                return Some(TransferRejectReason::BelowMinTip);
+            }
+        }
+
+        if let TransferAction::TipTelegram { telegram_account, expires_at: Some(expires_at), .. } = action {
+            if expires_at.0 <= env::block_timestamp() {
+                return Some(TransferRejectReason::InvalidExpiration);
+            }
+            if !self.can_add_expiring_tip(sender_account_id, *telegram_account, token_id) {
+                return Some(TransferRejectReason::TooManyExpiringTips);
+            }
+        }
+
+        if let TransferAction::FundGiveaway { slots, expires_at, .. } = action {
+            if !self.tip_available {
+                return Some(TransferRejectReason::TipsPaused);
+            }
+            if *slots == 0 || *slots > MAX_GIVEAWAY_SLOTS || amount < *slots as Balance || expires_at.is_some_and(|t| t.0 <= env::block_timestamp()) {
+                return Some(TransferRejectReason::InvalidGiveaway);
+            }
+            if amount / (*slots as Balance) < config.min_tip.0 {
+                return Some(TransferRejectReason::BelowMinTip);
+            }
+        }
+
+        // deposit and the records created from it are paid by the receiver of the deposit
+        let new_records_storage = self.get_new_deposit_storage(receiver_account_id, token_id) + match action {
+            TransferAction::TipTelegram { telegram_account, chat_id, expires_at } => {
+                self.get_new_telegram_tip_storage(receiver_account_id, *telegram_account, token_id, *chat_id)
+                    + if expires_at.is_some() { EXPIRING_TIP_STORAGE } else { 0 }
+            }
+            TransferAction::FundGiveaway { .. } => GIVEAWAY_STORAGE,
+            _ => 0
+        };
+        if !self.has_available_storage(receiver_account_id, new_records_storage) {
+            return Some(TransferRejectReason::InsufficientStorage);
+        }
+        None
+    }
+
+    pub(crate) fn execute_transfer_action(&mut self,
+                                          sender_account_id: AccountId,
+                                          token_id: TokenAccountId,
+                                          amount: Balance,
+                                          action: TransferAction) {
+        match action {
+            TransferAction::Deposit { account_id } => {
+                let receiver_account_id: AccountId = account_id.map(|a| a.into()).unwrap_or(sender_account_id);
+                self.deposit_with_storage(&receiver_account_id, amount, &token_id);
+            }
+            TransferAction::TipTelegram { telegram_account, chat_id, expires_at } => {
+                self.deposit_with_storage(&sender_account_id, amount, &token_id);
+                self.send_tip_to_telegram_from_account(sender_account_id, telegram_account, amount.into(), chat_id, Some(token_id), expires_at);
+            }
+            TransferAction::TipContact { contact } => {
+                self.deposit_with_storage(&sender_account_id, amount, &token_id);
+                self.tip_contact_from_deposit(sender_account_id, contact, amount, Some(token_id));
+            }
+            TransferAction::FundGiveaway { slots, split, chat_id, expires_at } => {
+                self.deposit_with_storage(&sender_account_id, amount, &token_id);
+                self.create_giveaway_from_deposit(sender_account_id, token_id, amount, slots, split, chat_id, expires_at.map(|t| t.0));
+            }
+        }
+    }
+
+    fn deposit_with_storage(&mut self, account_id: &AccountId, amount: Balance, token_id: &TokenAccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.deposit_amount_to_account(account_id, amount, Some(token_id.clone()));
+        self.charge_storage(account_id, initial_storage_usage);
+    }
+}
@@ -0,0 +1,614 @@
+//! Offline simulation of the tipbot contract together with mock fungible token,
+//! linkdrop and auth contracts. Receipts created by the contract are routed to the
+//! mocks (or back to the contract for callbacks) until the whole flow settles.
+#![allow(dead_code)]
+
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::de::DeserializeOwned;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::test_utils::{get_created_receipts, get_logs, VMContextBuilder};
+use near_sdk::{env, AccountId, Balance, MockedBlockchain, PromiseResult, VMContext};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use tipbot::*;
+
+pub const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+pub const ONE_DAI: Balance = 1_000_000_000_000_000_000;
+pub const STORAGE_DEPOSIT: Balance = ONE_NEAR / 10;
+
+pub const NEAR: &str = "near";
+pub const TIPBOT: &str = "tipbot.near";
+pub const MASTER: &str = "bot.near";
+pub const LINKDROP: &str = "linkdrop.near";
+pub const AUTH: &str = "auth.near";
+pub const TIPTOKEN: &str = "tiptoken.near";
+pub const DAI: &str = "dai.near";
+
+pub const ALICE: &str = "alice.near";
+pub const BOB: &str = "bob.near";
+pub const CHARLIE: &str = "charlie.near";
+
+pub fn account(account_id: &str) -> ValidAccountId {
+    ValidAccountId::try_from(account_id).unwrap()
+}
+
+pub fn telegram_contact(telegram_account: TelegramAccountId) -> Contact {
+    Contact {
+        category: ContactCategories::Telegram,
+        value: "".to_string(),
+        account_id: Some(telegram_account),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ReceiptView {
+    receipt_indices: Vec<u64>,
+    receiver_id: AccountId,
+    actions: Vec<ActionView>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+enum ActionView {
+    FunctionCall {
+        method_name: String,
+        args: String,
+        deposit: Balance,
+    },
+    Transfer {
+        deposit: Balance,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Success(Vec<u8>),
+    Failure(String),
+}
+
+impl Outcome {
+    fn to_promise_result(&self) -> PromiseResult {
+        match self {
+            Outcome::Success(value) => PromiseResult::Successful(value.clone()),
+            Outcome::Failure(_) => PromiseResult::Failed,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MockFungibleToken {
+    pub balances: HashMap<AccountId, Balance>,
+}
+
+impl MockFungibleToken {
+    pub fn register(&mut self, account_id: &str) {
+        self.balances.entry(account_id.to_string()).or_insert(0);
+    }
+
+    pub fn mint(&mut self, account_id: &str, amount: Balance) {
+        *self.balances.entry(account_id.to_string()).or_insert(0) += amount;
+    }
+
+    pub fn ft_balance_of(&self, account_id: &str) -> Balance {
+        self.balances.get(account_id).cloned().unwrap_or(0)
+    }
+
+    fn ft_transfer(&mut self, sender_id: &str, receiver_id: &str, amount: Balance) -> Result<(), String> {
+        if !self.balances.contains_key(receiver_id) {
+            return Err(format!("The account {} is not registered", receiver_id));
+        }
+        let sender_balance = self.ft_balance_of(sender_id);
+        if sender_balance < amount {
+            return Err("The account doesn't have enough balance".to_string());
+        }
+        self.balances.insert(sender_id.to_string(), sender_balance - amount);
+        *self.balances.get_mut(receiver_id).unwrap() += amount;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MockAuth {
+    pub owners: Vec<(Contact, AccountId)>,
+    pub unavailable: bool,
+}
+
+impl MockAuth {
+    pub fn link(&mut self, contact: Contact, account_id: &str) {
+        self.owners.push((contact, account_id.to_string()));
+    }
+
+    pub fn unlink(&mut self, contact: &Contact) {
+        self.owners.retain(|(owner_contact, _)| !MockAuth::are_contacts_equal(owner_contact, contact));
+    }
+
+    fn get_account_for_contact(&self, contact: &Contact) -> Option<AccountId> {
+        self.owners
+            .iter()
+            .find(|(owner_contact, _)| MockAuth::are_contacts_equal(owner_contact, contact))
+            .map(|(_, account_id)| account_id.clone())
+    }
+
+    fn get_contacts(&self, account_id: &str) -> Option<Vec<Contact>> {
+        let contacts: Vec<Contact> = self.owners
+            .iter()
+            .filter(|(_, owner)| owner == account_id)
+            .map(|(contact, _)| contact.clone())
+            .collect();
+        if contacts.is_empty() { None } else { Some(contacts) }
+    }
+
+    fn are_contacts_equal(contact1: &Contact, contact2: &Contact) -> bool {
+        if contact1.category == ContactCategories::Telegram && contact2.category == ContactCategories::Telegram {
+            contact1.account_id == contact2.account_id
+        } else {
+            contact1.category == contact2.category && contact1.value == contact2.value
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MockLinkdrop {
+    pub drops: Vec<(String, Balance)>,
+}
+
+pub struct Simulation {
+    context: VMContextBuilder,
+    pub tipbot: NearTips,
+    pub tipbot_balance: Balance,
+    pub tokens: HashMap<AccountId, MockFungibleToken>,
+    pub auth: MockAuth,
+    pub linkdrop: MockLinkdrop,
+    // native NEAR balances of existing accounts, transfers to other accounts fail
+    pub accounts: HashMap<AccountId, Balance>,
+    pub logs: Vec<String>,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        let mut context = VMContextBuilder::new();
+        context.current_account_id(account(TIPBOT));
+        reset_env(context.predecessor_account_id(account(TIPBOT)).build(), vec![], HashMap::new());
+
+        let tipbot = NearTips::new(account(MASTER), account(LINKDROP), account(AUTH), account(TIPTOKEN));
+
+        let mut tokens = HashMap::new();
+        let mut dai = MockFungibleToken::default();
+        dai.register(TIPBOT);
+        let mut tiptoken = MockFungibleToken::default();
+        tiptoken.mint(TIPBOT, 100_000_000 * ONE_NEAR);
+        tokens.insert(DAI.to_string(), dai);
+        tokens.insert(TIPTOKEN.to_string(), tiptoken);
+
+        let mut accounts = HashMap::new();
+        for account_id in &[MASTER, ALICE, BOB, CHARLIE] {
+            accounts.insert(account_id.to_string(), 100 * ONE_NEAR);
+        }
+
+        let mut simulation = Self {
+            context,
+            tipbot,
+            tipbot_balance: 10 * ONE_NEAR,
+            tokens,
+            auth: MockAuth::default(),
+            linkdrop: MockLinkdrop::default(),
+            accounts,
+            logs: vec![],
+        };
+
+        for token_id in &[NEAR, DAI, TIPTOKEN] {
+            simulation.call(MASTER, 0, |tipbot| tipbot.whitelist_token(token_id.to_string())).unwrap();
+        }
+        // storage of the users is paid by the bot, so their NEAR balances stay round
+        for account_id in &[ALICE, BOB, CHARLIE] {
+            simulation.call(MASTER, STORAGE_DEPOSIT, |tipbot| tipbot.storage_deposit(Some(account(account_id)), None)).unwrap();
+        }
+        simulation
+    }
+
+    pub fn near_balance(&self, account_id: &str) -> Balance {
+        self.accounts.get(account_id).cloned().unwrap_or(0)
+    }
+
+    pub fn ft_balance_of(&self, token_id: &str, account_id: &str) -> Balance {
+        self.tokens[token_id].ft_balance_of(account_id)
+    }
+
+    pub fn ft(&mut self, token_id: &str) -> &mut MockFungibleToken {
+        self.tokens.get_mut(token_id).unwrap()
+    }
+
+    /// Read-only access to the contract
+    pub fn view<R>(&mut self, f: impl FnOnce(&NearTips) -> R) -> R {
+        let context = self.context.predecessor_account_id(account(TIPBOT)).attached_deposit(0).build();
+        let storage = take_storage();
+        reset_env(context, vec![], storage);
+        f(&self.tipbot)
+    }
+
+    /// Calls the contract as a transaction from `predecessor` and processes all created receipts.
+    /// Returns JSON of the returned value.
+    pub fn call<R: Serialize>(&mut self,
+                              predecessor: &str,
+                              attached_deposit: Balance,
+                              f: impl FnOnce(&mut NearTips) -> R) -> Result<String, String> {
+        if let Some(balance) = self.accounts.get_mut(predecessor) {
+            assert!(*balance >= attached_deposit, "Not enough NEAR to attach");
+            *balance -= attached_deposit;
+        }
+        self.tipbot_balance += attached_deposit;
+
+        match self.execute(predecessor, attached_deposit, vec![], f) {
+            Ok((value, receipts)) => {
+                self.process_receipts(receipts);
+                Ok(String::from_utf8(value).unwrap())
+            }
+            Err(error) => {
+                self.tipbot_balance -= attached_deposit;
+                if let Some(balance) = self.accounts.get_mut(predecessor) {
+                    *balance += attached_deposit;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Mock of `ft_transfer_call` to the tipbot with `ft_resolve_transfer` refund.
+    /// Returns the amount used by the tipbot.
+    pub fn ft_transfer_call(&mut self, token_id: &str, sender_id: &str, amount: Balance, msg: &str) -> Result<Balance, String> {
+        self.ft(token_id).ft_transfer(sender_id, TIPBOT, amount)?;
+
+        let sender = account(sender_id);
+        let msg = msg.to_string();
+        let unused = match self.execute(token_id, 0, vec![], |tipbot| tipbot.ft_on_transfer(sender, amount.into(), msg)) {
+            Ok((value, receipts)) => {
+                self.process_receipts(receipts);
+                serde_json::from_slice::<U128>(&value).map(|unused| unused.0).unwrap_or(0)
+            }
+            Err(_) => amount,
+        };
+
+        if unused > 0 {
+            self.ft(token_id).ft_transfer(TIPBOT, sender_id, unused).unwrap();
+        }
+        Ok(amount - unused)
+    }
+
+    fn execute<R: Serialize>(&mut self,
+                             predecessor: &str,
+                             attached_deposit: Balance,
+                             promise_results: Vec<PromiseResult>,
+                             f: impl FnOnce(&mut NearTips) -> R) -> Result<(Vec<u8>, Vec<ReceiptView>), String> {
+        let context: VMContext = self.context
+            .predecessor_account_id(account(predecessor))
+            .attached_deposit(attached_deposit)
+            .account_balance(self.tipbot_balance)
+            .build();
+
+        let storage = take_storage();
+        let state_snapshot = near_sdk::borsh::BorshSerialize::try_to_vec(&self.tipbot).unwrap();
+        reset_env(context.clone(), promise_results, storage.clone());
+
+        let tipbot = &mut self.tipbot;
+        // promises are scheduled once the returned value is serialized and dropped
+        let result = catch_unwind(AssertUnwindSafe(|| serde_json::to_vec(&f(tipbot)).unwrap()));
+        self.logs.extend(get_logs());
+
+        match result {
+            Ok(value) => {
+                let receipts = get_created_receipts()
+                    .iter()
+                    .map(|receipt| serde_json::from_str(&serde_json::to_string(receipt).unwrap()).unwrap())
+                    .collect();
+                Ok((value, receipts))
+            }
+            Err(error) => {
+                // a failed receipt doesn't change the state
+                self.tipbot = near_sdk::borsh::BorshDeserialize::try_from_slice(&state_snapshot).unwrap();
+                reset_env(context, vec![], storage);
+
+                let message = if let Some(message) = error.downcast_ref::<String>() {
+                    message.clone()
+                } else if let Some(message) = error.downcast_ref::<&str>() {
+                    message.to_string()
+                } else {
+                    "Unknown panic".to_string()
+                };
+                Err(message)
+            }
+        }
+    }
+
+    fn process_receipts(&mut self, receipts: Vec<ReceiptView>) {
+        let mut outcomes: Vec<Outcome> = vec![];
+        for receipt in receipts {
+            let promise_results: Vec<PromiseResult> = receipt.receipt_indices
+                .iter()
+                .map(|index| outcomes[*index as usize].to_promise_result())
+                .collect();
+            let outcome = self.execute_receipt(receipt, promise_results);
+            outcomes.push(outcome);
+        }
+    }
+
+    fn execute_receipt(&mut self, receipt: ReceiptView, promise_results: Vec<PromiseResult>) -> Outcome {
+        let mut outcome = Outcome::Success(vec![]);
+        for action in receipt.actions {
+            outcome = match action {
+                ActionView::Transfer { deposit } => self.transfer_near(&receipt.receiver_id, deposit),
+                ActionView::FunctionCall { method_name, args, deposit } => {
+                    self.tipbot_balance -= deposit;
+                    let outcome = self.function_call(&receipt.receiver_id, &method_name, &args, deposit, &promise_results);
+                    if let Outcome::Failure(_) = outcome {
+                        self.tipbot_balance += deposit;
+                    }
+                    outcome
+                }
+            };
+            if let Outcome::Failure(_) = outcome {
+                break;
+            }
+        }
+        outcome
+    }
+
+    fn transfer_near(&mut self, receiver_id: &str, amount: Balance) -> Outcome {
+        match self.accounts.get_mut(receiver_id) {
+            Some(balance) => {
+                self.tipbot_balance -= amount;
+                *balance += amount;
+                Outcome::Success(vec![])
+            }
+            None => Outcome::Failure(format!("Account {} doesn't exist", receiver_id)),
+        }
+    }
+
+    fn function_call(&mut self,
+                     receiver_id: &str,
+                     method_name: &str,
+                     args: &str,
+                     deposit: Balance,
+                     promise_results: &[PromiseResult]) -> Outcome {
+        if receiver_id == TIPBOT {
+            return self.tipbot_callback(method_name, args, promise_results);
+        }
+        if receiver_id == AUTH {
+            if self.auth.unavailable {
+                return Outcome::Failure("Auth contract is unavailable".to_string());
+            }
+            return match method_name {
+                "get_account_for_contact" => {
+                    let args: ContactArgs = parse_args(args);
+                    success(&self.auth.get_account_for_contact(&args.contact))
+                }
+                "get_contacts" => {
+                    let args: AccountArgs = parse_args(args);
+                    success(&self.auth.get_contacts(&args.account_id))
+                }
+                _ => Outcome::Failure(format!("Unknown auth method {}", method_name)),
+            };
+        }
+        if receiver_id == LINKDROP {
+            let args: LinkdropSendArgs = parse_args(args);
+            self.linkdrop.drops.push((args.public_key, deposit));
+            return Outcome::Success(vec![]);
+        }
+        if let Some(token) = self.tokens.get_mut(receiver_id) {
+            return match method_name {
+                "ft_transfer" => {
+                    assert_eq!(deposit, 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+                    let args: FtTransferArgs = parse_args(args);
+                    match token.ft_transfer(TIPBOT, &args.receiver_id, args.amount.0) {
+                        Ok(()) => Outcome::Success(vec![]),
+                        Err(error) => Outcome::Failure(error),
+                    }
+                }
+                "ft_balance_of" => {
+                    let args: AccountArgs = parse_args(args);
+                    success(&U128(token.ft_balance_of(&args.account_id)))
+                }
+                _ => Outcome::Failure(format!("Unknown token method {}", method_name)),
+            };
+        }
+        Outcome::Failure(format!("Account {} doesn't exist", receiver_id))
+    }
+
+    /// Routes callbacks the contract schedules on itself
+    fn tipbot_callback(&mut self, method_name: &str, args: &str, promise_results: &[PromiseResult]) -> Outcome {
+        let promise_results: Vec<PromiseResult> = promise_results.iter().map(clone_promise_result).collect();
+        match method_name {
+            "after_ft_transfer_balance" => {
+                let a: AfterFtTransferBalanceArgs = parse_args(args);
+                self.callback(promise_results, |c| c.after_ft_transfer_balance(a.telegram_account, a.amount, a.token_account_id, a.commission, a.expiring_tips))
+            }
+            "after_ft_transfer_deposit" => {
+                let a: AfterFtTransferDepositArgs = parse_args(args);
+                self.callback(promise_results, |c| c.after_ft_transfer_deposit(a.account_id, a.amount, a.token_account_id))
+            }
+            "after_ft_transfer_commission" => {
+                let a: AfterFtTransferCommissionArgs = parse_args(args);
+                self.callback(promise_results, |c| c.after_ft_transfer_commission(a.amount, a.token_account_id))
+            }
+            "after_ft_transfer_claim_tiptokens" => {
+                let a: AfterFtTransferClaimTiptokensArgs = parse_args(args);
+                self.callback(promise_results, |c| c.after_ft_transfer_claim_tiptokens(a.account_id, a.amount_redeemed, a.token_account_id, a.rate_version))
+            }
+            "on_ft_balance_of_check_solvency" => {
+                let a: TokenIdArgs = parse_args(args);
+                self.callback(promise_results, |c| c.on_ft_balance_of_check_solvency(a.token_id))
+            }
+            "on_get_contact_owner_on_send_tip_to_telegram_with_auth" => {
+                let a: SendTipToTelegramWithAuthArgs = parse_args(args);
+                self.callback(promise_results, |c| c.on_get_contact_owner_on_send_tip_to_telegram_with_auth(
+                    a.reservation_id, a.telegram_account, a.chat_id))
+            }
+            "on_get_contact_owner_on_withdraw_from_telegram_with_auth" => {
+                let a: WithdrawFromTelegramWithAuthArgs = parse_args(args);
+                self.callback(promise_results, |c| c.on_get_contact_owner_on_withdraw_from_telegram_with_auth(
+                    a.recipient_account_id, a.contact, a.token_id, a.amount))
+            }
+            "on_get_contact_owner_on_tip_contact_to_deposit" => {
+                let a: TipContactToDepositArgs = parse_args(args);
+                self.callback(promise_results, |c| c.on_get_contact_owner_on_tip_contact_to_deposit(
+                    a.reservation_id, a.contact))
+            }
+            "on_get_contact_owner_on_tip_contact_with_attached_tokens" => {
+                let a: TipContactWithAttachedTokensArgs = parse_args(args);
+                self.callback(promise_results, |c| c.on_get_contact_owner_on_tip_contact_with_attached_tokens(
+                    a.sender_account_id, a.contact, a.deposit))
+            }
+            _ => Outcome::Failure(format!("Unknown callback {}", method_name)),
+        }
+    }
+
+    fn callback<R: Serialize>(&mut self, promise_results: Vec<PromiseResult>, f: impl FnOnce(&mut NearTips) -> R) -> Outcome {
+        match self.execute(TIPBOT, 0, promise_results, f) {
+            Ok((value, receipts)) => {
+                self.process_receipts(receipts);
+                Outcome::Success(value)
+            }
+            Err(error) => Outcome::Failure(error),
+        }
+    }
+
+    pub fn event_logs(&self) -> Vec<String> {
+        self.logs.iter().filter(|log| log.starts_with("EVENT_JSON:")).cloned().collect()
+    }
+}
+
+fn take_storage() -> HashMap<Vec<u8>, Vec<u8>> {
+    env::take_blockchain_interface()
+        .unwrap()
+        .as_mut_mocked_blockchain()
+        .unwrap()
+        .take_storage()
+}
+
+fn reset_env(context: VMContext, promise_results: Vec<PromiseResult>, storage: HashMap<Vec<u8>, Vec<u8>>) {
+    env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+        context,
+        Default::default(),
+        Default::default(),
+        promise_results,
+        storage,
+        Default::default(),
+        None,
+    )));
+}
+
+fn clone_promise_result(result: &PromiseResult) -> PromiseResult {
+    match result {
+        PromiseResult::NotReady => PromiseResult::NotReady,
+        PromiseResult::Successful(value) => PromiseResult::Successful(value.clone()),
+        PromiseResult::Failed => PromiseResult::Failed,
+    }
+}
+
+fn success<T: Serialize>(value: &T) -> Outcome {
+    Outcome::Success(serde_json::to_vec(value).unwrap())
+}
+
+fn parse_args<T: DeserializeOwned>(args: &str) -> T {
+    serde_json::from_str(args).unwrap_or_else(|error| panic!("Invalid args {}: {}", args, error))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct ContactArgs {
+    contact: Contact,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AccountArgs {
+    account_id: AccountId,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct LinkdropSendArgs {
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct FtTransferArgs {
+    receiver_id: AccountId,
+    amount: U128,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AfterFtTransferBalanceArgs {
+    telegram_account: TelegramAccountId,
+    amount: WrappedBalance,
+    token_account_id: TokenAccountId,
+    commission: Option<WrappedBalance>,
+    expiring_tips: Option<Vec<ExpiringTipOutput>>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TokenIdArgs {
+    token_id: TokenAccountId,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AfterFtTransferCommissionArgs {
+    amount: WrappedBalance,
+    token_account_id: TokenAccountId,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AfterFtTransferDepositArgs {
+    account_id: AccountId,
+    amount: WrappedBalance,
+    token_account_id: TokenAccountId,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct AfterFtTransferClaimTiptokensArgs {
+    account_id: AccountId,
+    amount_redeemed: WrappedBalance,
+    token_account_id: TokenAccountId,
+    rate_version: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct SendTipToTelegramWithAuthArgs {
+    reservation_id: ReservationId,
+    telegram_account: TelegramAccountId,
+    chat_id: Option<TelegramChatId>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct WithdrawFromTelegramWithAuthArgs {
+    recipient_account_id: AccountId,
+    contact: Contact,
+    token_id: Option<TokenAccountId>,
+    amount: Option<WrappedBalance>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TipContactToDepositArgs {
+    reservation_id: ReservationId,
+    contact: Contact,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TipContactWithAttachedTokensArgs {
+    sender_account_id: AccountId,
+    contact: Contact,
+    deposit: Balance,
+}
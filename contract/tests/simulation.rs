@@ -0,0 +1,397 @@
+mod common;
+
+use common::*;
+use near_contract_standards::storage_management::StorageManagement;
+use tipbot::*;
+
+const TELEGRAM_ACCOUNT: TelegramAccountId = 123;
+const CHAT_ID: TelegramChatId = 42;
+
+#[test]
+fn simulate_near_tip_and_withdraw() {
+    let mut sim = Simulation::new();
+    sim.call(MASTER, 0, |tipbot| tipbot.add_chat_settings(CHAT_ID, account(CHARLIE), 10, true, None)).unwrap();
+
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    assert_eq!(sim.near_balance(ALICE), 90 * ONE_NEAR);
+
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 9 * ONE_NEAR);
+    let fee_breakdown = sim.view(|tipbot| tipbot.get_fee_breakdown(None));
+    assert_eq!(fee_breakdown.chat_admin_collected.0, 4 * ONE_NEAR / 10);
+    assert_eq!(fee_breakdown.sender_collected.0, 4 * ONE_NEAR / 10);
+    assert_eq!(fee_breakdown.treasury_collected.0, 2 * ONE_NEAR / 10);
+    assert_eq!(fee_breakdown.reward_pool.0, 8 * ONE_NEAR / 10);
+    assert_eq!(fee_breakdown.treasury.0, 2 * ONE_NEAR / 10);
+
+    let master_balance = sim.near_balance(MASTER);
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT, account(BOB), None, None)).unwrap();
+
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 0);
+    assert_eq!(sim.near_balance(BOB), 100 * ONE_NEAR + 9 * ONE_NEAR - 3 * ONE_NEAR / 1000);
+    assert_eq!(sim.view(|tipbot| tipbot.get_commission_balance(None)).0, 3 * ONE_NEAR / 1000);
+
+    // commission is accrued by the contract until master withdraws it
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_commission(None)).unwrap();
+    assert_eq!(sim.near_balance(MASTER), master_balance + 3 * ONE_NEAR / 1000);
+    assert_eq!(sim.view(|tipbot| tipbot.get_commission_balance(None)).0, 0);
+}
+
+#[test]
+fn simulate_ft_withdraw_commission() {
+    let mut sim = Simulation::new();
+    sim.ft(DAI).mint(ALICE, 10 * ONE_DAI);
+    sim.ft(DAI).register(BOB);
+
+    let mut config = sim.view(|tipbot| tipbot.get_token_config(Some(DAI.to_string()))).unwrap();
+    config.withdraw_commission = WithdrawCommission::BasisPoints(100);
+    sim.call(MASTER, 0, |tipbot| tipbot.update_token(DAI.to_string(), config)).unwrap();
+
+    sim.ft_transfer_call(DAI, ALICE, 10 * ONE_DAI, "").unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_DAI).into(), None, Some(DAI.to_string()), None)).unwrap();
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT, account(BOB), Some(DAI.to_string()), None)).unwrap();
+
+    assert_eq!(sim.ft_balance_of(DAI, BOB), 10 * ONE_DAI - ONE_DAI / 10);
+    assert_eq!(sim.view(|tipbot| tipbot.get_commission_balance(Some(DAI.to_string()))).0, ONE_DAI / 10);
+
+    // master isn't registered in the token contract yet, commission stays in the ledger
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_commission(Some(DAI.to_string()))).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_commission_balance(Some(DAI.to_string()))).0, ONE_DAI / 10);
+
+    sim.ft(DAI).register(MASTER);
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_commission(Some(DAI.to_string()))).unwrap();
+    assert_eq!(sim.ft_balance_of(DAI, MASTER), ONE_DAI / 10);
+    assert_eq!(sim.view(|tipbot| tipbot.get_commission_balance(Some(DAI.to_string()))).0, 0);
+}
+
+#[test]
+fn simulate_ft_tip_and_withdraw_with_rollback() {
+    let mut sim = Simulation::new();
+    sim.ft(DAI).mint(ALICE, 100 * ONE_DAI);
+    sim.ft(DAI).register(BOB);
+
+    assert_eq!(sim.ft_transfer_call(DAI, ALICE, 20 * ONE_DAI, ""), Ok(20 * ONE_DAI));
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), Some(DAI.to_string()))).0, 20 * ONE_DAI);
+
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT + 1, (5 * ONE_DAI).into(), None, Some(DAI.to_string()), Some(u64::MAX.into()))).unwrap();
+
+    // receiver is registered in the token contract
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT, account(BOB), Some(DAI.to_string()), None)).unwrap();
+    assert_eq!(sim.ft_balance_of(DAI, BOB), 5 * ONE_DAI);
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string()))).0, 0);
+
+    // ft_transfer fails, balance is restored by the callback
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT + 1, account(CHARLIE), Some(DAI.to_string()), None)).unwrap();
+    assert_eq!(sim.ft_balance_of(DAI, CHARLIE), 0);
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT + 1, Some(DAI.to_string()))).0, 5 * ONE_DAI);
+    assert_eq!(sim.view(|tipbot| tipbot.get_expiring_tips(TELEGRAM_ACCOUNT + 1, Some(DAI.to_string())))[0].amount.0, 5 * ONE_DAI);
+
+    // deposit withdrawal to an unregistered account is rolled back as well
+    sim.ft(DAI).balances.remove(ALICE);
+    sim.call(ALICE, 0, |tipbot| tipbot.withdraw(Some(DAI.to_string()), None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), Some(DAI.to_string()))).0, 10 * ONE_DAI);
+
+    sim.ft(DAI).register(ALICE);
+    sim.call(ALICE, 0, |tipbot| tipbot.withdraw(Some(DAI.to_string()), None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), Some(DAI.to_string()))).0, 0);
+    assert_eq!(sim.ft_balance_of(DAI, ALICE), 10 * ONE_DAI);
+}
+
+#[test]
+fn simulate_not_whitelisted_ft_is_refunded() {
+    let mut sim = Simulation::new();
+    sim.tokens.insert("usdc.near".to_string(), MockFungibleToken::default());
+    sim.ft("usdc.near").mint(ALICE, ONE_DAI);
+    sim.ft("usdc.near").register(TIPBOT);
+
+    assert_eq!(sim.ft_transfer_call("usdc.near", ALICE, ONE_DAI, ""), Ok(0));
+    assert_eq!(sim.ft_balance_of("usdc.near", ALICE), ONE_DAI);
+}
+
+#[test]
+fn simulate_tips_with_auth() {
+    let mut sim = Simulation::new();
+    sim.auth.link(telegram_contact(TELEGRAM_ACCOUNT), BOB);
+
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+
+    // linked telegram account gets the tip to the NEAR deposit
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), None)).0, ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 0);
+
+    // unknown telegram account gets a regular telegram tip
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT + 1, ONE_NEAR.into(), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT + 1, None)).0, ONE_NEAR);
+
+    sim.call(ALICE, 0, |tipbot| tipbot.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), None)).0, 2 * ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), None)).0, 7 * ONE_NEAR);
+}
+
+#[test]
+fn simulate_withdraw_from_telegram_with_auth() {
+    let mut sim = Simulation::new();
+    sim.auth.link(telegram_contact(TELEGRAM_ACCOUNT), BOB);
+
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (2 * ONE_NEAR).into(), None, None, None)).unwrap();
+
+    // only the linked account can withdraw
+    sim.call(CHARLIE, 0, |tipbot| tipbot.withdraw_from_telegram_with_auth(TELEGRAM_ACCOUNT, None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 2 * ONE_NEAR);
+    assert_eq!(sim.near_balance(CHARLIE), 100 * ONE_NEAR);
+
+    sim.call(BOB, 0, |tipbot| tipbot.withdraw_from_telegram_with_auth(TELEGRAM_ACCOUNT, None, Some(ONE_NEAR.into()))).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, ONE_NEAR);
+    assert_eq!(sim.near_balance(BOB), 101 * ONE_NEAR);
+
+    sim.call(BOB, 0, |tipbot| tipbot.withdraw_from_telegram_with_auth(TELEGRAM_ACCOUNT, None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 0);
+    assert_eq!(sim.near_balance(BOB), 102 * ONE_NEAR);
+}
+
+#[test]
+fn simulate_auth_contract_failure() {
+    let mut sim = Simulation::new();
+    sim.auth.unavailable = true;
+
+    // tip falls back to the telegram balance
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None)).unwrap();
+
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), None)).0, 9 * ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, ONE_NEAR);
+    assert!(sim.event_logs().iter().any(|log| log.contains(r#""event":"auth_failed""#)));
+
+    // tip to the deposit returns to the sender
+    sim.call(ALICE, 0, |tipbot| tipbot.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), None)).0, 9 * ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_locked_balance(account(ALICE), None)).0, 0);
+
+    // withdrawal doesn't touch the balance
+    sim.call(BOB, 0, |tipbot| tipbot.withdraw_from_telegram_with_auth(TELEGRAM_ACCOUNT, None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, ONE_NEAR);
+}
+
+#[test]
+fn simulate_withdraw_linkdrop() {
+    let mut sim = Simulation::new();
+    sim.call(ALICE, 5 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (5 * ONE_NEAR).into(), None, None, None)).unwrap();
+
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_linkdrop("ed25519:key".to_string(), TELEGRAM_ACCOUNT)).unwrap();
+
+    assert_eq!(sim.linkdrop.drops, vec![("ed25519:key".to_string(), 5 * ONE_NEAR - 3 * ONE_NEAR / 1000)]);
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 0);
+}
+
+#[test]
+fn simulate_claim_and_redeem_tiptokens() {
+    let mut sim = Simulation::new();
+    sim.call(MASTER, 0, |tipbot| tipbot.add_chat_settings(CHAT_ID, account(CHARLIE), 10, true, None)).unwrap();
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (10 * ONE_NEAR).into(), Some(CHAT_ID), None, None)).unwrap();
+
+    let points = sim.view(|tipbot| tipbot.get_user_tokens(ALICE.to_string(), None)).0;
+    assert_eq!(points, 4 * ONE_NEAR / 10);
+
+    // not registered in the TipToken contract, points are restored
+    sim.call(ALICE, 1, |tipbot| tipbot.claim_tiptokens(None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_user_tokens(ALICE.to_string(), None)).0, points);
+    assert_eq!(sim.view(|tipbot| tipbot.get_total_tiptokens()).0, 0);
+    assert!(sim.event_logs().iter().any(|log| log.contains(r#""event":"claim_tiptokens_failed""#)));
+
+    sim.ft(TIPTOKEN).register(ALICE);
+    sim.call(ALICE, 1, |tipbot| tipbot.claim_tiptokens(None)).unwrap();
+    assert_eq!(sim.ft_balance_of(TIPTOKEN, ALICE), points);
+    assert_eq!(sim.view(|tipbot| tipbot.get_total_tiptokens()).0, points);
+
+    // TipTokens come back as a deposit and are redeemed for the reward pool
+    assert_eq!(sim.ft_transfer_call(TIPTOKEN, ALICE, points, ""), Ok(points));
+    sim.call(ALICE, 1, |tipbot| tipbot.redeem_tiptokens(vec![NEAR.to_string()], None)).unwrap();
+
+    assert_eq!(sim.view(|tipbot| tipbot.get_reward_pool_balance(None)).0, 0);
+    assert_eq!(sim.view(|tipbot| tipbot.get_treasure_balance(None)).0, 2 * ONE_NEAR / 10);
+    assert_eq!(sim.view(|tipbot| tipbot.get_fee_breakdown(None)).redeemed.0, 8 * ONE_NEAR / 10);
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), None)).0, 8 * ONE_NEAR / 10);
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), Some(TIPTOKEN.to_string()))).0, 0);
+    assert!(sim.call(ALICE, 1, |tipbot| tipbot.redeem_tiptokens(vec![NEAR.to_string()], None)).is_err());
+}
+
+#[test]
+fn simulate_storage_registration() {
+    let mut sim = Simulation::new();
+    let dave = "dave.near";
+    sim.accounts.insert(dave.to_string(), 10 * ONE_NEAR);
+    sim.ft(DAI).mint(dave, ONE_DAI);
+
+    // unregistered account can't create deposits, tokens are refunded
+    assert!(sim.call(dave, ONE_NEAR, |tipbot| tipbot.deposit(None)).is_err());
+    assert_eq!(sim.near_balance(dave), 10 * ONE_NEAR);
+    sim.ft_transfer_call(DAI, dave, ONE_DAI, "").ok();
+    assert_eq!(sim.ft_balance_of(DAI, dave), ONE_DAI);
+
+    sim.call(dave, STORAGE_DEPOSIT, |tipbot| tipbot.storage_deposit(None, None)).unwrap();
+    assert_eq!(sim.ft_transfer_call(DAI, dave, ONE_DAI, ""), Ok(ONE_DAI));
+
+    sim.call(dave, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, ONE_DAI.into(), None, Some(DAI.to_string()), None)).unwrap();
+    let storage_balance = sim.view(|tipbot| tipbot.storage_balance_of(account(dave))).unwrap();
+    assert!(storage_balance.available.0 < STORAGE_DEPOSIT);
+
+    // storage used by the sent tip stays paid
+    sim.call(dave, 1, |tipbot| tipbot.storage_unregister(None)).unwrap();
+    assert!(sim.view(|tipbot| tipbot.storage_balance_of(account(dave))).is_none());
+    assert!(sim.near_balance(dave) > 10 * ONE_NEAR - STORAGE_DEPOSIT);
+    assert!(sim.near_balance(dave) < 10 * ONE_NEAR);
+}
+
+#[test]
+fn simulate_check_solvency() {
+    let mut sim = Simulation::new();
+    sim.ft(DAI).mint(ALICE, 10 * ONE_DAI);
+    sim.ft_transfer_call(DAI, ALICE, 10 * ONE_DAI, "").unwrap();
+    sim.call(ALICE, 5 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (4 * ONE_DAI).into(), None, Some(DAI.to_string()), None)).unwrap();
+
+    let liabilities = sim.view(|tipbot| tipbot.get_liabilities(Some(DAI.to_string())));
+    assert_eq!(liabilities.deposits.0, 6 * ONE_DAI);
+    assert_eq!(liabilities.telegram_balances.0, 4 * ONE_DAI);
+    assert_eq!(liabilities.total.0, 10 * ONE_DAI);
+
+    sim.call(MASTER, 0, |tipbot| tipbot.check_solvency(None, None)).unwrap();
+    assert!(!sim.event_logs().iter().any(|log| log.contains(r#""event":"solvency_alert""#)));
+
+    // tokens left the contract without passing the ledgers
+    sim.ft(DAI).balances.insert(TIPBOT.to_string(), 7 * ONE_DAI);
+    sim.call(MASTER, 0, |tipbot| tipbot.check_solvency(None, None)).unwrap();
+    let alerts: Vec<String> = sim.event_logs().into_iter().filter(|log| log.contains(r#""event":"solvency_alert""#)).collect();
+    assert_eq!(alerts.len(), 1);
+    assert!(alerts[0].contains(&format!(r#""token_id":"{}","balance":"{}","liabilities":"{}","shortfall":"{}""#, DAI, 7 * ONE_DAI, 10 * ONE_DAI, 3 * ONE_DAI)));
+}
+
+#[test]
+fn simulate_ft_transfer_call_actions() {
+    let mut sim = Simulation::new();
+    sim.auth.link(Contact { category: ContactCategories::Github, value: "bob".to_string(), account_id: None }, BOB);
+    sim.ft(DAI).mint(ALICE, 10 * ONE_DAI);
+
+    let msg = format!(r#"{{"action":"tip_telegram","telegram_account":{}}}"#, TELEGRAM_ACCOUNT);
+    assert_eq!(sim.ft_transfer_call(DAI, ALICE, 3 * ONE_DAI, &msg), Ok(3 * ONE_DAI));
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, Some(DAI.to_string()))).0, 3 * ONE_DAI);
+
+    let msg = r#"{"action":"tip_contact","contact":{"category":"Github","value":"bob","account_id":null}}"#;
+    assert_eq!(sim.ft_transfer_call(DAI, ALICE, 2 * ONE_DAI, msg), Ok(2 * ONE_DAI));
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), Some(DAI.to_string()))).0, 2 * ONE_DAI);
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(ALICE), Some(DAI.to_string()))).0, 0);
+
+    let msg = format!(r#"{{"action":"deposit","account_id":"{}"}}"#, CHARLIE);
+    assert_eq!(sim.ft_transfer_call(DAI, ALICE, ONE_DAI, &msg), Ok(ONE_DAI));
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(CHARLIE), Some(DAI.to_string()))).0, ONE_DAI);
+
+    assert_eq!(sim.ft_transfer_call(DAI, ALICE, ONE_DAI, "{}"), Ok(0));
+    assert_eq!(sim.ft_balance_of(DAI, ALICE), 4 * ONE_DAI);
+}
+
+#[test]
+fn simulate_near_withdraw_rollback() {
+    let mut sim = Simulation::new();
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram(TELEGRAM_ACCOUNT, (2 * ONE_NEAR).into(), None, None, None)).unwrap();
+
+    // receiver doesn't exist, balance and commission are restored
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT, account("ghost.near"), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 2 * ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_commission_balance(None)).0, 0);
+    assert!(sim.event_logs().iter().any(|log| log.contains(r#""event":"withdraw_failed""#)));
+
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT, account(BOB), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, 0);
+    assert_eq!(sim.near_balance(BOB), 102 * ONE_NEAR - 3 * ONE_NEAR / 1000);
+}
+
+#[test]
+fn simulate_giveaway() {
+    let mut sim = Simulation::new();
+    sim.ft(DAI).mint(ALICE, 10 * ONE_DAI);
+    sim.ft(DAI).register(BOB);
+
+    let msg = r#"{"action":"fund_giveaway","slots":2,"split":"equal"}"#;
+    assert_eq!(sim.ft_transfer_call(DAI, ALICE, 4 * ONE_DAI, msg), Ok(4 * ONE_DAI));
+    sim.call(MASTER, 0, |tipbot| tipbot.claim_giveaway(0, TELEGRAM_ACCOUNT, None)).unwrap();
+    sim.call(MASTER, 0, |tipbot| tipbot.claim_giveaway(0, TELEGRAM_ACCOUNT + 1, None)).unwrap();
+    assert!(sim.view(|tipbot| tipbot.get_giveaway(0)).is_none());
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT + 1, Some(DAI.to_string()))).0, 2 * ONE_DAI);
+
+    sim.call(MASTER, 0, |tipbot| tipbot.withdraw_from_telegram(TELEGRAM_ACCOUNT, account(BOB), Some(DAI.to_string()), None)).unwrap();
+    assert_eq!(sim.ft_balance_of(DAI, BOB), 2 * ONE_DAI);
+    sim.call(MASTER, 0, |tipbot| tipbot.check_solvency(None, None)).unwrap();
+    assert!(!sim.event_logs().iter().any(|log| log.contains(r#""event":"solvency_alert""#)));
+}
+
+#[test]
+fn simulate_unresolved_tip_with_attached_tokens() {
+    let mut sim = Simulation::new();
+    let contact = telegram_contact(TELEGRAM_ACCOUNT);
+    sim.call(MASTER, 0, |tipbot| tipbot.set_generic_tips_available(true)).unwrap();
+
+    // auth contract failure refunds the sender
+    sim.auth.unavailable = true;
+    sim.call(ALICE, ONE_NEAR, |tipbot| tipbot.tip_contact_with_attached_tokens(telegram_contact(TELEGRAM_ACCOUNT))).unwrap();
+    assert_eq!(sim.near_balance(ALICE), 100 * ONE_NEAR);
+    assert!(sim.event_logs().iter().any(|log| log.contains(r#""event":"unresolved_tip""#) && log.contains(r#""outcome":"refunded""#)));
+
+    // contact without an owner keeps the tip under the undefined account
+    sim.auth.unavailable = false;
+    sim.call(ALICE, ONE_NEAR, |tipbot| tipbot.tip_contact_with_attached_tokens(telegram_contact(TELEGRAM_ACCOUNT))).unwrap();
+    assert_eq!(sim.near_balance(ALICE), 99 * ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_tip_by_contact("".to_string(), telegram_contact(TELEGRAM_ACCOUNT))).0, ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_liabilities(None)).tips.0, ONE_NEAR);
+    assert!(sim.event_logs().iter().any(|log| log.contains(r#""outcome":"parked""#)));
+
+    sim.auth.link(contact, BOB);
+    sim.call(ALICE, ONE_NEAR, |tipbot| tipbot.tip_contact_with_attached_tokens(telegram_contact(TELEGRAM_ACCOUNT))).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_tip_by_contact(BOB.to_string(), telegram_contact(TELEGRAM_ACCOUNT))).0, ONE_NEAR);
+}
+
+#[test]
+fn simulate_identity_provider_routing() {
+    let mut sim = Simulation::new();
+    sim.auth.link(telegram_contact(TELEGRAM_ACCOUNT), BOB);
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+
+    // telegram contacts are verified by a provider which is not deployed in the simulation
+    sim.call(MASTER, 0, |tipbot| tipbot.set_identity_provider(ContactCategories::Telegram, account("verifier.near"))).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), None)).0, 0);
+    assert!(sim.event_logs().iter().any(|log| log.contains(r#""event":"auth_failed""#)));
+
+    sim.call(MASTER, 0, |tipbot| tipbot.remove_identity_provider(ContactCategories::Telegram)).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.tip_contact_to_deposit(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), None)).0, ONE_NEAR);
+}
+
+#[test]
+fn simulate_cached_contact_owner() {
+    let mut sim = Simulation::new();
+    sim.auth.link(telegram_contact(TELEGRAM_ACCOUNT), BOB);
+    sim.call(ALICE, 10 * ONE_NEAR, |tipbot| tipbot.deposit(None)).unwrap();
+
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_cached_contact_owner(telegram_contact(TELEGRAM_ACCOUNT))), Some(BOB.to_string()));
+
+    // cached owner gets the tip without asking the auth contract
+    sim.auth.unavailable = true;
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), None)).0, 2 * ONE_NEAR);
+    assert!(!sim.event_logs().iter().any(|log| log.contains(r#""event":"auth_failed""#)));
+
+    // auth contract drops the cached owner on unlink
+    sim.auth.unavailable = false;
+    sim.auth.unlink(&telegram_contact(TELEGRAM_ACCOUNT));
+    sim.call(AUTH, 0, |tipbot| tipbot.notify_contact_changed(telegram_contact(TELEGRAM_ACCOUNT))).unwrap();
+    sim.call(ALICE, 0, |tipbot| tipbot.send_tip_to_telegram_with_auth(TELEGRAM_ACCOUNT, ONE_NEAR.into(), None, None)).unwrap();
+    assert_eq!(sim.view(|tipbot| tipbot.get_deposit(account(BOB), None)).0, 2 * ONE_NEAR);
+    assert_eq!(sim.view(|tipbot| tipbot.get_balance(TELEGRAM_ACCOUNT, None)).0, ONE_NEAR);
+}